@@ -0,0 +1,423 @@
+use std::io::BufRead;
+
+use log::{error, info};
+use rand::{distributions::Alphanumeric, Rng};
+
+use crate::{
+    auth,
+    config::Config,
+    model::{
+        database::Database,
+        room::{self, Visibility},
+        store::Backend,
+        Role, Room, Snowflake, User,
+    },
+};
+
+/// How many characters a generated password has.
+const GENERATED_PASSWORD_LEN: usize = 20;
+
+#[derive(Debug, clap::Subcommand)]
+pub enum UserCommand {
+    /// Create a user.
+    Create {
+        name: String,
+        /// Read the password from the first line of stdin, instead of generating (and printing) one.
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Change a user's password, and log them out everywhere.
+    ResetPassword {
+        name: String,
+        /// Read the password from the first line of stdin, instead of generating (and printing) one.
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Delete a user, with their sessions, roles, blocks and reactions (their messages are kept).
+    Delete { name: String },
+    /// Give a user a role in a room.
+    Promote {
+        name: String,
+        /// The room's name or id.
+        room: String,
+        #[arg(long, value_enum, default_value_t = RoleArg::Moderator)]
+        role: RoleArg,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum RoomCommand {
+    /// Create a room.
+    Create {
+        name: String,
+        #[arg(long, value_enum, default_value_t = VisibilityArg::Public)]
+        visibility: VisibilityArg,
+        /// A user to make the room's owner.
+        #[arg(long)]
+        owner: Option<String>,
+    },
+    /// List every room (including unlisted, private and direct ones).
+    List,
+    /// Delete a room, with its messages, members, moderation, filters and invites.
+    Delete {
+        /// The room's name or id.
+        room: String,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum SessionCommand {
+    /// Delete sessions, logging their users out.
+    Purge {
+        /// Only delete the sessions of this user.
+        #[arg(long)]
+        user: Option<String>,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum DbCommand {
+    /// Rebuild the database file, to reclaim the space of deleted rows.
+    Vacuum,
+    /// Scan the database for rows that can't be read.
+    Check,
+}
+
+/// The roles that can be given with `golem user promote`.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum RoleArg {
+    Member,
+    Moderator,
+    Owner,
+}
+
+impl From<RoleArg> for Role {
+    fn from(role: RoleArg) -> Self {
+        match role {
+            RoleArg::Member => Role::Member,
+            RoleArg::Moderator => Role::Moderator,
+            RoleArg::Owner => Role::Owner,
+        }
+    }
+}
+
+/// The visibilities that rooms can be created with (direct conversations are started by users).
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum VisibilityArg {
+    Public,
+    Unlisted,
+    Private,
+}
+
+impl From<VisibilityArg> for Visibility {
+    fn from(visibility: VisibilityArg) -> Self {
+        match visibility {
+            VisibilityArg::Public => Visibility::Public,
+            VisibilityArg::Unlisted => Visibility::Unlisted,
+            VisibilityArg::Private => Visibility::Private,
+        }
+    }
+}
+
+/// Why a command failed. It has already been logged.
+struct Failed;
+
+type CommandResult = Result<(), Failed>;
+
+/// Log a database error, and fail.
+fn failed(action: &str) -> impl FnOnce(rusqlite::Error) -> Failed + '_ {
+    move |err| {
+        error!("Failed to {}: {}", action, err);
+        Failed
+    }
+}
+
+pub fn user(config: &Config, command: UserCommand) -> i32 {
+    exit_code(open(config).and_then(|db| match command {
+        UserCommand::Create {
+            name,
+            password_stdin,
+        } => create_user(config, &db, name, password_stdin),
+        UserCommand::ResetPassword {
+            name,
+            password_stdin,
+        } => reset_password(&db, &name, password_stdin),
+        UserCommand::Delete { name } => {
+            let user = find_user(&db, &name)?;
+            db.delete_user(&user.id).map_err(failed("delete user"))?;
+            info!("Deleted user {} ({})", user.name, user.id.id());
+            Ok(())
+        }
+        UserCommand::Promote { name, room, role } => {
+            let user = find_user(&db, &name)?;
+            let room = find_room(&db, &room)?;
+            let role = Role::from(role);
+            db.set_role(&room.id, &user.id, role)
+                .map_err(failed("set role"))?;
+            info!("{} is now {:?} of room {}", user.name, role, room.name);
+            Ok(())
+        }
+    }))
+}
+
+pub fn room(config: &Config, command: RoomCommand) -> i32 {
+    exit_code(open(config).and_then(|db| match command {
+        RoomCommand::Create {
+            name,
+            visibility,
+            owner,
+        } => create_room(config, &db, name, visibility.into(), owner),
+        RoomCommand::List => {
+            let rooms = db.get_all_rooms().map_err(failed("get rooms"))?;
+            for room in rooms {
+                println!("{}\t{}\t{:?}", room.id.id(), room.name, room.visibility);
+            }
+            Ok(())
+        }
+        RoomCommand::Delete { room } => {
+            let room = find_room(&db, &room)?;
+            if room.id.id() == room::MAIN_ID {
+                error!("The main room can't be deleted");
+                return Err(Failed);
+            }
+            db.delete_room(&room.id).map_err(failed("delete room"))?;
+            info!("Deleted room {} ({})", room.name, room.id.id());
+            Ok(())
+        }
+    }))
+}
+
+pub fn session(config: &Config, command: SessionCommand) -> i32 {
+    exit_code(open(config).and_then(|db| match command {
+        SessionCommand::Purge { user } => {
+            let user = user.map(|name| find_user(&db, &name)).transpose()?;
+            let deleted = db
+                .delete_sessions(user.as_ref().map(|user| &user.id))
+                .map_err(failed("delete sessions"))?;
+            info!("Deleted {} session(s)", deleted);
+            Ok(())
+        }
+    }))
+}
+
+pub fn db(config: &Config, command: DbCommand) -> i32 {
+    match command {
+        DbCommand::Vacuum => {
+            exit_code(open(config).and_then(|db| db.vacuum().map_err(failed("vacuum database"))))
+        }
+        DbCommand::Check => check(config),
+    }
+}
+
+/// Check the database for rows that can't be read (`golem db check`), returning the exit code.
+fn check(config: &Config) -> i32 {
+    if config.store != Backend::Sqlite {
+        error!("Only the sqlite store can be checked");
+        return 1;
+    }
+
+    let checked = match Database::open_reader(&config.database).and_then(|db| db.check()) {
+        Ok(checked) => checked,
+        Err(err) => {
+            error!("Failed to check database: {}", err);
+            return 1;
+        }
+    };
+
+    for problem in &checked.problems {
+        error!("{}", problem);
+    }
+    if checked.problems.is_empty() {
+        info!("Checked {} rows, and they can all be read", checked.rows);
+        0
+    } else {
+        error!(
+            "Checked {} rows, and {} can't be read",
+            checked.rows,
+            checked.problems.len()
+        );
+        1
+    }
+}
+
+fn exit_code(result: CommandResult) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(Failed) => 1,
+    }
+}
+
+/// Open the database (bringing it up to date), which a running server may be using too.
+fn open(config: &Config) -> Result<Database, Failed> {
+    if config.store != Backend::Sqlite {
+        error!("Only the sqlite store can be managed (the memory store is in the server)");
+        return Err(Failed);
+    }
+
+    Database::build(&config.database).map_err(|err| {
+        error!("Failed to open database: {}", err);
+        Failed
+    })
+}
+
+fn create_user(
+    config: &Config,
+    db: &Database,
+    name: String,
+    password_stdin: bool,
+) -> CommandResult {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        error!("The name can't be empty");
+        return Err(Failed);
+    }
+    if db
+        .get_user_by_name(&name)
+        .map_err(failed("get user"))?
+        .is_some()
+    {
+        error!("User {} already exists", name);
+        return Err(Failed);
+    }
+
+    let password = password(password_stdin)?;
+    let user = User {
+        id: next_snowflake(config)?,
+        name,
+        password: auth::hash::hash_password(password),
+    };
+    let id = user.id.clone();
+    db.add_user(user).map_err(failed("add user"))?;
+    info!("Created user {}", id.id());
+
+    // The first user owns the main room, like when registering
+    if db.count_users().map_err(failed("count users"))? == 1 {
+        db.set_role(&room::main_id(), &id, Role::Owner)
+            .map_err(failed("make user the owner of the main room"))?;
+        info!("User {} is the owner of the main room", id.id());
+    }
+    Ok(())
+}
+
+fn reset_password(db: &Database, name: &str, password_stdin: bool) -> CommandResult {
+    let user = find_user(db, name)?;
+    let password = password(password_stdin)?;
+    let hash = auth::hash::hash_password(password);
+    db.update_password(&user.id, &hash)
+        .map_err(failed("update password"))?;
+
+    // So whoever knew the old password is logged out
+    let deleted = db
+        .delete_sessions(Some(&user.id))
+        .map_err(failed("delete sessions"))?;
+    info!(
+        "Changed the password of {}, and deleted {} session(s)",
+        user.name, deleted
+    );
+    Ok(())
+}
+
+fn create_room(
+    config: &Config,
+    db: &Database,
+    name: String,
+    visibility: Visibility,
+    owner: Option<String>,
+) -> CommandResult {
+    let name = name.trim().to_string();
+    if name.is_empty() || name.contains('/') || name.starts_with(room::DIRECT_NAME_PREFIX) {
+        error!(
+            "Room names can't be empty, contain '/' or start with {:?}",
+            room::DIRECT_NAME_PREFIX
+        );
+        return Err(Failed);
+    }
+    if db
+        .get_room_by_name(&name)
+        .map_err(failed("get room"))?
+        .is_some()
+    {
+        error!("Room {} already exists", name);
+        return Err(Failed);
+    }
+    let owner = owner.map(|name| find_user(db, &name)).transpose()?;
+
+    let room = Room {
+        id: next_snowflake(config)?,
+        name,
+        anonymous_reactions: false,
+        visibility,
+    };
+    db.add_room(&room).map_err(failed("add room"))?;
+    if let Some(owner) = owner {
+        db.set_role(&room.id, &owner.id, Role::Owner)
+            .map_err(failed("make user the owner of room"))?;
+    }
+    info!("Created room {} ({})", room.name, room.id.id());
+    Ok(())
+}
+
+fn find_user(db: &Database, name: &str) -> Result<User, Failed> {
+    match db.get_user_by_name(name).map_err(failed("get user"))? {
+        Some(user) => Ok(user),
+        None => {
+            error!("User {} doesn't exist", name);
+            Err(Failed)
+        }
+    }
+}
+
+/// Find a room by its id, or else its name.
+fn find_room(db: &Database, room: &str) -> Result<Room, Failed> {
+    let by_id = match room.parse::<i64>().map(room::Id::try_from) {
+        Ok(Ok(id)) => db.get_room(&id).map_err(failed("get room"))?,
+        _ => None,
+    };
+    let found = match by_id {
+        Some(room) => Some(room),
+        None => db
+            .get_room_by_name(&room.to_string())
+            .map_err(failed("get room"))?,
+    };
+
+    found.ok_or_else(|| {
+        error!("Room {} doesn't exist", room);
+        Failed
+    })
+}
+
+/// Read the password from stdin, or generate one and print it.
+fn password(from_stdin: bool) -> Result<String, Failed> {
+    if !from_stdin {
+        let password: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(GENERATED_PASSWORD_LEN)
+            .map(char::from)
+            .collect();
+        println!("{}", password);
+        return Ok(password);
+    }
+
+    let mut line = String::new();
+    if let Err(err) = std::io::stdin().lock().read_line(&mut line) {
+        error!("Failed to read password: {}", err);
+        return Err(Failed);
+    }
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        error!("The password can't be empty");
+        return Err(Failed);
+    }
+    Ok(password)
+}
+
+/// Generate an id. It's generated with the config's worker id, like the server's.
+fn next_snowflake(config: &Config) -> Result<Snowflake, Failed> {
+    crate::Snowcloud::new(config.worker_id, crate::EPOCH)
+        .and_then(|snowcloud| snowcloud.next_id())
+        .map(Snowflake::from)
+        .map_err(|err| {
+            error!("Failed to generate snowflake: {}", err);
+            Failed
+        })
+}
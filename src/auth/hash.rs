@@ -1,5 +1,5 @@
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    password_hash::{self, rand_core::OsRng, PasswordHasher, SaltString},
     Argon2, PasswordHash, PasswordVerifier,
 };
 
@@ -18,14 +18,17 @@ pub fn hash_password(password: String) -> String {
     password_hash
 }
 
+/// Parse a PHC string, to check that it's a valid hash.
+pub fn parse_hash(hash: &str) -> Result<PasswordHash<'_>, password_hash::Error> {
+    PasswordHash::new(hash)
+}
+
 /// Check if a password matches a hash.
 ///
-/// # Panics
-///
-/// This function will panic if the hash is invalid.
-pub fn check_passwords(password: String, hash: String) -> bool {
-    let parsed_hash = PasswordHash::new(&hash).expect("hash is valid");
-    Argon2::default()
+/// Returns an error if the hash is invalid (e.g. it was corrupted in the database).
+pub fn check_passwords(password: String, hash: String) -> Result<bool, password_hash::Error> {
+    let parsed_hash = parse_hash(&hash)?;
+    Ok(Argon2::default()
         .verify_password(password.as_bytes(), &parsed_hash)
-        .is_ok()
+        .is_ok())
 }
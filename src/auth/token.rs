@@ -4,6 +4,20 @@ use rand_core::OsRng;
 use crate::model::session::Token;
 
 pub fn generate_token() -> Token {
-    let mut rng = OsRng::default();
+    let mut rng = OsRng;
     rng.next_u64() as Token
 }
+
+/// The number of characters in an invite token.
+const INVITE_TOKEN_LEN: usize = 24;
+
+/// Generate a random, URL safe invite token.
+pub fn generate_invite_token() -> crate::model::invite::Token {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(INVITE_TOKEN_LEN)
+        .map(char::from)
+        .collect()
+}
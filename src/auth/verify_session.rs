@@ -1,27 +1,26 @@
 use log::{debug, error};
-use tokio::sync::MutexGuard;
 
-use crate::model::{Database, Session};
+use crate::model::{Session, Store};
 
 pub enum Error {
     SessionNotFound,
     DatabaseError,
 }
 
-pub fn verify_session(
+pub async fn verify_session(
     token: crate::model::session::Token,
-    database: MutexGuard<Database>,
+    database: &dyn Store,
 ) -> Result<Session, Error> {
     // Get and verify session
-    match database.get_session_from_token(&token) {
-        Ok(Some(session)) => return Ok(session),
+    match database.get_session_from_token(&token).await {
+        Ok(Some(session)) => Ok(session),
         Ok(None) => {
-            debug!("Session {} not found in database", token);
-            return Err(Error::SessionNotFound);
+            debug!("Session not found in database");
+            Err(Error::SessionNotFound)
         }
         Err(err) => {
             error!("Failed to get session from database: {}", err);
-            return Err(Error::DatabaseError);
+            Err(Error::DatabaseError)
         }
-    };
+    }
 }
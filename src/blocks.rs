@@ -0,0 +1,64 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use crate::model::{search::SearchResult, user, Message};
+
+/// The users that each user has blocked.
+#[derive(Debug, Default)]
+pub struct Blocks {
+    blocked: RwLock<HashMap<i64, HashSet<i64>>>,
+}
+
+impl Blocks {
+    /// Build from (user, blocked user) pairs.
+    pub fn build(blocks: Vec<(user::Id, user::Id)>) -> Blocks {
+        let this = Blocks::default();
+        for (user_id, blocked) in blocks {
+            this.add(&user_id, &blocked);
+        }
+        this
+    }
+
+    pub fn add(&self, user_id: &user::Id, blocked: &user::Id) {
+        let mut users = self.blocked.write().expect("blocks lock isn't poisoned");
+        users.entry(user_id.id()).or_default().insert(blocked.id());
+    }
+
+    pub fn remove(&self, user_id: &user::Id, blocked: &user::Id) {
+        let mut users = self.blocked.write().expect("blocks lock isn't poisoned");
+        if let Some(blocked_users) = users.get_mut(&user_id.id()) {
+            blocked_users.remove(&blocked.id());
+        }
+    }
+
+    pub fn has_blocked(&self, user_id: &user::Id, other: &user::Id) -> bool {
+        let users = self.blocked.read().expect("blocks lock isn't poisoned");
+        users
+            .get(&user_id.id())
+            .is_some_and(|blocked_users| blocked_users.contains(&other.id()))
+    }
+
+    /// Collapse the messages (in history) whose author the user has blocked: they're kept,
+    /// so their replies still have a parent, but their content is hidden.
+    pub fn collapse(&self, user_id: Option<&user::Id>, messages: &mut [Message]) {
+        let Some(user_id) = user_id else {
+            return;
+        };
+        for message in messages {
+            if self.has_blocked(user_id, &message.author) {
+                message.content = String::new();
+                message.blocked = true;
+            }
+        }
+    }
+
+    /// Drop the search results whose author the user has blocked.
+    pub fn filter_results(&self, user_id: Option<&user::Id>, results: &mut Vec<SearchResult>) {
+        let Some(user_id) = user_id else {
+            return;
+        };
+        results.retain(|result| !self.has_blocked(user_id, &result.message.author));
+    }
+}
@@ -0,0 +1,615 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::LevelFilter;
+use toml::{Table, Value};
+
+use crate::admin::{DbCommand, RoomCommand, SessionCommand, UserCommand};
+use crate::model::{
+    limits::{humantime_duration, Rate},
+    store::Backend,
+    Limits,
+};
+
+/// The config file that is used if none is given, if it exists.
+const DEFAULT_CONFIG_PATH: &str = "golem.toml";
+/// The environment variable with the config file to load.
+const CONFIG_ENV: &str = "GOLEM_CONFIG";
+/// The prefix of the environment variables that set config options.
+const ENV_PREFIX: &str = "GOLEM_";
+/// Separates the keys of nested options in environment variables, e.g. `GOLEM_LIMITS__MAX_MESSAGE_CHARS`.
+const ENV_SEPARATOR: &str = "__";
+/// The biggest worker id that fits in a snowflake.
+const MAX_WORKER_ID: i64 = (1 << 8) - 1;
+
+/// The command line flags.
+#[derive(Debug, clap::Parser)]
+#[command(version, about = "The golem chat server")]
+pub struct Cli {
+    /// The config file to load (or `GOLEM_CONFIG`). Defaults to `golem.toml`, if it exists.
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// The address to listen on, e.g. `0.0.0.0:7878`.
+    #[arg(long)]
+    bind: Option<String>,
+    /// The SQLite database file.
+    #[arg(long, value_name = "FILE")]
+    database: Option<String>,
+    /// The directory of the page templates.
+    #[arg(long, value_name = "DIR")]
+    templates: Option<String>,
+    /// The directory of the static files.
+    #[arg(long, value_name = "DIR")]
+    public: Option<String>,
+    /// The id of this server in the snowflakes it generates (0 to 255).
+    #[arg(long)]
+    worker_id: Option<i64>,
+    /// The log level of golem.
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<String>,
+    /// Set any other option, e.g. `--set limits.post_rate.burst=10`.
+    #[arg(short, long = "set", value_name = "KEY=VALUE")]
+    set: Vec<String>,
+    /// Bring the database's schema up to date, then exit instead of starting the server.
+    #[arg(long)]
+    pub migrate_only: bool,
+    /// With `--migrate-only`, check the migrations that would be run without changing the database.
+    #[arg(long, requires = "migrate_only")]
+    pub dry_run: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// What to do: start the server, or manage a deployment's data (and exit).
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Start the server (the default).
+    Serve,
+    /// Manage users.
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Manage rooms.
+    #[command(subcommand)]
+    Room(RoomCommand),
+    /// Manage sessions.
+    #[command(subcommand)]
+    Session(SessionCommand),
+    /// Maintain the database.
+    #[command(subcommand)]
+    Db(DbCommand),
+}
+
+/// The server's config.
+///
+/// Options are taken from (in order of precedence, lowest first) the defaults, the config file,
+/// `GOLEM_*` environment variables and command line flags.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The address to listen on.
+    pub bind: SocketAddr,
+    /// Where data is kept: in the SQLite `database`, or in memory.
+    pub store: Backend,
+    /// The SQLite database file.
+    pub database: PathBuf,
+    /// How many connections read from the database at once (there is one more that writes).
+    pub database_readers: usize,
+    /// The directory of the page templates.
+    pub templates: PathBuf,
+    /// The directory of the static files, served at `/static`.
+    pub public: PathBuf,
+    /// The id of this server in the snowflakes it generates.
+    pub worker_id: i64,
+    /// The addresses of reverse proxies, whose connections' clients are taken from
+    /// `X-Forwarded-For` (for bans and rate limits).
+    pub trusted_proxies: Vec<IpAddr>,
+    pub log: LogConfig,
+    pub limits: Limits,
+    pub shutdown: ShutdownConfig,
+    pub metrics: MetricsConfig,
+    pub health: HealthConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: ([0, 0, 0, 0], 7878).into(),
+            store: Backend::Sqlite,
+            database: "./db.sqlite3".into(),
+            database_readers: 4,
+            templates: "templates".into(),
+            public: "public".into(),
+            worker_id: 1,
+            trusted_proxies: Vec::new(),
+            log: LogConfig::default(),
+            limits: Limits::default(),
+            shutdown: ShutdownConfig::default(),
+            metrics: MetricsConfig::default(),
+            health: HealthConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// The log level of golem.
+    #[serde(with = "level_filter")]
+    pub level: LevelFilter,
+    /// The log level of dependencies.
+    #[serde(with = "level_filter")]
+    pub dependencies: LevelFilter,
+    pub format: LogFormat,
+}
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Coloured lines, for people.
+    #[default]
+    Text,
+    /// A JSON object on each line, for log pipelines.
+    Json,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: LevelFilter::Debug,
+            dependencies: LevelFilter::Info,
+            format: LogFormat::Text,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// How long to wait for connections to close and writes to finish, before exiting anyway.
+    #[serde(with = "humantime_duration")]
+    pub timeout: Duration,
+    /// How long clients are told to wait before reconnecting.
+    #[serde(with = "humantime_duration")]
+    pub reconnect_after: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            // Docker kills containers 10 seconds after stopping them
+            timeout: Duration::from_secs(8),
+            reconnect_after: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Whether the Prometheus metrics are served at `/metrics`.
+    pub enabled: bool,
+    /// The bearer token needed to get the metrics. If it's empty, they can only be got from
+    /// loopback addresses.
+    pub token: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// How long the database has to answer, for the server to be ready (`/readyz`).
+    #[serde(with = "humantime_duration")]
+    pub database_timeout: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            database_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// An environment variable or flag that isn't a valid option.
+    Override(String),
+    /// The options are the wrong type, or unknown.
+    Deserialize(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Read(path, err) => write!(f, "couldn't read {}: {}", path.display(), err),
+            Error::Parse(path, err) => write!(f, "couldn't parse {}: {}", path.display(), err),
+            Error::Override(msg) => write!(f, "{}", msg),
+            Error::Deserialize(err) => write!(f, "invalid config: {}", err),
+            Error::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Config {
+    /// Load the config from the config file, the environment and the command line, and validate it.
+    pub fn load(cli: &Cli) -> Result<Config, Error> {
+        Config::layer(cli, std::env::vars().collect())
+    }
+
+    /// Layer the config file, the environment variables (`env`) and the command line over the defaults.
+    fn layer(cli: &Cli, env: Vec<(String, String)>) -> Result<Config, Error> {
+        let mut table = Table::try_from(Config::default()).expect("default config serializes");
+
+        let config_path = cli.config.clone().or_else(|| {
+            env.iter()
+                .find(|(name, _)| name == CONFIG_ENV)
+                .map(|(_, path)| PathBuf::from(path))
+        });
+        match &config_path {
+            Some(path) => merge(&mut table, read_file(path)?),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                merge(&mut table, read_file(Path::new(DEFAULT_CONFIG_PATH))?)
+            }
+            None => {}
+        }
+
+        for (name, value) in &env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if name == CONFIG_ENV {
+                continue;
+            }
+            let path = key
+                .split(ENV_SEPARATOR)
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>();
+            set(&mut table, &path, value, name)?;
+        }
+
+        for (key, value) in cli.overrides()? {
+            let path = key.split('.').map(str::to_string).collect::<Vec<_>>();
+            set(&mut table, &path, &value, &key)?;
+        }
+
+        let config = Config::deserialize(table)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn deserialize(table: Table) -> Result<Config, Error> {
+        // Going through the text (instead of deserializing the table) makes errors show the option
+        let content = toml::to_string(&table).expect("config table serializes");
+        toml::from_str(&content).map_err(Error::Deserialize)
+    }
+
+    /// Check what only serving needs (not the admin commands or `--migrate-only`).
+    pub fn validate_serving(&self) -> Result<(), Error> {
+        for (name, dir) in [("templates", &self.templates), ("public", &self.public)] {
+            if !dir.is_dir() {
+                return Err(Error::Invalid(format!(
+                    "{} directory {} doesn't exist",
+                    name,
+                    dir.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if !(0..=MAX_WORKER_ID).contains(&self.worker_id) {
+            return Err(Error::Invalid(format!(
+                "worker_id must be between 0 and {} (got {})",
+                MAX_WORKER_ID, self.worker_id
+            )));
+        }
+
+        if self.database_readers == 0 {
+            return Err(Error::Invalid(
+                "database_readers must be more than 0".to_string(),
+            ));
+        }
+
+        if let (Backend::Sqlite, Some(dir)) = (self.store, self.database.parent()) {
+            if !dir.as_os_str().is_empty() && !dir.is_dir() {
+                return Err(Error::Invalid(format!(
+                    "the directory of database {} doesn't exist",
+                    self.database.display()
+                )));
+            }
+        }
+
+        if self.health.database_timeout.is_zero() {
+            return Err(Error::Invalid(
+                "health.database_timeout must be more than 0".to_string(),
+            ));
+        }
+
+        let limits = &self.limits;
+        if limits.max_message_chars == 0 {
+            return Err(Error::Invalid(
+                "limits.max_message_chars must be more than 0".to_string(),
+            ));
+        }
+        if limits.max_ws_frame_bytes == 0 {
+            return Err(Error::Invalid(
+                "limits.max_ws_frame_bytes must be more than 0".to_string(),
+            ));
+        }
+        let rates = [
+            ("post_rate", &limits.post_rate),
+            ("name_change_rate", &limits.name_change_rate),
+            ("load_rate", &limits.load_rate),
+            ("auth_rate", &limits.auth_rate),
+            ("strike_rate", &limits.strike_rate),
+        ];
+        for (name, rate) in rates {
+            validate_rate(name, rate)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Cli {
+    /// The options set by flags, as (dotted key, value) pairs.
+    fn overrides(&self) -> Result<Vec<(String, String)>, Error> {
+        let mut overrides = Vec::new();
+
+        let flags = [
+            ("bind", &self.bind),
+            ("database", &self.database),
+            ("templates", &self.templates),
+            ("public", &self.public),
+            ("log.level", &self.log_level),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                overrides.push((key.to_string(), value.clone()));
+            }
+        }
+        if let Some(worker_id) = self.worker_id {
+            overrides.push(("worker_id".to_string(), worker_id.to_string()));
+        }
+
+        for set in &self.set {
+            let Some((key, value)) = set.split_once('=') else {
+                return Err(Error::Override(format!(
+                    "--set {} should look like KEY=VALUE",
+                    set
+                )));
+            };
+            overrides.push((key.trim().to_string(), value.trim().to_string()));
+        }
+
+        Ok(overrides)
+    }
+}
+
+fn read_file(path: &Path) -> Result<Table, Error> {
+    let content =
+        std::fs::read_to_string(path).map_err(|err| Error::Read(path.to_path_buf(), err))?;
+    content
+        .parse::<Table>()
+        .map_err(|err| Error::Parse(path.to_path_buf(), err))
+}
+
+/// Merge the options of `other` into `table`, replacing the ones that are in both.
+fn merge(table: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (table.get_mut(&key), value) {
+            (Some(Value::Table(table)), Value::Table(other)) => merge(table, other),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Set a (nested) option. `source` is where it was set, for errors.
+fn set(table: &mut Table, path: &[String], value: &str, source: &str) -> Result<(), Error> {
+    let Some((key, parents)) = path.split_last() else {
+        return Err(Error::Override(format!("{} isn't an option", source)));
+    };
+
+    let mut table = table;
+    for parent in parents {
+        table = match table.get_mut(parent) {
+            Some(Value::Table(table)) => table,
+            _ => return Err(Error::Override(format!("{} isn't an option", source))),
+        };
+    }
+    let Some(current) = table.get(key) else {
+        return Err(Error::Override(format!("{} isn't an option", source)));
+    };
+    let value = parse_value(current, value);
+
+    // Tables (like a rate) can be set as a whole, with an inline table
+    match (table.get_mut(key), value) {
+        (Some(Value::Table(table)), Value::Table(other)) => merge(table, other),
+        (_, value) => {
+            table.insert(key.clone(), value);
+        }
+    }
+    Ok(())
+}
+
+/// Parse the value of an environment variable or flag as the type of the option's `current` value.
+///
+/// Options that are strings take the value as it is (so e.g. a token of digits stays a string).
+/// Others parse it as TOML (e.g. a number, or an inline table), falling back to a plain string.
+fn parse_value(current: &Value, value: &str) -> Value {
+    if let Value::String(_) = current {
+        return Value::String(value.to_string());
+    }
+    format!("value = {}", value)
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+fn validate_rate(name: &str, rate: &Rate) -> Result<(), Error> {
+    if rate.burst == 0 || rate.refill.is_zero() {
+        return Err(Error::Invalid(format!(
+            "limits.{} must have a burst and a refill of more than 0",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// (De)serialize a log level by name, e.g. `"info"`.
+mod level_filter {
+    use std::str::FromStr;
+
+    use log::LevelFilter;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(level: &LevelFilter, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&level.as_str().to_lowercase())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<LevelFilter, D::Error> {
+        let level = String::deserialize(deserializer)?;
+        LevelFilter::from_str(&level).map_err(|_| {
+            serde::de::Error::custom(format!(
+                "unknown log level {:?}, expected off, error, warn, info, debug or trace",
+                level
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    /// A config file (in the temporary directory) that is deleted when dropped.
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(content: &str) -> ConfigFile {
+            let name = format!("golem-test-{:016x}.toml", rand::random::<u64>());
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, content).unwrap();
+            ConfigFile(path)
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::parse_from(std::iter::once("golem").chain(args.iter().copied()))
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let file = ConfigFile::new(
+            "worker_id = 1\ndatabase_readers = 2\n[limits]\nmax_message_chars = 10\n",
+        );
+        let path = file.0.to_str().unwrap();
+
+        let config = Config::layer(&cli(&["--config", path]), Vec::new()).unwrap();
+        assert_eq!(config.worker_id, 1);
+        assert_eq!(config.database_readers, 2);
+        assert_eq!(config.limits.max_message_chars, 10);
+
+        let vars = env(&[("GOLEM_WORKER_ID", "2"), ("GOLEM_DATABASE_READERS", "3")]);
+        let config = Config::layer(&cli(&["--config", path]), vars.clone()).unwrap();
+        assert_eq!(config.worker_id, 2);
+        assert_eq!(config.database_readers, 3);
+
+        let config = Config::layer(&cli(&["--config", path, "--worker-id", "3"]), vars).unwrap();
+        assert_eq!(config.worker_id, 3);
+        assert_eq!(config.database_readers, 3);
+        assert_eq!(config.limits.max_message_chars, 10);
+
+        // The file can be given by the environment too
+        let config = Config::layer(&cli(&[]), env(&[("GOLEM_CONFIG", path)])).unwrap();
+        assert_eq!(config.worker_id, 1);
+    }
+
+    #[test]
+    fn nested_tables_are_merged() {
+        let file = ConfigFile::new("[limits.post_rate]\nburst = 2\n");
+        let path = file.0.to_str().unwrap();
+        let defaults = Limits::default();
+
+        let config = Config::layer(&cli(&["--config", path]), Vec::new()).unwrap();
+        assert_eq!(config.limits.post_rate.burst, 2);
+        assert_eq!(config.limits.post_rate.refill, defaults.post_rate.refill);
+        assert_eq!(config.limits.max_message_chars, defaults.max_message_chars);
+
+        let vars = env(&[("GOLEM_LIMITS__POST_RATE__REFILL", "3s")]);
+        let config = Config::layer(&cli(&["--config", path]), vars).unwrap();
+        assert_eq!(config.limits.post_rate.burst, 2);
+        assert_eq!(config.limits.post_rate.refill, Duration::from_secs(3));
+
+        let set = ["--set", "limits.load_rate={ burst = 4 }"];
+        let config = Config::layer(&cli(&set), Vec::new()).unwrap();
+        assert_eq!(config.limits.load_rate.burst, 4);
+        assert_eq!(config.limits.load_rate.refill, defaults.load_rate.refill);
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        for name in ["GOLEM_NOPE", "GOLEM_LIMITS__NOPE", "GOLEM_WORKER_ID__NOPE"] {
+            let result = Config::layer(&cli(&[]), env(&[(name, "1")]));
+            assert!(matches!(result, Err(Error::Override(_))), "{}", name);
+        }
+        let result = Config::layer(&cli(&["--set", "nope=1"]), Vec::new());
+        assert!(matches!(result, Err(Error::Override(_))));
+
+        // Other variables are ignored
+        assert!(Config::layer(&cli(&[]), env(&[("PATH", "/bin")])).is_ok());
+    }
+
+    #[test]
+    fn values_have_the_type_of_their_option() {
+        let vars = env(&[
+            ("GOLEM_METRICS__TOKEN", "123456"),
+            ("GOLEM_METRICS__ENABLED", "true"),
+            ("GOLEM_LOG__LEVEL", "info"),
+        ]);
+        let config = Config::layer(&cli(&[]), vars).unwrap();
+        assert_eq!(config.metrics.token, "123456");
+        assert!(config.metrics.enabled);
+        assert_eq!(config.log.level, LevelFilter::Info);
+
+        let config = Config::layer(&cli(&["--set", "metrics.token=true"]), Vec::new()).unwrap();
+        assert_eq!(config.metrics.token, "true");
+
+        let vars = env(&[("GOLEM_TRUSTED_PROXIES", r#"["10.0.0.1"]"#)]);
+        let config = Config::layer(&cli(&[]), vars).unwrap();
+        assert_eq!(config.trusted_proxies, [IpAddr::from([10, 0, 0, 1])]);
+
+        let vars = env(&[("GOLEM_WORKER_ID", "one")]);
+        let result = Config::layer(&cli(&[]), vars);
+        assert!(matches!(result, Err(Error::Deserialize(_))));
+    }
+}
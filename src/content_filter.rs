@@ -0,0 +1,340 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use log::{debug, warn};
+use regex::{Regex, RegexBuilder};
+
+use crate::model::{
+    filter::{Action, Rule},
+    room,
+};
+
+/// The most memory a compiled blocklist regex can use.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Something that checks the content of messages before they're stored,
+/// e.g. a blocklist or a classifier.
+pub trait ContentFilter: Send + Sync {
+    /// The name of the filter, recorded in the moderation log when it matches.
+    fn name(&self) -> &str;
+
+    /// Check the content of a message that is being sent in a room.
+    fn check(&self, room_id: &room::Id, content: &str) -> Verdict;
+}
+
+/// What a [`ContentFilter`] decided to do with a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Send the message with different (masked) content.
+    Mask {
+        content: String,
+        reason: String,
+    },
+    Hold {
+        reason: String,
+    },
+    Reject {
+        reason: String,
+    },
+}
+
+/// A filter that matched a message.
+#[derive(Clone, Debug)]
+pub struct Hit {
+    pub action: Action,
+    /// The filter's name, and why it matched.
+    pub reason: String,
+}
+
+/// The result of running every filter on a message.
+#[derive(Clone, Debug)]
+pub struct Outcome {
+    /// The content to send, after masking.
+    pub content: String,
+    pub hits: Vec<Hit>,
+}
+
+impl Outcome {
+    /// The highest action of the filters that matched, if any did.
+    pub fn action(&self) -> Option<Action> {
+        self.hits.iter().map(|hit| hit.action).max()
+    }
+}
+
+/// The content filters that messages go through: the rooms' blocklists,
+/// then any other filters that were added.
+pub struct ContentFilters {
+    blocklist: Blocklist,
+    filters: Vec<Box<dyn ContentFilter>>,
+}
+
+impl ContentFilters {
+    /// The other `filters` run after the blocklist, in order.
+    pub fn new(blocklist: Blocklist, filters: Vec<Box<dyn ContentFilter>>) -> ContentFilters {
+        ContentFilters { blocklist, filters }
+    }
+
+    pub fn blocklist(&self) -> &Blocklist {
+        &self.blocklist
+    }
+
+    /// Run every filter on a message's content.
+    ///
+    /// Stops at the first filter that rejects it. Masked content is passed on to the next filter.
+    pub fn check(&self, room_id: &room::Id, content: &str) -> Outcome {
+        let mut outcome = Outcome {
+            content: content.to_string(),
+            hits: Vec::new(),
+        };
+
+        let filters = std::iter::once(&self.blocklist as &dyn ContentFilter)
+            .chain(self.filters.iter().map(|filter| filter.as_ref()));
+        for filter in filters {
+            let (action, reason) = match filter.check(room_id, &outcome.content) {
+                Verdict::Allow => continue,
+                Verdict::Mask { content, reason } => {
+                    outcome.content = content;
+                    (Action::Mask, reason)
+                }
+                Verdict::Hold { reason } => (Action::Hold, reason),
+                Verdict::Reject { reason } => (Action::Reject, reason),
+            };
+            debug!("Content filter {} matched: {:?}", filter.name(), action);
+
+            outcome.hits.push(Hit {
+                action,
+                reason: format!("{}: {}", filter.name(), reason),
+            });
+            if action == Action::Reject {
+                break;
+            }
+        }
+
+        outcome
+    }
+}
+
+/// The regexes that each room blocks.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    rooms: RwLock<HashMap<i64, Vec<(Rule, Regex)>>>,
+}
+
+impl Blocklist {
+    /// Build a blocklist from every room's rules.
+    ///
+    /// Rules that aren't valid regexes are skipped.
+    pub fn build(rules: Vec<Rule>) -> Blocklist {
+        let blocklist = Blocklist::default();
+        for rule in rules {
+            match compile(&rule.pattern) {
+                Ok(regex) => blocklist.add(rule, regex),
+                Err(err) => warn!("Skipping invalid blocklist rule {}: {}", rule.id, err),
+            }
+        }
+        blocklist
+    }
+
+    /// Add a rule (and its [compiled](compile) pattern) to its room's blocklist.
+    pub fn add(&self, rule: Rule, regex: Regex) {
+        let mut rooms = self.rooms.write().expect("blocklist lock isn't poisoned");
+        rooms
+            .entry(rule.room_id.id())
+            .or_default()
+            .push((rule, regex));
+    }
+
+    pub fn remove(&self, room_id: &room::Id, id: &crate::model::filter::Id) {
+        let mut rooms = self.rooms.write().expect("blocklist lock isn't poisoned");
+        if let Some(rules) = rooms.get_mut(&room_id.id()) {
+            rules.retain(|(rule, _)| &rule.id != id);
+        }
+    }
+}
+
+impl ContentFilter for Blocklist {
+    fn name(&self) -> &str {
+        "blocklist"
+    }
+
+    fn check(&self, room_id: &room::Id, content: &str) -> Verdict {
+        let rooms = self.rooms.read().expect("blocklist lock isn't poisoned");
+        let Some(rules) = rooms.get(&room_id.id()) else {
+            return Verdict::Allow;
+        };
+
+        let mut content = content.to_string();
+        let mut matched: Option<&Rule> = None;
+        for (rule, regex) in rules {
+            if !regex.is_match(&content) {
+                continue;
+            }
+            if rule.action == Action::Mask {
+                content = regex
+                    .replace_all(&content, |captures: &regex::Captures| {
+                        "*".repeat(captures[0].chars().count())
+                    })
+                    .into_owned();
+            }
+            if matched.is_none_or(|matched| rule.action > matched.action) {
+                matched = Some(rule);
+            }
+        }
+
+        let Some(rule) = matched else {
+            return Verdict::Allow;
+        };
+        let reason = format!("rule {}", rule.id);
+        match rule.action {
+            Action::Mask => Verdict::Mask { content, reason },
+            Action::Hold => Verdict::Hold { reason },
+            Action::Reject => Verdict::Reject { reason },
+        }
+    }
+}
+
+/// Compile a blocklist pattern. Patterns are case insensitive.
+pub fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Snowflake;
+
+    fn id(id: i64) -> Snowflake {
+        Snowflake::try_from(id).unwrap()
+    }
+
+    fn rule(rule_id: i64, pattern: &str, action: Action) -> Rule {
+        Rule {
+            id: id(rule_id),
+            room_id: room::main_id(),
+            pattern: pattern.to_string(),
+            action,
+        }
+    }
+
+    fn blocklist(rules: &[(&str, Action)]) -> Blocklist {
+        let rules = rules
+            .iter()
+            .enumerate()
+            .map(|(i, &(pattern, action))| rule(i as i64 + 1, pattern, action))
+            .collect();
+        Blocklist::build(rules)
+    }
+
+    /// Rejects messages that have some text in them.
+    struct Contains(&'static str);
+
+    impl ContentFilter for Contains {
+        fn name(&self) -> &str {
+            "contains"
+        }
+
+        fn check(&self, _: &room::Id, content: &str) -> Verdict {
+            if content.contains(self.0) {
+                Verdict::Reject {
+                    reason: self.0.to_string(),
+                }
+            } else {
+                Verdict::Allow
+            }
+        }
+    }
+
+    #[test]
+    fn actions() {
+        let main = room::main_id();
+        let masks = blocklist(&[("darn", Action::Mask)]);
+        assert_eq!(
+            masks.check(&main, "darn it, darn"),
+            Verdict::Mask {
+                content: "**** it, ****".to_string(),
+                reason: "rule 1".to_string(),
+            }
+        );
+        assert_eq!(masks.check(&main, "hello"), Verdict::Allow);
+
+        let holds = blocklist(&[("https?://", Action::Hold)]);
+        assert!(matches!(
+            holds.check(&main, "see https://example.com"),
+            Verdict::Hold { .. }
+        ));
+
+        let rejects = blocklist(&[("spam", Action::Reject)]);
+        assert!(matches!(
+            rejects.check(&main, "buy spam"),
+            Verdict::Reject { .. }
+        ));
+    }
+
+    #[test]
+    fn the_highest_action_wins() {
+        let main = room::main_id();
+        let blocklist = blocklist(&[
+            ("darn", Action::Mask),
+            ("spam", Action::Reject),
+            ("link", Action::Hold),
+        ]);
+        assert_eq!(
+            blocklist.check(&main, "darn link"),
+            Verdict::Hold {
+                reason: "rule 3".to_string()
+            }
+        );
+        assert_eq!(
+            blocklist.check(&main, "darn spam link"),
+            Verdict::Reject {
+                reason: "rule 2".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn patterns_are_case_insensitive_regexes() {
+        let main = room::main_id();
+        let blocklist = blocklist(&[(r"\bcat\b", Action::Mask)]);
+        assert!(matches!(
+            blocklist.check(&main, "CAT!"),
+            Verdict::Mask { content, .. } if content == "***!"
+        ));
+        assert_eq!(blocklist.check(&main, "concatenate"), Verdict::Allow);
+    }
+
+    #[test]
+    fn rules_only_apply_in_their_room() {
+        let blocklist = blocklist(&[("spam", Action::Reject)]);
+        assert_eq!(blocklist.check(&id(2), "spam"), Verdict::Allow);
+
+        blocklist.remove(&room::main_id(), &id(1));
+        assert_eq!(blocklist.check(&room::main_id(), "spam"), Verdict::Allow);
+    }
+
+    #[test]
+    fn filters_run_after_the_blocklist() {
+        let main = room::main_id();
+        let filters = ContentFilters::new(
+            blocklist(&[("darn", Action::Mask), ("spam", Action::Reject)]),
+            vec![Box::new(Contains("darn")), Box::new(Contains("spam"))],
+        );
+
+        // The other filters get the masked content
+        let outcome = filters.check(&main, "darn");
+        assert_eq!(outcome.content, "****");
+        assert_eq!(outcome.action(), Some(Action::Mask));
+
+        // and don't run once a message is rejected
+        let outcome = filters.check(&main, "spam");
+        assert_eq!(outcome.hits.len(), 1);
+        assert_eq!(outcome.hits[0].reason, "blocklist: rule 2");
+
+        let outcome = filters.check(&main, "hello");
+        assert!(outcome.hits.is_empty());
+        assert_eq!(outcome.action(), None);
+    }
+}
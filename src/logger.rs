@@ -1,34 +1,182 @@
-use std::time::SystemTime;
+use std::{
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
+};
 
+use crate::config::{Cli, Config, LogConfig, LogFormat};
+use axum::{
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
 use fern::{
     colors::{Color, ColoredLevelConfig},
     Dispatch,
 };
-use log::LevelFilter;
+use log::{error, info, LevelFilter, Metadata};
+use tokio::task::JoinHandle;
 
-pub fn init() {
-    let colors = ColoredLevelConfig::new()
-        .info(Color::Green)
-        .trace(Color::BrightBlack);
+/// The header that a request's span id is returned in.
+const REQUEST_ID_HEADER: &str = "x-request-id";
 
-    Dispatch::new()
-        .format(move |out, message, record| {
+/// The log levels, which can be changed while the server runs (see [`set_levels`]).
+static LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+static DEPENDENCIES_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+
+tokio::task_local! {
+    /// The id of the request or websocket connection that is being handled, added to log lines.
+    static SPAN: String;
+}
+
+pub fn init(config: &LogConfig) {
+    let dispatch = match config.format {
+        LogFormat::Text => text(),
+        LogFormat::Json => Dispatch::new().format(|out, message, record| {
+            let line = JsonLine {
+                time: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+                level: record.level().as_str(),
+                target: record.target(),
+                span: current_span(),
+                message: message.to_string(),
+            };
             out.finish(format_args!(
-                // Color the info the level color, and the message bright white
-                "{}[{} {} {}]\x1B[0m {}{}\x1B[0m",
-                format_args!("\x1B[{}m", colors.get_color(&record.level()).to_fg_str()),
-                humantime::format_rfc3339_seconds(SystemTime::now()),
-                colors.color(record.level()),
-                record.target(),
-                format_args!("\x1B[{}m", Color::BrightWhite.to_fg_str()),
-                message
+                "{}",
+                serde_json::to_string(&line).expect("log line serializes")
             ))
-        })
-        // Only info from dependencies
-        .level(LevelFilter::Info)
-        // But everything for this crate (golem)
-        .level_for("golem", LevelFilter::Debug)
+        }),
+    };
+
+    dispatch
+        .filter(enabled)
         .chain(std::io::stdout())
         .apply()
         .expect("logger should initialize");
+    set_levels(config);
+}
+
+fn text() -> Dispatch {
+    let colors = ColoredLevelConfig::new()
+        .info(Color::Green)
+        .trace(Color::BrightBlack);
+
+    Dispatch::new().format(move |out, message, record| {
+        out.finish(format_args!(
+            // Color the info the level color, and the message bright white
+            "{}[{} {} {}{}]\x1B[0m {}{}\x1B[0m",
+            format_args!("\x1B[{}m", colors.get_color(&record.level()).to_fg_str()),
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            colors.color(record.level()),
+            record.target(),
+            current_span()
+                .map(|span| format!(" {}", span))
+                .unwrap_or_default(),
+            format_args!("\x1B[{}m", Color::BrightWhite.to_fg_str()),
+            message
+        ))
+    })
+}
+
+#[derive(serde::Serialize)]
+struct JsonLine<'a> {
+    time: String,
+    level: &'a str,
+    target: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    span: Option<String>,
+    message: String,
+}
+
+/// Change the log levels (e.g. when the config is reloaded).
+pub fn set_levels(config: &LogConfig) {
+    LEVEL.store(config.level as usize, Ordering::Relaxed);
+    DEPENDENCIES_LEVEL.store(config.dependencies as usize, Ordering::Relaxed);
+    // So the `log` macros can skip what won't be logged, without asking the logger
+    log::set_max_level(config.level.max(config.dependencies));
+}
+
+/// Reload the log levels from the config each time the server gets SIGHUP.
+pub async fn reload_on_hangup(cli: Cli) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(err) => {
+                error!("Failed to listen for SIGHUP: {}", err);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            match Config::load(&cli) {
+                Ok(config) => {
+                    set_levels(&config.log);
+                    info!(
+                        "Reloaded log levels (golem: {}, dependencies: {})",
+                        config.log.level, config.log.dependencies
+                    );
+                }
+                Err(err) => error!("Failed to reload the config: {}", err),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = cli;
+}
+
+fn enabled(metadata: &Metadata) -> bool {
+    // Less from dependencies than from this crate (golem)
+    let target = metadata.target();
+    let level = if target == "golem" || target.starts_with("golem::") {
+        &LEVEL
+    } else {
+        &DEPENDENCIES_LEVEL
+    };
+    metadata.level() as usize <= level.load(Ordering::Relaxed)
+}
+
+/// The span of the current task, if it has one.
+pub fn current_span() -> Option<String> {
+    SPAN.try_with(|span| span.clone()).ok()
+}
+
+/// A new span id, to tell apart the log lines of concurrent requests.
+pub fn new_span() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Run a future in a span, so its log lines have the span's id.
+pub async fn in_span<F: Future>(span: String, future: F) -> F::Output {
+    SPAN.scope(span, future).await
+}
+
+/// Run a closure in a span, for code that isn't async (like database queries).
+pub fn in_span_blocking<T>(span: String, f: impl FnOnce() -> T) -> T {
+    SPAN.sync_scope(span, f)
+}
+
+/// Spawn a task that stays in the current span.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let span = current_span();
+    tokio::spawn(async move {
+        match span {
+            Some(span) => in_span(span, future).await,
+            None => future.await,
+        }
+    })
+}
+
+/// A middleware that runs each request in a new span, and returns its id in `X-Request-Id`.
+pub async fn request_span<B>(request: Request<B>, next: Next<B>) -> Response {
+    let span = new_span();
+    let mut response = in_span(span.clone(), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&span) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
 }
@@ -1,34 +1,125 @@
+use std::net::SocketAddr;
+
 use axum::{
     middleware,
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
-use log::info;
-use model::AppState;
+use clap::Parser;
+use config::{Cli, Command, Config};
+use log::{error, info, warn};
+use model::{database::migrations, room, store::Backend, AppState, Store};
 
+mod admin;
 mod auth;
+mod blocks;
+mod config;
+mod content_filter;
 mod logger;
+mod metrics;
 mod model;
+mod presences;
+mod rate_limit;
 mod routes;
+mod shutdown;
 mod templates;
 
 type Snowcloud = snowcloud::MultiThread<43, 8, 12>;
 const EPOCH: u64 = 1650667342;
-const PRIMARY_ID: i64 = 1;
-
-const ROOT_PATH: &str = "0.0.0.0:7878";
 
 #[tokio::main]
 async fn main() {
-    logger::init();
+    let mut cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("golem: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    logger::init(&config.log);
+
+    if cli.migrate_only {
+        std::process::exit(migrate(&config, cli.dry_run));
+    }
+    match cli.command.take() {
+        None | Some(Command::Serve) => {}
+        Some(Command::User(command)) => std::process::exit(admin::user(&config, command)),
+        Some(Command::Room(command)) => std::process::exit(admin::room(&config, command)),
+        Some(Command::Session(command)) => std::process::exit(admin::session(&config, command)),
+        Some(Command::Db(command)) => std::process::exit(admin::db(&config, command)),
+    }
+    if let Err(err) = config.validate_serving() {
+        eprintln!("golem: {}", err);
+        std::process::exit(1);
+    }
 
-    info!("Starting golem server at {}", ROOT_PATH);
+    info!("Starting golem server at {}", config.bind);
+    tokio::spawn(logger::reload_on_hangup(cli));
 
-    let state = AppState::new();
+    let state = AppState::new(&config).await;
+    let shutdown = state.shutdown.clone();
+    let database = state.database.clone();
+    log_main_room_owners(database.as_ref()).await;
 
     let app = Router::new()
         .route("/api/user/:id", get(routes::get_user))
         .route("/api/logout", post(routes::sessions::logout))
+        .route(
+            "/api/user/blocks",
+            get(routes::blocks::get_blocks)
+                .post(routes::blocks::block_user)
+                .delete(routes::blocks::unblock_user),
+        )
+        .route("/api/rooms", post(routes::rooms::create_room))
+        .route(
+            "/api/rooms/:id/invites",
+            get(routes::invites::get_invites).post(routes::invites::create_invite),
+        )
+        .route(
+            "/api/rooms/:id/invites/:token",
+            delete(routes::invites::delete_invite),
+        )
+        .route("/api/invites/:token", post(routes::invites::accept_invite))
+        .route(
+            "/api/dm",
+            get(routes::dm::get_direct_rooms_of_user).post(routes::dm::create_direct_room),
+        )
+        .route(
+            "/api/notifications",
+            get(routes::notifications::get_notifications),
+        )
+        .route(
+            "/api/notifications/read",
+            post(routes::notifications::read_notifications),
+        )
+        .route("/api/rooms/:id/modlog", get(routes::rooms::get_mod_log))
+        .route("/api/rooms/:id/reports", get(routes::reports::get_reports))
+        .route(
+            "/api/rooms/:id/held",
+            get(routes::reports::get_held_messages),
+        )
+        .route(
+            "/api/rooms/:id/filters",
+            get(routes::filters::get_filter_rules).post(routes::filters::add_filter_rule),
+        )
+        .route(
+            "/api/rooms/:id/filters/:rule_id",
+            delete(routes::filters::remove_filter_rule),
+        )
+        .route(
+            "/api/reports/:id/claim",
+            post(routes::reports::claim_report),
+        )
+        .route(
+            "/api/reports/:id/resolve",
+            post(routes::reports::resolve_report),
+        )
+        .route(
+            "/api/reports/:id/dismiss",
+            post(routes::reports::dismiss_report),
+        )
         .layer(middleware::from_fn_with_state(
             state.clone(),
             routes::auth::authenticate,
@@ -37,12 +128,125 @@ async fn main() {
         .route("/api/login", post(routes::sessions::login))
         .route("/api/register", post(routes::register::register))
         .route("/api/snowflake", get(routes::snowflake))
+        .route("/metrics", get(routes::metrics::metrics))
+        .route("/healthz", get(routes::health::healthz))
+        .route("/readyz", get(routes::health::readyz))
+        .route("/api/rooms", get(routes::rooms::get_rooms))
         .route("/api/snapshot", get(routes::messages::get_snapshot))
-        .nest_service("/", templates::router(state.clone().into()))
-        .with_state(state.into());
+        .route("/api/rooms/:id/search", get(routes::rooms::search))
+        .route(
+            "/api/rooms/:id/messages",
+            get(routes::rooms::get_messages_between),
+        )
+        .nest_service("/", templates::router(state.clone(), &config))
+        .with_state(state.into())
+        .layer(middleware::from_fn(metrics::track_requests))
+        .layer(middleware::from_fn(logger::request_span));
+
+    let server = axum::Server::bind(&config.bind)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown({
+            let shutdown = shutdown.clone();
+            async move { shutdown.wait().await }
+        });
+    let mut server = tokio::spawn(server);
+
+    tokio::select! {
+        result = &mut server => {
+            match result {
+                Ok(Ok(())) => error!("Server stopped unexpectedly"),
+                Ok(Err(err)) => error!("Server failed: {}", err),
+                Err(err) => error!("Server panicked: {}", err),
+            }
+            std::process::exit(1);
+        }
+        _ = shutdown::signal() => {}
+    }
+
+    info!(
+        "Shutting down (waiting up to {:?} for connections to close)",
+        config.shutdown.timeout
+    );
+    shutdown.start();
+
+    let drained = tokio::time::timeout(config.shutdown.timeout, async {
+        if let Ok(Err(err)) = server.await {
+            error!("Server failed while shutting down: {}", err);
+        }
+        shutdown.connections_closed().await;
+        database.close().await
+    })
+    .await;
+
+    match drained {
+        Ok(Ok(())) => info!("Shut down"),
+        Ok(Err(err)) => {
+            error!("Failed to close the database: {}", err);
+            std::process::exit(1);
+        }
+        Err(_) => {
+            warn!("Connections didn't close in time, so exiting anyway");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Say who owns the main room, since it's given to its first user (even in a database that had
+/// users before rooms had owners) rather than chosen by an operator.
+async fn log_main_room_owners(database: &dyn Store) {
+    let owners = match database.get_owners(&room::main_id()).await {
+        Ok(owners) => owners,
+        Err(err) => {
+            error!("Failed to get the owners of the main room: {:?}", err);
+            return;
+        }
+    };
+    if owners.is_empty() {
+        warn!("The main room has no owner yet (the first user to register will be one)");
+        return;
+    }
+
+    let mut names = Vec::new();
+    for id in owners {
+        match database.get_user_name(&id).await {
+            Ok(Some(name)) => names.push(name),
+            Ok(None) => names.push(id.to_string()),
+            Err(err) => {
+                error!("Failed to get user name from database: {:?}", err);
+                return;
+            }
+        }
+    }
+    info!("The main room is owned by {}", names.join(", "));
+}
+
+/// Run the database migrations (`--migrate-only`), returning the exit code.
+fn migrate(config: &Config, dry_run: bool) -> i32 {
+    if config.store != Backend::Sqlite {
+        error!("Only the sqlite store has migrations");
+        return 1;
+    }
+
+    let result = rusqlite::Connection::open(&config.database)
+        .map_err(migrations::Error::from)
+        .and_then(|mut conn| migrations::run(&mut conn, dry_run));
 
-    axum::Server::bind(&ROOT_PATH.parse().unwrap())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    match result {
+        Ok(migrations) if migrations.is_empty() => {
+            info!("Database is up to date");
+            0
+        }
+        Ok(migrations) if dry_run => {
+            info!("{} migration(s) would be run", migrations.len());
+            0
+        }
+        Ok(migrations) => {
+            info!("Ran {} migration(s)", migrations.len());
+            0
+        }
+        Err(err) => {
+            error!("Failed to migrate database: {}", err);
+            1
+        }
+    }
 }
@@ -0,0 +1,113 @@
+use std::time::Instant;
+
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::Response};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
+    HistogramVec, IntCounter, IntCounterVec, IntGaugeVec,
+};
+
+use crate::model::room;
+
+lazy_static! {
+    static ref CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "golem_ws_connections",
+        "The open websocket connections in each room.",
+        &["room"]
+    )
+    .expect("metric is valid");
+    static ref MESSAGES_POSTED: IntCounter = register_int_counter!(
+        "golem_messages_posted_total",
+        "The messages that were posted (including held ones)."
+    )
+    .expect("metric is valid");
+    static ref BROADCAST_LAGS: IntCounter = register_int_counter!(
+        "golem_broadcast_lags_total",
+        "How often a connection fell behind the broadcast channel, and missed messages."
+    )
+    .expect("metric is valid");
+    static ref DATABASE_QUERIES: HistogramVec = register_histogram_vec!(
+        "golem_database_query_seconds",
+        "How long database queries take, by `Database` method (including waiting for a connection).",
+        &["method"],
+        vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+    )
+    .expect("metric is valid");
+    static ref LOGINS: IntCounterVec = register_int_counter_vec!(
+        "golem_logins_total",
+        "Login attempts, by whether they succeeded.",
+        &["result"]
+    )
+    .expect("metric is valid");
+    static ref HTTP_REQUESTS: HistogramVec = register_histogram_vec!(
+        "golem_http_request_seconds",
+        "How long HTTP requests take, by route, method and status.",
+        &["route", "method", "status"]
+    )
+    .expect("metric is valid");
+}
+
+/// A websocket connection to a room, counted until it is dropped.
+pub struct Connection {
+    room: String,
+}
+
+impl Connection {
+    pub fn open(room_id: &room::Id) -> Connection {
+        let room = room_id.id().to_string();
+        CONNECTIONS.with_label_values(&[&room]).inc();
+        Connection { room }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Empty rooms are kept at 0: removing them here would race with new connections to them
+        CONNECTIONS.with_label_values(&[&self.room]).dec();
+    }
+}
+
+pub fn message_posted() {
+    MESSAGES_POSTED.inc();
+}
+
+pub fn broadcast_lagged() {
+    BROADCAST_LAGS.inc();
+}
+
+pub fn login(succeeded: bool) {
+    let result = if succeeded { "success" } else { "failure" };
+    LOGINS.with_label_values(&[result]).inc();
+}
+
+/// Time a database query, until the returned timer is dropped.
+pub fn time_query(method: &'static str) -> prometheus::HistogramTimer {
+    DATABASE_QUERIES.with_label_values(&[method]).start_timer()
+}
+
+/// A middleware that records how long requests take.
+pub async fn track_requests<B>(request: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    // The route, rather than the path, so ids don't each get their own series
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_owned(),
+        None => "unmatched".to_owned(),
+    };
+    let method = request.method().clone();
+
+    let response = next.run(request).await;
+
+    HTTP_REQUESTS
+        .with_label_values(&[&route, method.as_str(), response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
+
+/// Every metric, in the Prometheus text format.
+pub fn render() -> Result<String, prometheus::Error> {
+    use prometheus::Encoder;
+
+    let mut buffer = Vec::new();
+    prometheus::TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer).expect("the text format is UTF-8"))
+}
@@ -1,36 +1,94 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
-use tokio::sync::Mutex;
+use crate::{
+    blocks::Blocks,
+    config::{Config, HealthConfig, MetricsConfig},
+    content_filter::{Blocklist, ContentFilters},
+    presences::Presences,
+    rate_limit::RateLimiter,
+    shutdown::Shutdown,
+};
 
 pub mod database;
+pub mod filter;
+pub mod invite;
+pub mod limits;
+pub mod mention;
 pub mod message;
+pub mod moderation;
+pub mod reaction;
+pub mod report;
+pub mod role;
 pub mod room;
+pub mod search;
 pub mod session;
 pub mod snowflake;
+pub mod store;
+pub mod timestamp;
 pub mod user;
 
-pub use database::Database;
+pub use limits::Limits;
+pub use mention::Mention;
 pub use message::Message;
+pub use reaction::Reaction;
+pub use report::Report;
+pub use role::Role;
 pub use room::Room;
 pub use session::Session;
 pub use snowflake::Snowflake;
+pub use store::Store;
+pub use timestamp::Timestamp;
 pub use user::User;
 
 #[derive(Clone)]
 pub struct AppState {
     pub snowcloud: crate::Snowcloud,
-    pub database: Arc<Mutex<Database>>,
+    pub database: Arc<dyn Store>,
+    pub limits: Limits,
+    pub metrics: MetricsConfig,
+    pub health: HealthConfig,
+    pub trusted_proxies: Vec<IpAddr>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub presences: Arc<Presences>,
+    pub content_filters: Arc<ContentFilters>,
+    pub blocks: Arc<Blocks>,
+    pub shutdown: Arc<Shutdown>,
 }
 
 impl AppState {
-    pub fn new() -> AppState {
-        let snowcloud = crate::Snowcloud::new(crate::PRIMARY_ID, crate::EPOCH)
+    pub async fn new(config: &Config) -> AppState {
+        let snowcloud = crate::Snowcloud::new(config.worker_id, crate::EPOCH)
             .expect("Failed to create snowcloud.");
-        let database = Arc::new(Mutex::new(Database::build().unwrap()));
+        let database = store::open(config).expect("Failed to open store.");
+
+        let rules = database
+            .get_filter_rules(None)
+            .await
+            .expect("Failed to get filter rules from store.");
+        // Other filters (e.g. a classifier) go after the blocklist
+        let content_filters = Arc::new(ContentFilters::new(Blocklist::build(rules), Vec::new()));
+
+        let blocks = database
+            .get_blocks()
+            .await
+            .expect("Failed to get blocks from store.");
+        let blocks = Arc::new(Blocks::build(blocks));
+
+        let limits = config.limits.clone();
+        let rate_limiter = Arc::new(RateLimiter::new(limits.clone()));
 
         AppState {
             snowcloud,
             database,
+            limits,
+            metrics: config.metrics.clone(),
+            health: config.health.clone(),
+            trusted_proxies: config.trusted_proxies.clone(),
+            rate_limiter,
+            presences: Arc::new(Presences::default()),
+            content_filters,
+            blocks,
+            shutdown: Arc::new(Shutdown::new(config.shutdown.reconnect_after)),
         }
     }
 
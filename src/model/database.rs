@@ -1,6 +1,31 @@
-use super::{Message, Room, Session, Snowflake, User};
+use super::{
+    filter,
+    invite::Invite,
+    moderation::{Ban, LogEntry, Silence},
+    report::{self, Report},
+    room::Visibility,
+    search::{self, SearchQuery, SearchResult},
+    Mention, Message, Reaction, Role, Room, Session, Snowflake, Timestamp, User,
+};
 use log::{debug, info, trace};
-use rusqlite::{types::FromSql, Connection, OptionalExtension, Result as SqlResult, Row};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Result as SqlResult, Row};
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+mod admin;
+mod check;
+pub mod migrations;
+mod pool;
+#[cfg(test)]
+pub(crate) mod testing;
+
+pub use pool::Pool;
+
+/// How long a connection waits for the database to be unlocked before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 type Result<T> = SqlResult<Option<T>>;
 
@@ -11,63 +36,46 @@ pub struct Database {
 
 /// Build the database.
 impl Database {
-    pub fn build() -> SqlResult<Database> {
-        let conn = Connection::open("./db.sqlite3")?;
+    /// Open the database, and bring its schema up to date.
+    pub fn build(path: &Path) -> std::result::Result<Database, migrations::Error> {
+        let mut conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // So readers don't have to wait for the writer
+        let mode: String =
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+        debug!("Database journal mode: {}", mode);
+        migrations::run(&mut conn, false)?;
         let db = Database { conn };
-        db.init_tables()?;
         db.init_main_room()?;
         Ok(db)
     }
 
-    fn init_tables(&self) -> SqlResult<()> {
-        trace!("Initializing database tables...");
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                id   INT PRIMARY KEY,
-                name TEXT NOT NULL,
-                password TEXT NOT NULL
-            )",
-            (),
-        )?;
-
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id          INT PRIMARY KEY,
-                author      INT NOT NULL,
-                author_name TEXT NOT NULL,
-                parent      INT NOT NULL,
-                content     TEXT NOT NULL
-            )",
-            (),
-        )?;
-
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS sessions (
-                id      INT PRIMARY KEY,
-                token   INT NOT NULL,
-                user    INT NOT NULL,
-                FOREIGN KEY(user) REFERENCES users(id)
-            )",
-            (),
-        )?;
-
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS rooms (
-                id   INT PRIMARY KEY,
-                name TEXT NOT NULL
-            )",
-            (),
+    /// Open a connection that can only read from the database. The database must already be built.
+    pub fn open_reader(path: &Path) -> SqlResult<Database> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
         )?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(Database { conn })
+    }
 
-        trace!("Finished initializing database tables.");
+    /// The version of the database's schema (the last migration that was run on it).
+    pub fn schema_version(&self) -> SqlResult<u32> {
+        migrations::version(&self.conn)
+    }
 
-        Ok(())
+    /// Copy the changes in the write-ahead log into the database file, and empty the log.
+    pub fn checkpoint(&self) -> SqlResult<()> {
+        debug!("Checkpointing the database");
+        self.conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", (), |_| Ok(()))
     }
 
     fn init_main_room(&self) -> SqlResult<()> {
         self.conn.execute(
-            "INSERT OR IGNORE INTO rooms (id, name) VALUES (0, 'main')",
-            (),
+            "INSERT OR IGNORE INTO rooms (id, name) VALUES (?1, 'main')",
+            (super::room::MAIN_ID,),
         )?;
         info!("Created main room.");
         Ok(())
@@ -89,11 +97,7 @@ impl Database {
         debug!("Getting user {}", id.id());
         self.conn
             .query_row("SELECT * FROM users WHERE id=?1", (id.id(),), |row| {
-                Ok(User {
-                    id: self.get_snowflake_column(row, 0),
-                    name: self.get_column(row, 1),
-                    password: self.get_column(row, 2),
-                })
+                self.map_user(row)
             })
             .optional()
     }
@@ -102,11 +106,7 @@ impl Database {
         debug!("Getting user (name: {})", name);
         self.conn
             .query_row("SELECT * FROM users WHERE name=?1", (name,), |row| {
-                Ok(User {
-                    id: self.get_snowflake_column(row, 0),
-                    name: self.get_column(row, 1),
-                    password: self.get_column(row, 2),
-                })
+                self.map_user(row)
             })
             .optional()
     }
@@ -115,32 +115,97 @@ impl Database {
         debug!("Getting user name for user {}", id.id());
         self.conn
             .query_row("SELECT name FROM users WHERE id=?1", (id.id(),), |row| {
-                Ok(self.get_column(row, 0))
+                row.get(0)
             })
             .optional()
     }
+
+    /// How many users have registered.
+    pub fn count_users(&self) -> SqlResult<u64> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM users", (), |row| row.get(0))
+    }
+
+    fn map_user(&self, row: &Row) -> SqlResult<User> {
+        Ok(User {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            password: row.get(2)?,
+        })
+    }
+}
+
+/// Block stuff
+impl Database {
+    /// Block a user. Blocking a user twice does nothing.
+    pub fn add_block(&self, user_id: &super::user::Id, blocked: &super::user::Id) -> SqlResult<()> {
+        debug!("User {} is blocking user {}", user_id, blocked);
+        self.conn.execute(
+            "INSERT OR IGNORE INTO blocks (user, blocked) VALUES (?1, ?2)",
+            (user_id.id(), blocked.id()),
+        )?;
+        Ok(())
+    }
+
+    /// Unblock a user. Returns whether they were blocked.
+    pub fn remove_block(
+        &self,
+        user_id: &super::user::Id,
+        blocked: &super::user::Id,
+    ) -> SqlResult<bool> {
+        debug!("User {} is unblocking user {}", user_id, blocked);
+        let removed = self.conn.execute(
+            "DELETE FROM blocks WHERE user=?1 AND blocked=?2",
+            (user_id.id(), blocked.id()),
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// Get the users that a user has blocked.
+    pub fn get_blocked_users(&self, user_id: &super::user::Id) -> SqlResult<Vec<super::user::Id>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT blocked FROM blocks WHERE user=?1 ORDER BY blocked")?;
+        let blocked = stmt
+            .query_map((user_id.id(),), |row| row.get(0))?
+            .collect::<SqlResult<Vec<_>>>();
+
+        blocked
+    }
+
+    /// Get every block, as (user, blocked user) pairs.
+    pub fn get_blocks(&self) -> SqlResult<Vec<(super::user::Id, super::user::Id)>> {
+        let mut stmt = self.conn.prepare("SELECT user, blocked FROM blocks")?;
+        let blocks = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqlResult<Vec<_>>>();
+
+        blocks
+    }
 }
 
 /// Messages stuff
 impl Database {
     // FIXME: Only selects top level messages
-    pub fn get_recent_messages(&self) -> SqlResult<Vec<Message>> {
-        self.get_some_messages(None, 100)
+    pub fn get_recent_messages(&self, room_id: &super::room::Id) -> SqlResult<Vec<Message>> {
+        self.get_some_messages(room_id, None, 100)
     }
 
+    #[allow(dead_code)]
     pub fn get_messages(&self) -> SqlResult<Vec<Message>> {
         trace!("Getting all messages");
 
         let mut stmt = self
             .conn
             .prepare("SELECT * FROM messages ORDER BY id DESC")?;
-        let messages = stmt
+        let mut messages = stmt
             .query_map((), |row| self.map_message(row))?
-            .collect::<SqlResult<Vec<_>>>();
+            .collect::<SqlResult<Vec<_>>>()?;
+        self.add_reactions(&mut messages)?;
 
         trace!("Got all messages");
 
-        messages
+        Ok(messages)
     }
 
     /// Get the `amount` messages before the given message.
@@ -151,65 +216,102 @@ impl Database {
     /// This will only get top level messages.
     pub fn get_some_messages(
         &self,
+        room_id: &super::room::Id,
         before: Option<Snowflake>,
         amount: u8,
     ) -> SqlResult<Vec<Message>> {
         // Get top level messages
         let mut stmt = self.conn.prepare(
-            "SELECT * FROM messages WHERE parent IS NULL AND id <= ?1 ORDER BY id DESC LIMIT ?2",
+            "SELECT * FROM messages
+                WHERE room=?1 AND parent=?1 AND held=0 AND (?2 IS NULL OR id < ?2)
+                ORDER BY id DESC LIMIT ?3",
         )?;
-        let messages = stmt
-            .query_map((before.map(|id| id.id()), amount), |row| {
+        let mut messages = stmt
+            .query_map((room_id.id(), before.map(|id| id.id()), amount), |row| {
                 self.map_message(row)
             })?
-            .collect::<SqlResult<Vec<_>>>();
+            .collect::<SqlResult<Vec<_>>>()?;
+        self.add_reactions(&mut messages)?;
 
-        messages
+        Ok(messages)
     }
 
-    pub fn get_children_of(&self, parent: Option<&Snowflake>) -> SqlResult<Vec<Message>> {
-        // Start by getting only the direct children of the given message
-        // Then, for each child, get the children of that child, and so on
-        // Until there are no more children
+    /// Get every message in a room, oldest first.
+    pub fn get_room_messages(&self, room_id: &super::room::Id) -> SqlResult<Vec<Message>> {
+        trace!("Getting all messages in room {}", room_id);
 
         let mut stmt = self
             .conn
-            .prepare("SELECT * FROM messages WHERE parent=?1")?;
-
-        let mut direct_children: Vec<Message> = stmt
-            .query_map((parent.map(|id| id.id()),), |row| self.map_message(row))?
-            // Safe b/c the values are all set to be `Ok` above
-            .map(|msg| msg.unwrap())
-            .collect();
-
-        let mut children: Vec<Message> = direct_children
-            .iter()
-            .map(|child| self.get_children_of(Some(&child.id)))
-            .flat_map(|result| match result {
-                Ok(vec) => vec.into_iter().map(|item| Ok(item)).collect(),
-                Err(er) => vec![Err(er)],
-            })
-            .collect::<SqlResult<_>>()?;
+            .prepare("SELECT * FROM messages WHERE room=?1 AND held=0 ORDER BY id")?;
+        let mut messages = stmt
+            .query_map((room_id.id(),), |row| self.map_message(row))?
+            .collect::<SqlResult<Vec<_>>>()?;
+        self.add_reactions(&mut messages)?;
+
+        Ok(messages)
+    }
 
-        let mut messages = Vec::new();
-        messages.append(&mut direct_children);
-        messages.append(&mut children);
-        messages.sort_unstable();
+    /// Get every reply to a message (and their replies, and so on) that is in the room.
+    ///
+    /// Messages in other rooms aren't returned, even if `parent` is one of them.
+    pub fn get_children_of(
+        &self,
+        room_id: &super::room::Id,
+        parent: Option<&Snowflake>,
+    ) -> SqlResult<Vec<Message>> {
+        // Walk down from the given message, one generation of replies at a time
+        let mut stmt = self.conn.prepare_cached(
+            "WITH RECURSIVE children(id) AS (
+                SELECT id FROM messages WHERE parent=?1 AND room=?2 AND held=0
+                UNION ALL
+                SELECT messages.id FROM messages JOIN children ON messages.parent = children.id
+                    WHERE messages.room=?2 AND messages.held=0
+            )
+            SELECT messages.* FROM messages JOIN children ON messages.id = children.id
+                ORDER BY messages.id",
+        )?;
+        let mut messages = stmt
+            .query_map((parent.map(|id| id.id()), room_id.id()), |row| {
+                self.map_message(row)
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        self.add_reactions(&mut messages)?;
 
         Ok(messages)
     }
 
+    pub fn get_message(&self, id: &super::message::Id) -> Result<Message> {
+        debug!("Getting message {}", id.id());
+        let message = self
+            .conn
+            .query_row("SELECT * FROM messages WHERE id=?1", (id.id(),), |row| {
+                self.map_message(row)
+            })
+            .optional()?;
+
+        match message {
+            Some(mut message) => {
+                message.reactions = self.get_reactions(&message.id)?;
+                Ok(Some(message))
+            }
+            None => Ok(None),
+        }
+    }
+
     pub fn add_message(&self, message: &Message) -> SqlResult<()> {
         debug!("Adding message {} to database", message.id.id());
 
         self.conn.execute(
-            "INSERT INTO messages (id, author, author_name, parent, content) VALUES (?1, ?2, ?3, ?4, ?5)",
+            "INSERT INTO messages (id, author, author_name, parent, content, room, held)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
             (
                 message.id.id(),
                 message.author.id(),
                 message.author_name.as_str(),
                 message.parent.id(),
                 message.content.as_str(),
+                message.room.id(),
+                message.held,
             ),
         )?;
 
@@ -218,33 +320,389 @@ impl Database {
         Ok(())
     }
 
+    /// Get the ancestors of a message, starting with the room it is in,
+    /// then the top level message, and ending with the direct parent.
+    pub fn get_ancestors(&self, id: &super::message::Id) -> SqlResult<Vec<Snowflake>> {
+        trace!("Getting ancestors of message {}", id.id());
+
+        let mut stmt = self.conn.prepare_cached(
+            "WITH RECURSIVE path(id, parent, depth) AS (
+                SELECT id, parent, 0 FROM messages WHERE id=?1
+                UNION ALL
+                SELECT messages.id, messages.parent, path.depth + 1
+                    FROM messages JOIN path ON messages.id = path.parent
+            )
+            SELECT parent FROM path ORDER BY depth DESC",
+        )?;
+        let ancestors = stmt
+            .query_map((id.id(),), |row| row.get(0))?
+            .collect::<SqlResult<Vec<_>>>();
+
+        ancestors
+    }
+
+    /// Get the first `limit` messages in a room that were sent in the given time range
+    /// (from `start`, up to but not including `end`), oldest first.
+    pub fn get_messages_between(
+        &self,
+        room_id: &super::room::Id,
+        start: SystemTime,
+        end: SystemTime,
+        limit: u8,
+    ) -> SqlResult<Vec<Message>> {
+        debug!(
+            "Getting messages in room {} between {} and {}",
+            room_id,
+            humantime::format_rfc3339(start),
+            humantime::format_rfc3339(end)
+        );
+
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM messages
+                WHERE room=?1 AND held=0 AND id >= ?2 AND id < ?3
+                ORDER BY id LIMIT ?4",
+        )?;
+        let mut messages = stmt
+            .query_map(
+                (
+                    room_id.id(),
+                    Snowflake::first_at(start).id(),
+                    Snowflake::first_at(end).id(),
+                    limit,
+                ),
+                |row| self.map_message(row),
+            )?
+            .collect::<SqlResult<Vec<_>>>()?;
+        self.add_reactions(&mut messages)?;
+
+        Ok(messages)
+    }
+
+    /// Search the messages in a room, best matches first.
+    pub fn search_messages(
+        &self,
+        room_id: &super::room::Id,
+        query: &SearchQuery,
+    ) -> SqlResult<Vec<SearchResult>> {
+        debug!("Searching room {} for {:?}", room_id, query.q);
+
+        let Some(fts_query) = search::to_fts_query(&query.q) else {
+            return Ok(Vec::new());
+        };
+        let limit = query.limit.unwrap_or(search::DEFAULT_LIMIT);
+
+        let mut stmt = self.conn.prepare(
+            "SELECT messages_fts.rowid, snippet(messages_fts, 0, ?2, ?3, '…', 16)
+                FROM messages_fts JOIN messages ON messages.id = messages_fts.rowid
+                WHERE messages_fts MATCH ?1
+                    AND messages.room = ?7
+                    AND messages.held = 0
+                    AND (?4 IS NULL OR messages.author = ?4)
+                    AND (?5 IS NULL OR messages.id < ?5)
+                    AND (?6 IS NULL OR messages.id > ?6)
+                ORDER BY rank
+                LIMIT ?8",
+        )?;
+        let mut rows = stmt.query((
+            fts_query,
+            search::MATCH_START.to_string(),
+            search::MATCH_END.to_string(),
+            query.author.as_ref().map(|id| id.id()),
+            query.before.as_ref().map(|id| id.id()),
+            query.after.as_ref().map(|id| id.id()),
+            room_id.id(),
+            limit,
+        ))?;
+
+        let mut results = Vec::new();
+        while let Some(row) = rows.next()? {
+            let id: super::message::Id = row.get(0)?;
+            let snippet: String = row.get(1)?;
+
+            // The first ancestor is the room
+            let path = self.get_ancestors(&id)?.into_iter().skip(1).collect();
+
+            let Some(message) = self.get_message(&id)? else {
+                continue;
+            };
+
+            results.push(SearchResult {
+                message,
+                snippet: search::escape_snippet(&snippet),
+                path,
+            });
+        }
+
+        Ok(results)
+    }
+
+    pub fn update_message_content(&self, id: &super::message::Id, content: &str) -> SqlResult<()> {
+        debug!("Updating content of message {}", id.id());
+        self.conn.execute(
+            "UPDATE messages SET content=?2 WHERE id=?1",
+            (id.id(), content),
+        )?;
+        Ok(())
+    }
+
+    /// Delete a message.
+    ///
+    /// The message is kept (with empty content) so that its replies still have a parent,
+    /// but its reactions and mentions are removed.
+    pub fn delete_message(&self, id: &super::message::Id) -> SqlResult<()> {
+        debug!("Deleting message {}", id.id());
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE messages SET content='', deleted=1 WHERE id=?1",
+            (id.id(),),
+        )?;
+        tx.execute("DELETE FROM reactions WHERE message=?1", (id.id(),))?;
+        tx.execute("DELETE FROM mentions WHERE message=?1", (id.id(),))?;
+        tx.commit()
+    }
+
+    /// Get the messages in a room that are held for review, oldest first.
+    pub fn get_held_messages(&self, room_id: &super::room::Id) -> SqlResult<Vec<Message>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT * FROM messages WHERE room=?1 AND held=1 ORDER BY id")?;
+        let mut messages = stmt
+            .query_map((room_id.id(),), |row| self.map_message(row))?
+            .collect::<SqlResult<Vec<_>>>()?;
+        self.add_reactions(&mut messages)?;
+
+        Ok(messages)
+    }
+
+    /// Show a message that was held for review.
+    pub fn approve_message(&self, id: &super::message::Id) -> SqlResult<()> {
+        debug!("Approving message {}", id.id());
+        self.conn
+            .execute("UPDATE messages SET held=0 WHERE id=?1", (id.id(),))?;
+        Ok(())
+    }
+
+    /// Map a row to a message, without its reactions (see [`Database::add_reactions()`]).
     fn map_message(&self, row: &Row) -> SqlResult<Message> {
         trace!("Mapping db row to message");
 
-        let id = self.get_snowflake_column(row, 0);
-        let author = self.get_snowflake_column(row, 1);
-        let author_name = self.get_column(row, 2);
-        let parent = self.get_snowflake_column(row, 3);
-        let content = self.get_column(row, 4);
+        let id: super::message::Id = row.get(0)?;
+        let author = row.get(1)?;
+        let author_name = row.get(2)?;
+        let parent = row.get(3)?;
+        let content = row.get(4)?;
+        let room = row.get(5)?;
+        let deleted = row.get(6)?;
+        let held = row.get(7)?;
 
         Ok(Message {
+            created_at: id.created_at().into(),
             id,
             author,
             author_name,
             parent,
+            room,
             content,
+            deleted,
+            held,
+            blocked: false,
+            reactions: Vec::new(),
         })
     }
 }
 
+/// Reaction stuff
+impl Database {
+    /// Add a reaction to a message.
+    ///
+    /// Returns whether the reaction was added (i.e. it didn't already exist).
+    pub fn add_reaction(
+        &self,
+        message_id: &super::message::Id,
+        user_id: &super::user::Id,
+        emoji: &str,
+    ) -> SqlResult<bool> {
+        debug!(
+            "Adding reaction {} from user {} to message {}",
+            emoji,
+            user_id.id(),
+            message_id.id()
+        );
+        let changed = self.conn.execute(
+            "INSERT OR IGNORE INTO reactions (message, user, emoji) VALUES (?1, ?2, ?3)",
+            (message_id.id(), user_id.id(), emoji),
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Remove a reaction from a message.
+    ///
+    /// Returns whether the reaction was removed (i.e. it existed).
+    pub fn remove_reaction(
+        &self,
+        message_id: &super::message::Id,
+        user_id: &super::user::Id,
+        emoji: &str,
+    ) -> SqlResult<bool> {
+        debug!(
+            "Removing reaction {} from user {} on message {}",
+            emoji,
+            user_id.id(),
+            message_id.id()
+        );
+        let changed = self.conn.execute(
+            "DELETE FROM reactions WHERE message=?1 AND user=?2 AND emoji=?3",
+            (message_id.id(), user_id.id(), emoji),
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Get the reactions on a message, aggregated by emoji.
+    /// They are in the order that each emoji was first used.
+    pub fn get_reactions(&self, message_id: &super::message::Id) -> SqlResult<Vec<Reaction>> {
+        trace!("Getting reactions for message {}", message_id.id());
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT emoji, COUNT(*) FROM reactions WHERE message=?1
+                GROUP BY emoji ORDER BY MIN(rowid)",
+        )?;
+        let reactions = stmt
+            .query_map((message_id.id(),), |row| {
+                Ok(Reaction {
+                    emoji: row.get(0)?,
+                    count: row.get(1)?,
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>();
+
+        reactions
+    }
+
+    /// Get the reactions of some messages with one query, instead of one for each message.
+    fn add_reactions<'a>(
+        &self,
+        messages: impl IntoIterator<Item = &'a mut Message>,
+    ) -> SqlResult<()> {
+        let mut messages = messages
+            .into_iter()
+            .map(|message| (message.id.id(), message))
+            .collect::<HashMap<_, _>>();
+        if messages.is_empty() {
+            return Ok(());
+        }
+        trace!("Getting reactions for {} messages", messages.len());
+
+        // The ids are passed as a JSON array, since SQLite doesn't have array parameters
+        let ids =
+            serde_json::to_string(&messages.keys().collect::<Vec<_>>()).expect("ids serialize");
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT message, emoji, COUNT(*) FROM reactions
+                WHERE message IN (SELECT value FROM json_each(?1))
+                GROUP BY message, emoji ORDER BY MIN(rowid)",
+        )?;
+        let mut rows = stmt.query((ids,))?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            if let Some(message) = messages.get_mut(&id) {
+                message.reactions.push(Reaction {
+                    emoji: row.get(1)?,
+                    count: row.get(2)?,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Mention stuff
+impl Database {
+    pub fn add_mention(&self, message: &Message, user_id: &super::user::Id) -> SqlResult<()> {
+        debug!(
+            "Adding mention of user {} in message {}",
+            user_id.id(),
+            message.id.id()
+        );
+        self.conn.execute(
+            "INSERT OR IGNORE INTO mentions (message, user) VALUES (?1, ?2)",
+            (message.id.id(), user_id.id()),
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_mention(
+        &self,
+        message_id: &super::message::Id,
+        user_id: &super::user::Id,
+    ) -> SqlResult<()> {
+        debug!(
+            "Removing mention of user {} in message {}",
+            user_id.id(),
+            message_id.id()
+        );
+        self.conn.execute(
+            "DELETE FROM mentions WHERE message=?1 AND user=?2",
+            (message_id.id(), user_id.id()),
+        )?;
+        Ok(())
+    }
+
+    /// Get all of the mentions of a user that haven't been read yet, newest first.
+    pub fn get_unread_mentions(&self, user_id: &super::user::Id) -> SqlResult<Vec<Mention>> {
+        debug!("Getting unread mentions for user {}", user_id.id());
+
+        let mut stmt = self.conn.prepare(
+            "SELECT messages.* FROM mentions
+                JOIN messages ON messages.id = mentions.message
+                WHERE mentions.user=?1 AND mentions.read=0
+                ORDER BY messages.id DESC",
+        )?;
+        let mut mentions = stmt
+            .query_map((user_id.id(),), |row| {
+                Ok(Mention {
+                    user_id: user_id.clone(),
+                    message: self.map_message(row)?,
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        self.add_reactions(mentions.iter_mut().map(|mention| &mut mention.message))?;
+
+        Ok(mentions)
+    }
+
+    /// Mark all of a user's mentions up to (and including) the given message as read.
+    pub fn mark_mentions_read(
+        &self,
+        user_id: &super::user::Id,
+        up_to: &super::message::Id,
+    ) -> SqlResult<()> {
+        debug!(
+            "Marking mentions of user {} up to {} as read",
+            user_id.id(),
+            up_to.id()
+        );
+        self.conn.execute(
+            "UPDATE mentions SET read=1 WHERE user=?1 AND message<=?2",
+            (user_id.id(), up_to.id()),
+        )?;
+        Ok(())
+    }
+}
+
 /// Room stuff
 impl Database {
     pub fn add_room(&self, room: &Room) -> SqlResult<()> {
         debug!("Adding room {} to database", room.id.id());
 
         self.conn.execute(
-            "INSERT INTO rooms (id, name) VALUES (?1, ?2)",
-            (room.id.id(), room.name.as_str()),
+            "INSERT INTO rooms (id, name, anonymous_reactions, visibility)
+                VALUES (?1, ?2, ?3, ?4)",
+            (
+                room.id.id(),
+                room.name.as_str(),
+                room.anonymous_reactions,
+                room.visibility,
+            ),
         )?;
 
         debug!("Added room {} to database", room.id);
@@ -253,15 +711,12 @@ impl Database {
         Ok(())
     }
 
-    pub fn get_room(&self, id: crate::model::room::Id) -> Result<Room> {
+    pub fn get_room(&self, id: &crate::model::room::Id) -> Result<Room> {
         debug!("Getting room {} from database", id);
 
         self.conn
             .query_row("SELECT * FROM rooms WHERE id=?1", (id.id(),), |row| {
-                Ok(Room {
-                    id: self.get_snowflake_column(row, 0),
-                    name: self.get_column(row, 1),
-                })
+                self.map_room(row)
             })
             .optional()
     }
@@ -271,13 +726,553 @@ impl Database {
 
         self.conn
             .query_row("SELECT * FROM rooms WHERE name=?1", (name,), |row| {
-                Ok(Room {
-                    id: self.get_snowflake_column(row, 0),
-                    name: self.get_column(row, 1),
-                })
+                self.map_room(row)
+            })
+            .optional()
+    }
+
+    pub fn update_room_settings(
+        &self,
+        id: &crate::model::room::Id,
+        settings: &crate::model::room::Settings,
+    ) -> SqlResult<()> {
+        debug!("Updating settings of room {}", id);
+        self.conn.execute(
+            "UPDATE rooms SET anonymous_reactions=?2, visibility=COALESCE(?3, visibility)
+                WHERE id=?1",
+            (id.id(), settings.anonymous_reactions, settings.visibility),
+        )?;
+        Ok(())
+    }
+
+    fn map_room(&self, row: &Row) -> SqlResult<Room> {
+        Ok(Room {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            anonymous_reactions: row.get(2)?,
+            visibility: row.get(3)?,
+        })
+    }
+
+    /// Get the rooms that are listed (i.e. public), by name.
+    pub fn get_public_rooms(&self) -> SqlResult<Vec<Room>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT * FROM rooms WHERE visibility='public' ORDER BY name")?;
+        let rooms = stmt
+            .query_map((), |row| self.map_room(row))?
+            .collect::<SqlResult<Vec<_>>>();
+
+        rooms
+    }
+
+    /// Get the direct conversations that a user is in, newest first.
+    pub fn get_direct_rooms(&self, user_id: &super::user::Id) -> SqlResult<Vec<Room>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT rooms.* FROM rooms
+                JOIN room_members ON room_members.room=rooms.id
+                WHERE rooms.visibility='direct' AND room_members.user=?1
+                ORDER BY rooms.id DESC",
+        )?;
+        let rooms = stmt
+            .query_map((user_id.id(),), |row| self.map_room(row))?
+            .collect::<SqlResult<Vec<_>>>();
+
+        rooms
+    }
+
+    /// Whether a user (or an anonymous presence, if `None`) can see and join a room.
+    pub fn can_access_room(
+        &self,
+        room: &Room,
+        user_id: Option<&super::user::Id>,
+    ) -> SqlResult<bool> {
+        match (room.visibility, user_id) {
+            (Visibility::Public | Visibility::Unlisted, _) => Ok(true),
+            (Visibility::Private | Visibility::Direct, None) => Ok(false),
+            (Visibility::Private | Visibility::Direct, Some(user_id)) => {
+                self.is_member(&room.id, user_id)
+            }
+        }
+    }
+}
+
+/// Room member stuff
+impl Database {
+    /// Get the role of a user in a room.
+    /// If there is no user (i.e. they're anonymous), or they haven't been given a role,
+    /// the default role is returned.
+    pub fn get_role(
+        &self,
+        room_id: &crate::model::room::Id,
+        user_id: Option<&super::user::Id>,
+    ) -> SqlResult<Role> {
+        let Some(user_id) = user_id else {
+            return Ok(Role::ANONYMOUS);
+        };
+
+        trace!("Getting role of user {} in room {}", user_id, room_id);
+        let role = self
+            .conn
+            .query_row(
+                "SELECT role FROM room_members WHERE room=?1 AND user=?2",
+                (room_id.id(), user_id.id()),
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(role.unwrap_or(Role::DEFAULT_USER))
+    }
+
+    pub fn set_role(
+        &self,
+        room_id: &crate::model::room::Id,
+        user_id: &super::user::Id,
+        role: Role,
+    ) -> SqlResult<()> {
+        debug!(
+            "Setting role of user {} in room {} to {:?}",
+            user_id, room_id, role
+        );
+        self.conn.execute(
+            "INSERT INTO room_members (room, user, role) VALUES (?1, ?2, ?3)
+                ON CONFLICT(room, user) DO UPDATE SET role=excluded.role",
+            (room_id.id(), user_id.id(), role),
+        )?;
+        Ok(())
+    }
+
+    /// Whether a user has been given a role in a room (e.g. by accepting an invite).
+    pub fn is_member(
+        &self,
+        room_id: &crate::model::room::Id,
+        user_id: &super::user::Id,
+    ) -> SqlResult<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM room_members WHERE room=?1 AND user=?2)",
+            (room_id.id(), user_id.id()),
+            |row| row.get(0),
+        )
+    }
+
+    /// Get the users that have been given a role in a room.
+    pub fn get_members(&self, room_id: &crate::model::room::Id) -> SqlResult<Vec<super::user::Id>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT user FROM room_members WHERE room=?1 ORDER BY user")?;
+        let members = stmt
+            .query_map((room_id.id(),), |row| row.get(0))?
+            .collect::<SqlResult<Vec<_>>>();
+
+        members
+    }
+
+    /// Get the owners of a room.
+    pub fn get_owners(&self, room_id: &crate::model::room::Id) -> SqlResult<Vec<super::user::Id>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT user FROM room_members WHERE room=?1 AND role=?2 ORDER BY user")?;
+        let owners = stmt
+            .query_map((room_id.id(), Role::Owner), |row| row.get(0))?
+            .collect::<SqlResult<Vec<_>>>();
+
+        owners
+    }
+
+    /// Make a user a member of a room, unless they already have a role in it.
+    pub fn add_member(
+        &self,
+        room_id: &crate::model::room::Id,
+        user_id: &super::user::Id,
+    ) -> SqlResult<()> {
+        debug!("Adding user {} to room {}", user_id, room_id);
+        self.conn.execute(
+            "INSERT OR IGNORE INTO room_members (room, user, role) VALUES (?1, ?2, ?3)",
+            (room_id.id(), user_id.id(), Role::DEFAULT_USER),
+        )?;
+        Ok(())
+    }
+}
+
+/// Invite stuff
+impl Database {
+    pub fn add_invite(&self, invite: &Invite) -> SqlResult<()> {
+        debug!("Adding invite to room {}", invite.room_id);
+        self.conn.execute(
+            "INSERT INTO invites (token, room, creator, expires_at, max_uses, uses)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                &invite.token,
+                invite.room_id.id(),
+                invite.creator.id(),
+                invite.expires_at,
+                invite.max_uses,
+                invite.uses,
+            ),
+        )?;
+        Ok(())
+    }
+
+    pub fn get_invite(&self, token: &str) -> Result<Invite> {
+        self.conn
+            .query_row("SELECT * FROM invites WHERE token=?1", (token,), |row| {
+                self.map_invite(row)
             })
             .optional()
     }
+
+    pub fn get_invites(&self, room_id: &super::room::Id) -> SqlResult<Vec<Invite>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT * FROM invites WHERE room=?1 ORDER BY rowid")?;
+        let invites = stmt
+            .query_map((room_id.id(),), |row| self.map_invite(row))?
+            .collect::<SqlResult<Vec<_>>>();
+
+        invites
+    }
+
+    /// Use an invite, if it hasn't expired or run out of uses.
+    ///
+    /// Returns whether it was used.
+    pub fn use_invite(&self, token: &str) -> SqlResult<bool> {
+        let used = self.conn.execute(
+            "UPDATE invites SET uses=uses+1
+                WHERE token=?1
+                    AND (max_uses IS NULL OR uses < max_uses)
+                    AND (expires_at IS NULL OR expires_at > ?2)",
+            (token, Timestamp::now()),
+        )?;
+        Ok(used > 0)
+    }
+
+    /// Remove an invite from a room.
+    ///
+    /// Returns whether the invite existed.
+    pub fn delete_invite(&self, room_id: &super::room::Id, token: &str) -> SqlResult<bool> {
+        debug!("Deleting invite to room {}", room_id);
+        let deleted = self.conn.execute(
+            "DELETE FROM invites WHERE token=?1 AND room=?2",
+            (token, room_id.id()),
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn map_invite(&self, row: &Row) -> SqlResult<Invite> {
+        Ok(Invite {
+            token: row.get(0)?,
+            room_id: row.get(1)?,
+            creator: row.get(2)?,
+            expires_at: row.get(3)?,
+            max_uses: row.get(4)?,
+            uses: row.get(5)?,
+        })
+    }
+}
+
+/// Moderation stuff
+impl Database {
+    pub fn add_ban(&self, ban: &Ban) -> SqlResult<()> {
+        debug!(
+            "Adding ban {} of {} in room {}",
+            ban.id, ban.user_id, ban.room_id
+        );
+        self.conn.execute(
+            "INSERT INTO bans (id, room, user, ip, until, reason, actor)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            (
+                ban.id.id(),
+                ban.room_id.id(),
+                ban.user_id.id(),
+                ban.ip.map(|ip| ip.to_string()),
+                ban.until,
+                ban.reason.as_deref(),
+                ban.actor.id(),
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Get a ban (that hasn't ended) of the user or address from a room, if there is one.
+    ///
+    /// Addresses are only checked for anonymous users (when `user_id` is `None`),
+    /// so registered users aren't banned for sharing an address.
+    pub fn get_active_ban(
+        &self,
+        room_id: &super::room::Id,
+        user_id: Option<&super::user::Id>,
+        ip: std::net::IpAddr,
+    ) -> Result<Ban> {
+        trace!("Checking for bans in room {}", room_id);
+        self.conn
+            .query_row(
+                "SELECT * FROM bans
+                    WHERE room=?1 AND (user=?2 OR (?2 IS NULL AND ip=?3)) AND (until IS NULL OR until > ?4)
+                    ORDER BY until IS NULL DESC, until DESC
+                    LIMIT 1",
+                (
+                    room_id.id(),
+                    user_id.map(|id| id.id()),
+                    ip.to_string(),
+                    Timestamp::now(),
+                ),
+                |row| self.map_ban(row),
+            )
+            .optional()
+    }
+
+    pub fn set_silence(&self, silence: &Silence) -> SqlResult<()> {
+        debug!("Silencing {} in room {}", silence.user_id, silence.room_id);
+        self.conn.execute(
+            "INSERT INTO silences (room, user, until, actor) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(room, user) DO UPDATE SET until=excluded.until, actor=excluded.actor",
+            (
+                silence.room_id.id(),
+                silence.user_id.id(),
+                silence.until,
+                silence.actor.id(),
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Get the silence (that hasn't ended) of a user in a room, if there is one.
+    pub fn get_active_silence(
+        &self,
+        room_id: &super::room::Id,
+        user_id: &super::user::Id,
+    ) -> Result<Silence> {
+        trace!("Checking if {} is silenced in room {}", user_id, room_id);
+        self.conn
+            .query_row(
+                "SELECT * FROM silences
+                    WHERE room=?1 AND user=?2 AND (until IS NULL OR until > ?3)",
+                (room_id.id(), user_id.id(), Timestamp::now()),
+                |row| self.map_silence(row),
+            )
+            .optional()
+    }
+
+    fn map_ban(&self, row: &Row) -> SqlResult<Ban> {
+        Ok(Ban {
+            id: row.get(0)?,
+            room_id: row.get(1)?,
+            user_id: row.get(2)?,
+            ip: row
+                .get::<_, Option<String>>(3)?
+                .and_then(|ip| ip.parse().ok()),
+            until: row.get(4)?,
+            reason: row.get(5)?,
+            actor: row.get(6)?,
+        })
+    }
+
+    fn map_silence(&self, row: &Row) -> SqlResult<Silence> {
+        Ok(Silence {
+            room_id: row.get(0)?,
+            user_id: row.get(1)?,
+            until: row.get(2)?,
+            actor: row.get(3)?,
+        })
+    }
+}
+
+/// Moderation log stuff
+impl Database {
+    pub fn add_log_entry(&self, entry: &LogEntry) -> SqlResult<()> {
+        debug!(
+            "Logging {:?} by {:?} in room {}",
+            entry.action, entry.actor, entry.room_id
+        );
+        self.conn.execute(
+            "INSERT INTO mod_log (id, room, actor, target, action, reason)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                entry.id.id(),
+                entry.room_id.id(),
+                entry.actor.as_ref().map(|id| id.id()),
+                entry.target.as_ref().map(|id| id.id()),
+                entry.action,
+                entry.reason.as_deref(),
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Get the newest `amount` moderation log entries of a room (before `before`), newest first.
+    pub fn get_mod_log(
+        &self,
+        room_id: &super::room::Id,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> SqlResult<Vec<LogEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM mod_log
+                WHERE room=?1 AND (?2 IS NULL OR id < ?2)
+                ORDER BY id DESC LIMIT ?3",
+        )?;
+        let entries = stmt
+            .query_map((room_id.id(), before.map(|id| id.id()), amount), |row| {
+                self.map_log_entry(row)
+            })?
+            .collect::<SqlResult<Vec<_>>>();
+
+        entries
+    }
+
+    fn map_log_entry(&self, row: &Row) -> SqlResult<LogEntry> {
+        let id: Snowflake = row.get(0)?;
+        Ok(LogEntry {
+            created_at: id.created_at().into(),
+            id,
+            room_id: row.get(1)?,
+            actor: row.get(2)?,
+            target: row.get(3)?,
+            action: row.get(4)?,
+            reason: row.get(5)?,
+        })
+    }
+}
+
+/// Report stuff
+impl Database {
+    pub fn add_report(&self, report: &Report) -> SqlResult<()> {
+        debug!(
+            "Adding report {} of message {}",
+            report.id, report.message_id
+        );
+        self.conn.execute(
+            "INSERT INTO reports (id, room, message, reporter, reason, status, moderator)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            (
+                report.id.id(),
+                report.room_id.id(),
+                report.message_id.id(),
+                report.reporter.id(),
+                &report.reason,
+                report.status,
+                report.moderator.as_ref().map(|id| id.id()),
+            ),
+        )?;
+        Ok(())
+    }
+
+    pub fn get_report(&self, id: &report::Id) -> Result<Report> {
+        self.conn
+            .query_row("SELECT * FROM reports WHERE id=?1", (id.id(),), |row| {
+                self.map_report(row)
+            })
+            .optional()
+    }
+
+    /// Get the newest `amount` reports in a room (before `before`), newest first.
+    ///
+    /// If `status` is `None`, only the reports that are still pending are returned.
+    pub fn get_reports(
+        &self,
+        room_id: &super::room::Id,
+        status: Option<report::Status>,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> SqlResult<Vec<Report>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM reports
+                WHERE room=?1
+                    AND (status=?2 OR (?2 IS NULL AND status IN ('open', 'claimed')))
+                    AND (?3 IS NULL OR id < ?3)
+                ORDER BY id DESC LIMIT ?4",
+        )?;
+        let reports = stmt
+            .query_map(
+                (room_id.id(), status, before.map(|id| id.id()), amount),
+                |row| self.map_report(row),
+            )?
+            .collect::<SqlResult<Vec<_>>>();
+
+        reports
+    }
+
+    pub fn update_report_status(
+        &self,
+        id: &report::Id,
+        status: report::Status,
+        moderator: &super::user::Id,
+    ) -> SqlResult<()> {
+        debug!("Setting status of report {} to {:?}", id, status);
+        self.conn.execute(
+            "UPDATE reports SET status=?2, moderator=?3 WHERE id=?1",
+            (id.id(), status, moderator.id()),
+        )?;
+        Ok(())
+    }
+
+    fn map_report(&self, row: &Row) -> SqlResult<Report> {
+        let id: Snowflake = row.get(0)?;
+        Ok(Report {
+            created_at: id.created_at().into(),
+            id,
+            room_id: row.get(1)?,
+            message_id: row.get(2)?,
+            reporter: row.get(3)?,
+            reason: row.get(4)?,
+            status: row.get(5)?,
+            moderator: row.get(6)?,
+        })
+    }
+}
+
+/// Content filter stuff
+impl Database {
+    pub fn add_filter_rule(&self, rule: &filter::Rule) -> SqlResult<()> {
+        debug!("Adding filter rule {} to room {}", rule.id, rule.room_id);
+        self.conn.execute(
+            "INSERT INTO filter_rules (id, room, pattern, action) VALUES (?1, ?2, ?3, ?4)",
+            (rule.id.id(), rule.room_id.id(), &rule.pattern, rule.action),
+        )?;
+        Ok(())
+    }
+
+    /// Get the filter rules of a room, or of every room if `room_id` is `None`.
+    pub fn get_filter_rules(
+        &self,
+        room_id: Option<&super::room::Id>,
+    ) -> SqlResult<Vec<filter::Rule>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT * FROM filter_rules WHERE ?1 IS NULL OR room=?1 ORDER BY id")?;
+        let rules = stmt
+            .query_map((room_id.map(|id| id.id()),), |row| {
+                self.map_filter_rule(row)
+            })?
+            .collect::<SqlResult<Vec<_>>>();
+
+        rules
+    }
+
+    /// Remove a filter rule from a room.
+    ///
+    /// Returns whether the rule existed.
+    pub fn remove_filter_rule(
+        &self,
+        room_id: &super::room::Id,
+        id: &filter::Id,
+    ) -> SqlResult<bool> {
+        debug!("Removing filter rule {} from room {}", id, room_id);
+        let removed = self.conn.execute(
+            "DELETE FROM filter_rules WHERE id=?1 AND room=?2",
+            (id.id(), room_id.id()),
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn map_filter_rule(&self, row: &Row) -> SqlResult<filter::Rule> {
+        Ok(filter::Rule {
+            id: row.get(0)?,
+            room_id: row.get(1)?,
+            pattern: row.get(2)?,
+            action: row.get(3)?,
+        })
+    }
 }
 
 /// Session stuff
@@ -292,14 +1287,11 @@ impl Database {
     }
 
     pub fn get_session_from_token(&self, token: &super::session::Token) -> Result<Session> {
-        debug!("Getting session from token {}", token);
+        // The token is a secret, so it isn't logged
+        debug!("Getting session from token");
         self.conn
             .query_row("SELECT * FROM sessions WHERE token=?1", (token,), |row| {
-                Ok(Session {
-                    id: self.get_snowflake_column(row, 0),
-                    token: self.get_column(row, 1),
-                    user_id: self.get_snowflake_column(row, 2),
-                })
+                self.map_session(row)
             })
             .optional()
     }
@@ -310,41 +1302,12 @@ impl Database {
             .execute("DELETE FROM sessions WHERE id=?1", (id.id(),))?;
         Ok(())
     }
-}
-
-/// Helper methods
-impl Database {
-    /// Get a row from a query result.
-    /// It is just a wrapper around the [`rusqlite::Row::get()`] method,
-    /// but it panics if the value does not exist.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the value does not exist, or the type is incorrect.
-    fn get_column<T: FromSql>(&self, row: &rusqlite::Row, index: usize) -> T {
-        row.get::<usize, T>(index)
-            .expect(format!("value exists at row {}", index).as_str())
-    }
 
-    /// Get a row from a query result, and parse it as a snowflake.
-    /// It is just a wrapper around the [`Database::get_column()`] method, and the [`snowcloud::Snowflake::try_from()`] method.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the value does not exist, or the value is not a valid snowflake.
-    fn get_snowflake_column(&self, row: &rusqlite::Row, index: usize) -> super::Snowflake {
-        super::Snowflake::try_from(self.get_column::<i64>(row, index))
-            .expect("id from db is a valid snowflake")
-    }
-
-    /// Gets a row from a query result, and parse it as a snowflake.
-    /// It is just a wrapper around the [`Database::get_column()`] method, and the [`snowcloud::Snowflake::try_from()`] method.
-    fn get_snowflake_column_optional(
-        &self,
-        row: &rusqlite::Row,
-        index: usize,
-    ) -> Option<super::Snowflake> {
-        let Ok(Some(id)) = row.get::<usize, Option<i64>>(index) else { return None };
-        super::Snowflake::try_from(id).ok()
+    fn map_session(&self, row: &Row) -> SqlResult<Session> {
+        Ok(Session {
+            id: row.get(0)?,
+            token: row.get(1)?,
+            user_id: row.get(2)?,
+        })
     }
 }
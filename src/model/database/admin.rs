@@ -0,0 +1,97 @@
+use log::{debug, info};
+use rusqlite::Result as SqlResult;
+
+use super::Database;
+use crate::model::{room, user, Room};
+
+/// For the admin commands (`golem user`, `golem room`, ...), which change things that the server
+/// itself doesn't.
+impl Database {
+    /// Replace a user's password (hash).
+    ///
+    /// Returns whether the user exists.
+    pub fn update_password(&self, id: &user::Id, password: &str) -> SqlResult<bool> {
+        debug!("Updating password of user {}", id.id());
+        let updated = self.conn.execute(
+            "UPDATE users SET password=?1 WHERE id=?2",
+            (password, id.id()),
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Delete a user, with their sessions, roles, blocks, reactions and mentions.
+    ///
+    /// Their messages are kept (they have the author's name).
+    pub fn delete_user(&self, id: &user::Id) -> SqlResult<()> {
+        info!("Deleting user {}", id.id());
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM sessions WHERE user=?1", (id.id(),))?;
+        tx.execute("DELETE FROM room_members WHERE user=?1", (id.id(),))?;
+        tx.execute("DELETE FROM blocks WHERE user=?1 OR blocked=?1", (id.id(),))?;
+        tx.execute("DELETE FROM reactions WHERE user=?1", (id.id(),))?;
+        tx.execute("DELETE FROM mentions WHERE user=?1", (id.id(),))?;
+        tx.execute("DELETE FROM users WHERE id=?1", (id.id(),))?;
+        tx.commit()
+    }
+
+    /// Get every room (including unlisted, private and direct ones), by name.
+    pub fn get_all_rooms(&self) -> SqlResult<Vec<Room>> {
+        let mut stmt = self.conn.prepare("SELECT * FROM rooms ORDER BY name")?;
+        let rooms = stmt
+            .query_map((), |row| self.map_room(row))?
+            .collect::<SqlResult<Vec<_>>>();
+
+        rooms
+    }
+
+    /// Delete a room, with everything in it: messages, members, moderation, filters and invites.
+    pub fn delete_room(&self, id: &room::Id) -> SqlResult<()> {
+        info!("Deleting room {}", id.id());
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM reactions WHERE message IN (SELECT id FROM messages WHERE room=?1)",
+            (id.id(),),
+        )?;
+        tx.execute(
+            "DELETE FROM mentions WHERE message IN (SELECT id FROM messages WHERE room=?1)",
+            (id.id(),),
+        )?;
+        for table in [
+            "reports",
+            "messages",
+            "room_members",
+            "bans",
+            "silences",
+            "mod_log",
+            "filter_rules",
+            "invites",
+        ] {
+            tx.execute(&format!("DELETE FROM {} WHERE room=?1", table), (id.id(),))?;
+        }
+        tx.execute("DELETE FROM rooms WHERE id=?1", (id.id(),))?;
+        tx.commit()
+    }
+
+    /// Delete the sessions of a user, or everyone's if `user_id` is `None`, logging them out.
+    ///
+    /// Returns how many were deleted.
+    pub fn delete_sessions(&self, user_id: Option<&user::Id>) -> SqlResult<usize> {
+        match user_id {
+            Some(user_id) => {
+                debug!("Deleting sessions of user {}", user_id.id());
+                self.conn
+                    .execute("DELETE FROM sessions WHERE user=?1", (user_id.id(),))
+            }
+            None => {
+                debug!("Deleting every session");
+                self.conn.execute("DELETE FROM sessions", ())
+            }
+        }
+    }
+
+    /// Rebuild the database file, to reclaim the space of deleted rows.
+    pub fn vacuum(&self) -> SqlResult<()> {
+        info!("Vacuuming the database");
+        self.conn.execute_batch("VACUUM")
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+
+use log::{debug, warn};
+use rusqlite::{types::Type, Result as SqlResult, Row};
+
+use super::{migrations, Database};
+use crate::{
+    auth,
+    model::{Role, Snowflake},
+};
+
+/// A row that can't be read.
+#[derive(Debug)]
+pub struct Problem {
+    pub table: &'static str,
+    pub rowid: i64,
+    pub error: rusqlite::Error,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} row {}: {}", self.table, self.rowid, self.error)
+    }
+}
+
+/// What [`Database::check`] found.
+#[derive(Debug, Default)]
+pub struct Checked {
+    /// How many rows were read.
+    pub rows: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl Database {
+    /// Read every row of every table the way the server does, to find the ones that can't be
+    /// (like an invalid snowflake, an unknown role, or a password that isn't a valid hash).
+    pub fn check(&self) -> SqlResult<Checked> {
+        let version = migrations::version(&self.conn)?;
+        if version < migrations::latest_version() {
+            warn!(
+                "The database is at version {}, so newer tables may be missing (run --migrate-only)",
+                version
+            );
+        }
+
+        let mut checked = Checked::default();
+        self.check_table(&mut checked, "users", |row| {
+            let user = self.map_user(row)?;
+            if let Err(err) = auth::hash::parse_hash(&user.password) {
+                return Err(rusqlite::Error::FromSqlConversionFailure(
+                    2,
+                    Type::Text,
+                    err.to_string().into(),
+                ));
+            }
+            Ok(())
+        })?;
+        self.check_table(&mut checked, "sessions", |row| {
+            self.map_session(row).map(drop)
+        })?;
+        self.check_table(&mut checked, "blocks", |row| {
+            row.get::<_, Snowflake>(0)?;
+            row.get::<_, Snowflake>(1).map(drop)
+        })?;
+        self.check_table(&mut checked, "rooms", |row| self.map_room(row).map(drop))?;
+        self.check_table(&mut checked, "room_members", |row| {
+            row.get::<_, Snowflake>(0)?;
+            row.get::<_, Snowflake>(1)?;
+            row.get::<_, Role>(2).map(drop)
+        })?;
+        self.check_table(&mut checked, "messages", |row| {
+            self.map_message(row).map(drop)
+        })?;
+        self.check_table(&mut checked, "reactions", |row| {
+            row.get::<_, Snowflake>(0)?;
+            row.get::<_, Snowflake>(1)?;
+            row.get::<_, String>(2).map(drop)
+        })?;
+        self.check_table(&mut checked, "mentions", |row| {
+            row.get::<_, Snowflake>(0)?;
+            row.get::<_, Snowflake>(1)?;
+            row.get::<_, bool>(2).map(drop)
+        })?;
+        self.check_table(&mut checked, "invites", |row| {
+            self.map_invite(row).map(drop)
+        })?;
+        self.check_table(&mut checked, "bans", |row| self.map_ban(row).map(drop))?;
+        self.check_table(&mut checked, "silences", |row| {
+            self.map_silence(row).map(drop)
+        })?;
+        self.check_table(&mut checked, "mod_log", |row| {
+            self.map_log_entry(row).map(drop)
+        })?;
+        self.check_table(&mut checked, "reports", |row| {
+            self.map_report(row).map(drop)
+        })?;
+        self.check_table(&mut checked, "filter_rules", |row| {
+            self.map_filter_rule(row).map(drop)
+        })?;
+
+        Ok(checked)
+    }
+
+    /// Read every row of a table with `check`, recording the ones it fails on.
+    fn check_table(
+        &self,
+        checked: &mut Checked,
+        table: &'static str,
+        check: impl Fn(&Row) -> SqlResult<()>,
+    ) -> SqlResult<()> {
+        debug!("Checking table {}", table);
+
+        // The rowid is selected last, so the other columns are where the mapping expects them
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT *, rowid FROM {} ORDER BY rowid", table))?;
+        let rowid_index = stmt.column_count() - 1;
+
+        let mut rows = stmt.query(())?;
+        while let Some(row) = rows.next()? {
+            checked.rows += 1;
+            if let Err(error) = check(row) {
+                checked.problems.push(Problem {
+                    table,
+                    rowid: row.get(rowid_index)?,
+                    error,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
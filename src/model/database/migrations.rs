@@ -0,0 +1,322 @@
+use std::fmt;
+
+use log::{debug, info};
+use rusqlite::Connection;
+
+/// A change to the database's schema.
+///
+/// The version of the last migration that was run on a database is stored in its `user_version`.
+#[derive(Debug)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every migration, in order. New migrations are added to the end, and never changed once released.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "mentions",
+        sql: include_str!("migrations/0002_mentions.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "reactions",
+        sql: include_str!("migrations/0003_reactions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "search",
+        sql: include_str!("migrations/0004_search.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "message_rooms",
+        sql: include_str!("migrations/0005_message_rooms.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "room_members",
+        sql: include_str!("migrations/0006_room_members.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "bans_and_silences",
+        sql: include_str!("migrations/0007_bans_and_silences.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "mod_log",
+        sql: include_str!("migrations/0008_mod_log.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "reports",
+        sql: include_str!("migrations/0009_reports.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "filters",
+        sql: include_str!("migrations/0010_filters.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "invites",
+        sql: include_str!("migrations/0011_invites.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "blocks",
+        sql: include_str!("migrations/0012_blocks.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "main_room_owner",
+        sql: include_str!("migrations/0013_main_room_owner.sql"),
+    },
+];
+
+#[derive(Debug)]
+pub enum Error {
+    Sql(rusqlite::Error),
+    /// The database was migrated by a newer version of golem.
+    TooNew {
+        version: u32,
+        latest: u32,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Sql(err) => write!(f, "{}", err),
+            Error::TooNew { version, latest } => write!(
+                f,
+                "the database is at version {}, but this version of golem only knows up to {}",
+                version, latest
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Sql(err)
+    }
+}
+
+/// The version of the database's schema (the last migration that was run on it).
+pub fn version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
+
+/// The version of the newest migration, which a database is at once it is up to date.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Run the migrations that haven't been run on the database, in order, each in its own transaction.
+///
+/// If `dry_run`, they're run in one transaction that is rolled back, to check that they work
+/// without changing anything.
+///
+/// Returns the migrations that were (or would have been) run.
+pub fn run(conn: &mut Connection, dry_run: bool) -> Result<Vec<&'static Migration>, Error> {
+    let version = version(conn)?;
+    let latest = latest_version();
+    if version > latest {
+        return Err(Error::TooNew { version, latest });
+    }
+
+    let pending = MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version)
+        .collect::<Vec<_>>();
+    if pending.is_empty() {
+        debug!("Database is up to date (version {})", version);
+        return Ok(pending);
+    }
+
+    if dry_run {
+        let tx = conn.transaction()?;
+        for migration in &pending {
+            info!(
+                "Checking migration {:04} ({})",
+                migration.version, migration.name
+            );
+            tx.execute_batch(migration.sql)?;
+        }
+        tx.rollback()?;
+        return Ok(pending);
+    }
+
+    for migration in &pending {
+        info!(
+            "Running migration {:04} ({})",
+            migration.version, migration.name
+        );
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration.sql)?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+    }
+
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{database::Database, room::Visibility, Snowflake};
+
+    /// A database made before migrations were added: the original schema, with the main room,
+    /// a thread in it and a thread in another room.
+    fn original_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL, password TEXT NOT NULL);
+            CREATE TABLE messages (
+                id          INT PRIMARY KEY,
+                author      INT NOT NULL,
+                author_name TEXT NOT NULL,
+                parent      INT NOT NULL,
+                content     TEXT NOT NULL
+            );
+            CREATE TABLE sessions (
+                id      INT PRIMARY KEY,
+                token   INT NOT NULL,
+                user    INT NOT NULL,
+                FOREIGN KEY(user) REFERENCES users(id)
+            );
+            CREATE TABLE rooms (id INT PRIMARY KEY, name TEXT NOT NULL);
+
+            INSERT INTO users VALUES (1, 'alice', 'hash');
+            INSERT INTO rooms VALUES (0, 'main'), (100, 'other');
+            INSERT INTO messages VALUES
+                (10, 1, 'alice', 0, 'hello main'),
+                (11, 1, 'alice', 10, 'a reply'),
+                (12, 1, 'alice', 11, 'a reply to the reply'),
+                (20, 1, 'alice', 100, 'hello other'),
+                (21, 1, 'alice', 20, 'another reply');",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn id(id: i64) -> Snowflake {
+        Snowflake::try_from(id).unwrap()
+    }
+
+    #[test]
+    fn migrates_the_original_schema() {
+        let mut conn = original_database();
+
+        let ran = run(&mut conn, false).unwrap();
+        assert_eq!(ran.len(), MIGRATIONS.len());
+        assert_eq!(version(&conn).unwrap(), latest_version());
+
+        let db = Database { conn };
+        let room = db.get_room(&id(100)).unwrap().unwrap();
+        assert_eq!(room.name, "other");
+        assert!(!room.anonymous_reactions);
+        assert_eq!(room.visibility, Visibility::Public);
+
+        let message = db.get_message(&id(12)).unwrap().unwrap();
+        assert_eq!(message.content, "a reply to the reply");
+        assert!(!message.deleted);
+        assert!(!message.held);
+        assert!(message.reactions.is_empty());
+    }
+
+    #[test]
+    fn backfills_the_rooms_of_messages() {
+        let mut conn = original_database();
+        run(&mut conn, false).unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT id, room FROM messages ORDER BY id")
+            .unwrap();
+        let rooms = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(i64, i64)>>>()
+            .unwrap();
+        assert_eq!(rooms, [(10, 0), (11, 0), (12, 0), (20, 100), (21, 100)]);
+    }
+
+    #[test]
+    fn indexes_existing_messages() {
+        let mut conn = original_database();
+        run(&mut conn, false).unwrap();
+
+        let found: i64 = conn
+            .query_row(
+                "SELECT rowid FROM messages_fts WHERE messages_fts MATCH 'another'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(found, 21);
+    }
+
+    #[test]
+    fn makes_the_first_user_own_the_main_room() {
+        let mut conn = original_database();
+        conn.execute("INSERT INTO users VALUES (2, 'bob', 'hash')", ())
+            .unwrap();
+        run(&mut conn, false).unwrap();
+
+        let db = Database { conn };
+        assert_eq!(db.get_owners(&id(0)).unwrap(), [id(1)]);
+        assert!(db.get_owners(&id(100)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fresh_database_is_migrated_once() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        assert_eq!(run(&mut conn, false).unwrap().len(), MIGRATIONS.len());
+        assert!(run(&mut conn, false).unwrap().is_empty());
+        assert_eq!(version(&conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let mut conn = original_database();
+
+        assert_eq!(run(&mut conn, true).unwrap().len(), MIGRATIONS.len());
+        assert_eq!(version(&conn).unwrap(), 0);
+        let columns: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM pragma_table_info('messages')",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(columns, 5);
+    }
+
+    #[test]
+    fn refuses_newer_databases() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+
+        assert!(matches!(run(&mut conn, false), Err(Error::TooNew { .. })));
+    }
+
+    #[test]
+    fn versions_are_in_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version as usize, i + 1);
+        }
+    }
+}
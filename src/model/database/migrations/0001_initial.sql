@@ -0,0 +1,28 @@
+-- The schema from before migrations were added.
+-- Tables are only created if they don't exist, so databases from then are adopted as version 1.
+
+CREATE TABLE IF NOT EXISTS users (
+    id   INT PRIMARY KEY,
+    name TEXT NOT NULL,
+    password TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS messages (
+    id          INT PRIMARY KEY,
+    author      INT NOT NULL,
+    author_name TEXT NOT NULL,
+    parent      INT NOT NULL,
+    content     TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    id      INT PRIMARY KEY,
+    token   INT NOT NULL,
+    user    INT NOT NULL,
+    FOREIGN KEY(user) REFERENCES users(id)
+);
+
+CREATE TABLE IF NOT EXISTS rooms (
+    id   INT PRIMARY KEY,
+    name TEXT NOT NULL
+);
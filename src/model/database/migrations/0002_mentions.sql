@@ -0,0 +1,8 @@
+CREATE TABLE mentions (
+    message INT NOT NULL,
+    user    INT NOT NULL,
+    read    INT NOT NULL DEFAULT 0,
+    PRIMARY KEY(message, user),
+    FOREIGN KEY(message) REFERENCES messages(id),
+    FOREIGN KEY(user) REFERENCES users(id)
+);
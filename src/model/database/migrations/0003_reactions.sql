@@ -0,0 +1,9 @@
+CREATE TABLE reactions (
+    message INT NOT NULL,
+    user    INT NOT NULL,
+    emoji   TEXT NOT NULL,
+    PRIMARY KEY(message, user, emoji),
+    FOREIGN KEY(message) REFERENCES messages(id)
+);
+
+ALTER TABLE rooms ADD COLUMN anonymous_reactions INT NOT NULL DEFAULT 0;
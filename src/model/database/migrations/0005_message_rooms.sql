@@ -0,0 +1,19 @@
+ALTER TABLE messages ADD COLUMN room INT NOT NULL DEFAULT 0;
+
+-- A message's parent is either another message or (if it's top-level) its room,
+-- so the room of each existing message is found by walking up its parents.
+-- Before rooms were stored, every message was in the main room (0).
+WITH RECURSIVE ancestors (message, ancestor) AS (
+    SELECT id, parent FROM messages
+    UNION
+    SELECT ancestors.message, messages.parent FROM ancestors
+        JOIN messages ON messages.id = ancestors.ancestor
+)
+UPDATE messages SET room = coalesce(
+    (
+        SELECT ancestor FROM ancestors
+            WHERE ancestors.message = messages.id AND ancestor IN (SELECT id FROM rooms)
+            LIMIT 1
+    ),
+    0
+);
@@ -0,0 +1,10 @@
+CREATE TABLE room_members (
+    room INT NOT NULL,
+    user INT NOT NULL,
+    role TEXT NOT NULL,
+    PRIMARY KEY(room, user),
+    FOREIGN KEY(room) REFERENCES rooms(id),
+    FOREIGN KEY(user) REFERENCES users(id)
+);
+
+ALTER TABLE messages ADD COLUMN deleted INT NOT NULL DEFAULT 0;
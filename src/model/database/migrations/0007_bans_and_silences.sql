@@ -0,0 +1,19 @@
+CREATE TABLE bans (
+    id     INT PRIMARY KEY,
+    room   INT NOT NULL,
+    user   INT NOT NULL,
+    ip     TEXT,
+    until  INT,
+    reason TEXT,
+    actor  INT NOT NULL,
+    FOREIGN KEY(room) REFERENCES rooms(id)
+);
+
+CREATE TABLE silences (
+    room  INT NOT NULL,
+    user  INT NOT NULL,
+    until INT,
+    actor INT NOT NULL,
+    PRIMARY KEY(room, user),
+    FOREIGN KEY(room) REFERENCES rooms(id)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE mod_log (
+    id     INTEGER PRIMARY KEY,
+    room   INT NOT NULL,
+    actor  INT,
+    target INT,
+    action TEXT NOT NULL,
+    reason TEXT,
+    FOREIGN KEY(room) REFERENCES rooms(id)
+);
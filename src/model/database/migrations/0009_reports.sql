@@ -0,0 +1,11 @@
+CREATE TABLE reports (
+    id        INTEGER PRIMARY KEY,
+    room      INT NOT NULL,
+    message   INT NOT NULL,
+    reporter  INT NOT NULL,
+    reason    TEXT NOT NULL,
+    status    TEXT NOT NULL,
+    moderator INT,
+    FOREIGN KEY(room) REFERENCES rooms(id),
+    FOREIGN KEY(message) REFERENCES messages(id)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE filter_rules (
+    id      INTEGER PRIMARY KEY,
+    room    INT NOT NULL,
+    pattern TEXT NOT NULL,
+    action  TEXT NOT NULL,
+    FOREIGN KEY(room) REFERENCES rooms(id)
+);
+
+ALTER TABLE messages ADD COLUMN held INT NOT NULL DEFAULT 0;
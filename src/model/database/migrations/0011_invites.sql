@@ -0,0 +1,11 @@
+CREATE TABLE invites (
+    token      TEXT PRIMARY KEY,
+    room       INT NOT NULL,
+    creator    INT NOT NULL,
+    expires_at INT,
+    max_uses   INT,
+    uses       INT NOT NULL DEFAULT 0,
+    FOREIGN KEY(room) REFERENCES rooms(id)
+);
+
+ALTER TABLE rooms ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public';
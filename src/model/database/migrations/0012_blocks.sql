@@ -0,0 +1,7 @@
+CREATE TABLE blocks (
+    user    INT NOT NULL,
+    blocked INT NOT NULL,
+    PRIMARY KEY(user, blocked),
+    FOREIGN KEY(user) REFERENCES users(id),
+    FOREIGN KEY(blocked) REFERENCES users(id)
+);
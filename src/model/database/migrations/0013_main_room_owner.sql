@@ -0,0 +1,6 @@
+-- The main room was made without an owner, so its first user becomes one (if it has none)
+INSERT INTO room_members (room, user, role)
+    SELECT 0, id, 'owner' FROM users
+        WHERE NOT EXISTS (SELECT 1 FROM room_members WHERE room=0 AND role='owner')
+        ORDER BY id LIMIT 1
+    ON CONFLICT(room, user) DO UPDATE SET role='owner';
@@ -0,0 +1,744 @@
+use std::{
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use log::debug;
+use rusqlite::Result as SqlResult;
+use tokio::sync::{Mutex as AsyncMutex, Semaphore};
+
+use super::Database;
+use crate::{
+    logger, metrics,
+    model::{
+        filter,
+        invite::Invite,
+        message,
+        moderation::{Ban, LogEntry, Silence},
+        report::{self, Report},
+        room,
+        search::{SearchQuery, SearchResult},
+        session,
+        store::{Error, Result, Store, StoreResult},
+        user, Mention, Message, Reaction, Role, Room, Session, Snowflake, User,
+    },
+};
+
+/// The connections to the database: one that writes, and some that only read.
+///
+/// The database is in WAL mode, so reads don't wait for writes (or each other).
+/// Queries are run with [`tokio::task::spawn_blocking`], so they don't block the executor.
+///
+/// It is the SQLite [`Store`], running each of its methods on a connection.
+pub struct Pool {
+    writer: Arc<AsyncMutex<Database>>,
+    readers: Arc<Mutex<Vec<Database>>>,
+    /// A permit for each reader that isn't being used.
+    available: Arc<Semaphore>,
+}
+
+impl Pool {
+    /// Use a (built) database as the writer, and open `readers` read only connections to the same file.
+    pub fn new(writer: Database, path: &Path, readers: usize) -> SqlResult<Pool> {
+        debug!("Opening {} database readers", readers);
+        let readers = (0..readers)
+            .map(|_| Database::open_reader(path))
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        Ok(Pool {
+            writer: Arc::new(AsyncMutex::new(writer)),
+            available: Arc::new(Semaphore::new(readers.len())),
+            readers: Arc::new(Mutex::new(readers)),
+        })
+    }
+
+    /// Run queries that only read from the database, on one of the readers.
+    /// They're timed in the metrics as `method`.
+    async fn read<T, F>(&self, method: &'static str, f: F) -> StoreResult<T>
+    where
+        F: FnOnce(&Database) -> SqlResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let _timer = metrics::time_query(method);
+        let permit = self
+            .available
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("database reader semaphore isn't closed");
+        let readers = self.readers.clone();
+
+        run(move || {
+            let reader = Reader::take(readers);
+            let result = f(reader.database());
+            drop(reader);
+            drop(permit);
+            result
+        })
+        .await
+        .map_err(Error::from)
+    }
+
+    /// Run queries that write to the database, on the writer.
+    /// They're timed in the metrics as `method`.
+    async fn write<T, F>(&self, method: &'static str, f: F) -> StoreResult<T>
+    where
+        F: FnOnce(&Database) -> SqlResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let _timer = metrics::time_query(method);
+        let writer = self.writer.clone().lock_owned().await;
+        run(move || f(&writer)).await.map_err(Error::from)
+    }
+}
+
+/// Run a closure on the blocking thread pool (in the current span), passing on its panics.
+async fn run<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let span = logger::current_span();
+    let f = move || match span {
+        Some(span) => logger::in_span_blocking(span, f),
+        None => f(),
+    };
+
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// A reader that was taken from the pool. It is put back when dropped, even if a query panics.
+struct Reader {
+    database: Option<Database>,
+    readers: Arc<Mutex<Vec<Database>>>,
+}
+
+impl Reader {
+    /// Take a reader. There must be one available (i.e. a permit must be held).
+    fn take(readers: Arc<Mutex<Vec<Database>>>) -> Reader {
+        let database = readers
+            .lock()
+            .expect("database readers lock isn't poisoned")
+            .pop()
+            .expect("there is a reader for each permit");
+        Reader {
+            database: Some(database),
+            readers,
+        }
+    }
+
+    fn database(&self) -> &Database {
+        self.database.as_ref().expect("reader hasn't been put back")
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        if let Some(database) = self.database.take() {
+            if let Ok(mut readers) = self.readers.lock() {
+                readers.push(database);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Store for Pool {
+    // User stuff
+    async fn add_user(&self, user: User) -> StoreResult<()> {
+        self.write("add_user", move |db| db.add_user(user)).await
+    }
+
+    async fn get_user(&self, id: &user::Id) -> Result<User> {
+        let id = id.clone();
+        self.read("get_user", move |db| db.get_user(&id)).await
+    }
+
+    async fn get_user_by_name(&self, name: &str) -> Result<User> {
+        let name = name.to_string();
+        self.read("get_user_by_name", move |db| db.get_user_by_name(&name))
+            .await
+    }
+
+    async fn get_user_name(&self, id: &user::Id) -> Result<String> {
+        let id = id.clone();
+        self.read("get_user_name", move |db| db.get_user_name(&id))
+            .await
+    }
+
+    async fn count_users(&self) -> StoreResult<u64> {
+        self.read("count_users", |db| db.count_users()).await
+    }
+
+    // Block stuff
+    async fn add_block(&self, user_id: &user::Id, blocked: &user::Id) -> StoreResult<()> {
+        let user_id = user_id.clone();
+        let blocked = blocked.clone();
+        self.write("add_block", move |db| db.add_block(&user_id, &blocked))
+            .await
+    }
+
+    async fn remove_block(&self, user_id: &user::Id, blocked: &user::Id) -> StoreResult<bool> {
+        let user_id = user_id.clone();
+        let blocked = blocked.clone();
+        self.write("remove_block", move |db| {
+            db.remove_block(&user_id, &blocked)
+        })
+        .await
+    }
+
+    async fn get_blocked_users(&self, user_id: &user::Id) -> StoreResult<Vec<user::Id>> {
+        let user_id = user_id.clone();
+        self.read("get_blocked_users", move |db| {
+            db.get_blocked_users(&user_id)
+        })
+        .await
+    }
+
+    async fn get_blocks(&self) -> StoreResult<Vec<(user::Id, user::Id)>> {
+        self.read("get_blocks", move |db| db.get_blocks()).await
+    }
+
+    // Messages stuff
+    async fn get_recent_messages(&self, room_id: &room::Id) -> StoreResult<Vec<Message>> {
+        let room_id = room_id.clone();
+        self.read("get_recent_messages", move |db| {
+            db.get_recent_messages(&room_id)
+        })
+        .await
+    }
+
+    async fn get_some_messages(
+        &self,
+        room_id: &room::Id,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> StoreResult<Vec<Message>> {
+        let room_id = room_id.clone();
+        self.read("get_some_messages", move |db| {
+            db.get_some_messages(&room_id, before, amount)
+        })
+        .await
+    }
+
+    async fn get_room_messages(&self, room_id: &room::Id) -> StoreResult<Vec<Message>> {
+        let room_id = room_id.clone();
+        self.read("get_room_messages", move |db| {
+            db.get_room_messages(&room_id)
+        })
+        .await
+    }
+
+    async fn get_children_of(
+        &self,
+        room_id: &room::Id,
+        parent: Option<&Snowflake>,
+    ) -> StoreResult<Vec<Message>> {
+        let room_id = room_id.clone();
+        let parent = parent.cloned();
+        self.read("get_children_of", move |db| {
+            db.get_children_of(&room_id, parent.as_ref())
+        })
+        .await
+    }
+
+    async fn get_message(&self, id: &message::Id) -> Result<Message> {
+        let id = id.clone();
+        self.read("get_message", move |db| db.get_message(&id))
+            .await
+    }
+
+    async fn add_message(&self, message: &Message) -> StoreResult<()> {
+        let message = message.clone();
+        self.write("add_message", move |db| db.add_message(&message))
+            .await
+    }
+
+    async fn get_messages_between(
+        &self,
+        room_id: &room::Id,
+        start: SystemTime,
+        end: SystemTime,
+        limit: u8,
+    ) -> StoreResult<Vec<Message>> {
+        let room_id = room_id.clone();
+        self.read("get_messages_between", move |db| {
+            db.get_messages_between(&room_id, start, end, limit)
+        })
+        .await
+    }
+
+    async fn search_messages(
+        &self,
+        room_id: &room::Id,
+        query: &SearchQuery,
+    ) -> StoreResult<Vec<SearchResult>> {
+        let room_id = room_id.clone();
+        let query = query.clone();
+        self.read("search_messages", move |db| {
+            db.search_messages(&room_id, &query)
+        })
+        .await
+    }
+
+    async fn update_message_content(&self, id: &message::Id, content: &str) -> StoreResult<()> {
+        let id = id.clone();
+        let content = content.to_string();
+        self.write("update_message_content", move |db| {
+            db.update_message_content(&id, &content)
+        })
+        .await
+    }
+
+    async fn delete_message(&self, id: &message::Id) -> StoreResult<()> {
+        let id = id.clone();
+        self.write("delete_message", move |db| db.delete_message(&id))
+            .await
+    }
+
+    async fn get_held_messages(&self, room_id: &room::Id) -> StoreResult<Vec<Message>> {
+        let room_id = room_id.clone();
+        self.read("get_held_messages", move |db| {
+            db.get_held_messages(&room_id)
+        })
+        .await
+    }
+
+    async fn approve_message(&self, id: &message::Id) -> StoreResult<()> {
+        let id = id.clone();
+        self.write("approve_message", move |db| db.approve_message(&id))
+            .await
+    }
+
+    // Reaction stuff
+    async fn add_reaction(
+        &self,
+        message_id: &message::Id,
+        user_id: &user::Id,
+        emoji: &str,
+    ) -> StoreResult<bool> {
+        let message_id = message_id.clone();
+        let user_id = user_id.clone();
+        let emoji = emoji.to_string();
+        self.write("add_reaction", move |db| {
+            db.add_reaction(&message_id, &user_id, &emoji)
+        })
+        .await
+    }
+
+    async fn remove_reaction(
+        &self,
+        message_id: &message::Id,
+        user_id: &user::Id,
+        emoji: &str,
+    ) -> StoreResult<bool> {
+        let message_id = message_id.clone();
+        let user_id = user_id.clone();
+        let emoji = emoji.to_string();
+        self.write("remove_reaction", move |db| {
+            db.remove_reaction(&message_id, &user_id, &emoji)
+        })
+        .await
+    }
+
+    async fn get_reactions(&self, message_id: &message::Id) -> StoreResult<Vec<Reaction>> {
+        let message_id = message_id.clone();
+        self.read("get_reactions", move |db| db.get_reactions(&message_id))
+            .await
+    }
+
+    // Mention stuff
+    async fn add_mention(&self, message: &Message, user_id: &user::Id) -> StoreResult<()> {
+        let message = message.clone();
+        let user_id = user_id.clone();
+        self.write("add_mention", move |db| db.add_mention(&message, &user_id))
+            .await
+    }
+
+    async fn remove_mention(
+        &self,
+        message_id: &message::Id,
+        user_id: &user::Id,
+    ) -> StoreResult<()> {
+        let message_id = message_id.clone();
+        let user_id = user_id.clone();
+        self.write("remove_mention", move |db| {
+            db.remove_mention(&message_id, &user_id)
+        })
+        .await
+    }
+
+    async fn get_unread_mentions(&self, user_id: &user::Id) -> StoreResult<Vec<Mention>> {
+        let user_id = user_id.clone();
+        self.read("get_unread_mentions", move |db| {
+            db.get_unread_mentions(&user_id)
+        })
+        .await
+    }
+
+    async fn mark_mentions_read(&self, user_id: &user::Id, up_to: &message::Id) -> StoreResult<()> {
+        let user_id = user_id.clone();
+        let up_to = up_to.clone();
+        self.write("mark_mentions_read", move |db| {
+            db.mark_mentions_read(&user_id, &up_to)
+        })
+        .await
+    }
+
+    // Room stuff
+    async fn add_room(&self, room: &Room) -> StoreResult<()> {
+        let room = room.clone();
+        self.write("add_room", move |db| db.add_room(&room)).await
+    }
+
+    async fn get_room(&self, id: &room::Id) -> Result<Room> {
+        let id = id.clone();
+        self.read("get_room", move |db| db.get_room(&id)).await
+    }
+
+    async fn get_room_by_name(&self, name: &str) -> Result<Room> {
+        let name = name.to_string();
+        self.read("get_room_by_name", move |db| db.get_room_by_name(&name))
+            .await
+    }
+
+    async fn update_room_settings(
+        &self,
+        id: &room::Id,
+        settings: &room::Settings,
+    ) -> StoreResult<()> {
+        let id = id.clone();
+        let settings = settings.clone();
+        self.write("update_room_settings", move |db| {
+            db.update_room_settings(&id, &settings)
+        })
+        .await
+    }
+
+    async fn get_public_rooms(&self) -> StoreResult<Vec<Room>> {
+        self.read("get_public_rooms", move |db| db.get_public_rooms())
+            .await
+    }
+
+    async fn get_direct_rooms(&self, user_id: &user::Id) -> StoreResult<Vec<Room>> {
+        let user_id = user_id.clone();
+        self.read("get_direct_rooms", move |db| db.get_direct_rooms(&user_id))
+            .await
+    }
+
+    async fn can_access_room(&self, room: &Room, user_id: Option<&user::Id>) -> StoreResult<bool> {
+        let room = room.clone();
+        let user_id = user_id.cloned();
+        self.read("can_access_room", move |db| {
+            db.can_access_room(&room, user_id.as_ref())
+        })
+        .await
+    }
+
+    // Room member stuff
+    async fn get_role(&self, room_id: &room::Id, user_id: Option<&user::Id>) -> StoreResult<Role> {
+        let room_id = room_id.clone();
+        let user_id = user_id.cloned();
+        self.read("get_role", move |db| {
+            db.get_role(&room_id, user_id.as_ref())
+        })
+        .await
+    }
+
+    async fn set_role(
+        &self,
+        room_id: &room::Id,
+        user_id: &user::Id,
+        role: Role,
+    ) -> StoreResult<()> {
+        let room_id = room_id.clone();
+        let user_id = user_id.clone();
+        self.write("set_role", move |db| db.set_role(&room_id, &user_id, role))
+            .await
+    }
+
+    async fn is_member(&self, room_id: &room::Id, user_id: &user::Id) -> StoreResult<bool> {
+        let room_id = room_id.clone();
+        let user_id = user_id.clone();
+        self.read("is_member", move |db| db.is_member(&room_id, &user_id))
+            .await
+    }
+
+    async fn get_members(&self, room_id: &room::Id) -> StoreResult<Vec<user::Id>> {
+        let room_id = room_id.clone();
+        self.read("get_members", move |db| db.get_members(&room_id))
+            .await
+    }
+
+    async fn get_owners(&self, room_id: &room::Id) -> StoreResult<Vec<user::Id>> {
+        let room_id = room_id.clone();
+        self.read("get_owners", move |db| db.get_owners(&room_id))
+            .await
+    }
+
+    async fn add_member(&self, room_id: &room::Id, user_id: &user::Id) -> StoreResult<()> {
+        let room_id = room_id.clone();
+        let user_id = user_id.clone();
+        self.write("add_member", move |db| db.add_member(&room_id, &user_id))
+            .await
+    }
+
+    // Invite stuff
+    async fn add_invite(&self, invite: &Invite) -> StoreResult<()> {
+        let invite = invite.clone();
+        self.write("add_invite", move |db| db.add_invite(&invite))
+            .await
+    }
+
+    async fn get_invite(&self, token: &str) -> Result<Invite> {
+        let token = token.to_string();
+        self.read("get_invite", move |db| db.get_invite(&token))
+            .await
+    }
+
+    async fn get_invites(&self, room_id: &room::Id) -> StoreResult<Vec<Invite>> {
+        let room_id = room_id.clone();
+        self.read("get_invites", move |db| db.get_invites(&room_id))
+            .await
+    }
+
+    async fn use_invite(&self, token: &str) -> StoreResult<bool> {
+        let token = token.to_string();
+        self.write("use_invite", move |db| db.use_invite(&token))
+            .await
+    }
+
+    async fn delete_invite(&self, room_id: &room::Id, token: &str) -> StoreResult<bool> {
+        let room_id = room_id.clone();
+        let token = token.to_string();
+        self.write("delete_invite", move |db| {
+            db.delete_invite(&room_id, &token)
+        })
+        .await
+    }
+
+    // Moderation stuff
+    async fn add_ban(&self, ban: &Ban) -> StoreResult<()> {
+        let ban = ban.clone();
+        self.write("add_ban", move |db| db.add_ban(&ban)).await
+    }
+
+    async fn get_active_ban(
+        &self,
+        room_id: &room::Id,
+        user_id: Option<&user::Id>,
+        ip: IpAddr,
+    ) -> Result<Ban> {
+        let room_id = room_id.clone();
+        let user_id = user_id.cloned();
+        self.read("get_active_ban", move |db| {
+            db.get_active_ban(&room_id, user_id.as_ref(), ip)
+        })
+        .await
+    }
+
+    async fn set_silence(&self, silence: &Silence) -> StoreResult<()> {
+        let silence = silence.clone();
+        self.write("set_silence", move |db| db.set_silence(&silence))
+            .await
+    }
+
+    async fn get_active_silence(&self, room_id: &room::Id, user_id: &user::Id) -> Result<Silence> {
+        let room_id = room_id.clone();
+        let user_id = user_id.clone();
+        self.read("get_active_silence", move |db| {
+            db.get_active_silence(&room_id, &user_id)
+        })
+        .await
+    }
+
+    // Moderation log stuff
+    async fn add_log_entry(&self, entry: &LogEntry) -> StoreResult<()> {
+        let entry = entry.clone();
+        self.write("add_log_entry", move |db| db.add_log_entry(&entry))
+            .await
+    }
+
+    async fn get_mod_log(
+        &self,
+        room_id: &room::Id,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> StoreResult<Vec<LogEntry>> {
+        let room_id = room_id.clone();
+        self.read("get_mod_log", move |db| {
+            db.get_mod_log(&room_id, before, amount)
+        })
+        .await
+    }
+
+    // Report stuff
+    async fn add_report(&self, report: &Report) -> StoreResult<()> {
+        let report = report.clone();
+        self.write("add_report", move |db| db.add_report(&report))
+            .await
+    }
+
+    async fn get_report(&self, id: &report::Id) -> Result<Report> {
+        let id = id.clone();
+        self.read("get_report", move |db| db.get_report(&id)).await
+    }
+
+    async fn get_reports(
+        &self,
+        room_id: &room::Id,
+        status: Option<report::Status>,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> StoreResult<Vec<Report>> {
+        let room_id = room_id.clone();
+        self.read("get_reports", move |db| {
+            db.get_reports(&room_id, status, before, amount)
+        })
+        .await
+    }
+
+    async fn update_report_status(
+        &self,
+        id: &report::Id,
+        status: report::Status,
+        moderator: &user::Id,
+    ) -> StoreResult<()> {
+        let id = id.clone();
+        let moderator = moderator.clone();
+        self.write("update_report_status", move |db| {
+            db.update_report_status(&id, status, &moderator)
+        })
+        .await
+    }
+
+    // Content filter stuff
+    async fn add_filter_rule(&self, rule: &filter::Rule) -> StoreResult<()> {
+        let rule = rule.clone();
+        self.write("add_filter_rule", move |db| db.add_filter_rule(&rule))
+            .await
+    }
+
+    async fn get_filter_rules(&self, room_id: Option<&room::Id>) -> StoreResult<Vec<filter::Rule>> {
+        let room_id = room_id.cloned();
+        self.read("get_filter_rules", move |db| {
+            db.get_filter_rules(room_id.as_ref())
+        })
+        .await
+    }
+
+    async fn remove_filter_rule(&self, room_id: &room::Id, id: &filter::Id) -> StoreResult<bool> {
+        let room_id = room_id.clone();
+        let id = id.clone();
+        self.write("remove_filter_rule", move |db| {
+            db.remove_filter_rule(&room_id, &id)
+        })
+        .await
+    }
+
+    // Session stuff
+    async fn add_session(&self, session: Session) -> StoreResult<()> {
+        self.write("add_session", move |db| db.add_session(session))
+            .await
+    }
+
+    async fn get_session_from_token(&self, token: &session::Token) -> Result<Session> {
+        let token = *token;
+        self.read("get_session_from_token", move |db| {
+            db.get_session_from_token(&token)
+        })
+        .await
+    }
+
+    async fn delete_session(&self, id: &session::Id) -> StoreResult<()> {
+        let id = id.clone();
+        self.write("delete_session", move |db| db.delete_session(&id))
+            .await
+    }
+
+    async fn schema_version(&self) -> StoreResult<Option<u32>> {
+        self.read("schema_version", move |db| db.schema_version().map(Some))
+            .await
+    }
+
+    async fn close(&self) -> StoreResult<()> {
+        // Writes hold the writer until they're done, so this waits for them
+        self.write("checkpoint", move |db| db.checkpoint()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::model::database::testing::TempDatabase;
+
+    /// How long a test waits for a query, so that a missing reader fails it instead of hanging.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn pool(temp: &TempDatabase, readers: usize) -> Pool {
+        let database = Database::build(temp.path()).unwrap();
+        Pool::new(database, temp.path(), readers).unwrap()
+    }
+
+    fn user(id: i64) -> User {
+        User {
+            id: Snowflake::try_from(id).unwrap(),
+            name: format!("user {}", id),
+            password: "hash".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn readers_see_writes() {
+        let temp = TempDatabase::new();
+        let pool = pool(&temp, 2);
+
+        pool.add_user(user(1)).await.unwrap();
+        let found = pool.get_user(&user(1).id).await.unwrap().unwrap();
+        assert_eq!(found.name, "user 1");
+    }
+
+    #[tokio::test]
+    async fn reads_wait_for_a_reader() {
+        let temp = TempDatabase::new();
+        let pool = pool(&temp, 1);
+        let id = user(1).id;
+        pool.add_user(user(1)).await.unwrap();
+
+        let reads = (0..10).map(|_| pool.get_user_name(&id));
+        let names = tokio::time::timeout(TIMEOUT, futures::future::join_all(reads))
+            .await
+            .unwrap();
+        assert!(names
+            .into_iter()
+            .all(|name| name.unwrap().as_deref() == Some("user 1")));
+    }
+
+    #[tokio::test]
+    async fn readers_are_put_back_after_a_panic() {
+        let temp = TempDatabase::new();
+        let pool = Arc::new(pool(&temp, 1));
+
+        let panicking = pool.clone();
+        let result = tokio::spawn(async move {
+            panicking
+                .read("panic", |_| -> SqlResult<()> { panic!("query panicked") })
+                .await
+        })
+        .await;
+        assert!(result.unwrap_err().is_panic());
+
+        let version = tokio::time::timeout(TIMEOUT, pool.schema_version())
+            .await
+            .unwrap();
+        assert!(version.unwrap().is_some());
+    }
+}
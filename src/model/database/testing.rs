@@ -0,0 +1,25 @@
+//! Helpers for the tests that use a database file.
+
+use std::path::{Path, PathBuf};
+
+/// A database file (in the temporary directory) that is deleted when dropped.
+pub struct TempDatabase(PathBuf);
+
+impl TempDatabase {
+    pub fn new() -> TempDatabase {
+        let name = format!("golem-test-{:016x}.sqlite3", rand::random::<u64>());
+        TempDatabase(std::env::temp_dir().join(name))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.0.display(), suffix));
+        }
+    }
+}
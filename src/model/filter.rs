@@ -0,0 +1,59 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+
+use super::{room, Snowflake};
+
+pub type Id = Snowflake;
+
+/// The longest pattern a blocklist rule can have.
+pub const MAX_PATTERN_LEN: usize = 500;
+
+/// A regex in a room's blocklist, and what happens to messages that match it.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Rule {
+    pub id: Id,
+    pub room_id: room::Id,
+    pub pattern: String,
+    pub action: Action,
+}
+
+/// What happens to a message that a content filter matches.
+///
+/// Actions are ordered, so if several match, the highest one wins.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub enum Action {
+    /// Replace the matching text with `*`s.
+    Mask,
+    /// Store the message, but only show it once a moderator approves it.
+    Hold,
+    /// Don't send the message.
+    Reject,
+}
+
+impl Action {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Mask => "mask",
+            Action::Hold => "hold",
+            Action::Reject => "reject",
+        }
+    }
+}
+
+impl ToSql for Action {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Action {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "mask" => Ok(Action::Mask),
+            "hold" => Ok(Action::Hold),
+            "reject" => Ok(Action::Reject),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
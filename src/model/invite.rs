@@ -0,0 +1,16 @@
+use super::{room, user, Timestamp};
+
+pub type Token = String;
+
+/// A link that lets users join a private room.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Invite {
+    pub token: Token,
+    pub room_id: room::Id,
+    pub creator: user::Id,
+    /// When the invite stops working. If `None`, it doesn't.
+    pub expires_at: Option<Timestamp>,
+    /// How many times the invite can be used. If `None`, there is no limit.
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+/// Limits on what clients can send.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// The most characters (after normalization) that a message's content can have.
+    pub max_message_chars: usize,
+    /// The biggest websocket frame, in bytes, that a client can send.
+    pub max_ws_frame_bytes: usize,
+    /// How often messages can be posted (and reacted to).
+    pub post_rate: Rate,
+    /// How often a presence can change its name.
+    pub name_change_rate: Rate,
+    /// How often message history can be loaded (or searched).
+    pub load_rate: Rate,
+    /// How often a connection (and an address) can try to authenticate.
+    pub auth_rate: Rate,
+    /// How often a connection can be rate limited before it is disconnected.
+    pub strike_rate: Rate,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_message_chars: 4000,
+            max_ws_frame_bytes: 64 * 1024,
+            post_rate: Rate::new(5, Duration::from_secs(1)),
+            name_change_rate: Rate::new(3, Duration::from_secs(30)),
+            load_rate: Rate::new(10, Duration::from_secs(2)),
+            auth_rate: Rate::new(5, Duration::from_secs(10)),
+            strike_rate: Rate::new(10, Duration::from_secs(10)),
+        }
+    }
+}
+
+/// The rate of a token bucket.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    /// How many actions can be done at once.
+    pub burst: u32,
+    /// How long it takes to be able to do one more action, e.g. `"1s"` or `"500ms"`.
+    #[serde(with = "humantime_duration")]
+    pub refill: Duration,
+}
+
+impl Rate {
+    pub const fn new(burst: u32, refill: Duration) -> Rate {
+        Rate { burst, refill }
+    }
+}
+
+/// (De)serialize a duration as a human readable string, e.g. `"1m 30s"`.
+pub(crate) mod humantime_duration {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let duration = String::deserialize(deserializer)?;
+        humantime::parse_duration(&duration).map_err(serde::de::Error::custom)
+    }
+}
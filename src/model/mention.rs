@@ -0,0 +1,52 @@
+use super::{user, Message};
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Mention {
+    pub user_id: user::Id,
+    pub message: Message,
+}
+
+/// Get the names that are mentioned (with `@name`) in a message's content.
+///
+/// Trailing punctuation isn't counted as part of the name, so `@gavin, hi` mentions `gavin`.
+/// Each name is only returned once, even if it is mentioned multiple times.
+pub fn parse_names(content: &str) -> Vec<&str> {
+    let mut names: Vec<&str> = content
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| {
+            name.trim_end_matches(|c: char| c.is_ascii_punctuation() && c != '_' && c != '-')
+        })
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_parsed() {
+        assert_eq!(parse_names("@gavin, hi"), ["gavin"]);
+        assert_eq!(parse_names("hi @alice and @bob!"), ["alice", "bob"]);
+        assert_eq!(
+            parse_names("@snake_case @kebab-case"),
+            ["kebab-case", "snake_case"]
+        );
+    }
+
+    #[test]
+    fn names_are_only_returned_once() {
+        assert_eq!(parse_names("@bob @alice @bob. @bob"), ["alice", "bob"]);
+    }
+
+    #[test]
+    fn other_words_are_not_mentions() {
+        assert!(parse_names("").is_empty());
+        assert!(parse_names("@ @! email@example.com").is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use super::{user, Snowflake};
+use super::{room, user, Reaction, Snowflake, Timestamp};
 
 pub type Id = Snowflake;
 
@@ -9,7 +9,21 @@ pub struct Message {
     pub author_name: String,
     pub parent: Id, /* The way that Golem expresses a top level message is
                      * by making the parent of said message the room id. */
+    pub room: room::Id,
     pub content: String,
+    /// Deleted messages are kept (with empty content), so their replies still have a parent.
+    #[serde(default)]
+    pub deleted: bool,
+    /// Held messages were caught by a content filter, and are only shown once a moderator approves them.
+    #[serde(default)]
+    pub held: bool,
+    /// The author is blocked by the user that loaded the message, so its content is hidden.
+    #[serde(default)]
+    pub blocked: bool,
+    /// When the message was sent (derived from the id).
+    pub created_at: Timestamp,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
 }
 
 impl PartialEq for Message {
@@ -20,7 +34,7 @@ impl PartialEq for Message {
 
 impl PartialOrd for Message {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.id.id().partial_cmp(&other.id.id())
+        Some(self.cmp(other))
     }
 }
 
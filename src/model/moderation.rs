@@ -0,0 +1,121 @@
+use std::net::IpAddr;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+
+use super::{room, user, Snowflake, Timestamp};
+
+pub type BanId = Snowflake;
+
+/// A user (or anonymous presence) that can't join a room.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Ban {
+    pub id: BanId,
+    pub room_id: room::Id,
+    /// The banned user, or the presence id of an anonymous user.
+    pub user_id: user::Id,
+    /// The address of an anonymous user, so they can't rejoin as a new presence.
+    #[serde(skip)]
+    pub ip: Option<IpAddr>,
+    /// When the ban ends. If `None`, it never does.
+    pub until: Option<Timestamp>,
+    pub reason: Option<String>,
+    pub actor: user::Id,
+}
+
+/// A user (or anonymous presence) that can't post in a room.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Silence {
+    pub room_id: room::Id,
+    pub user_id: user::Id,
+    /// When the silence ends. If `None`, it never does.
+    pub until: Option<Timestamp>,
+    pub actor: user::Id,
+}
+
+/// Something a moderator did, as recorded in the moderation log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub enum ModAction {
+    /// Deleted someone else's message.
+    DeleteMessage,
+    Kick,
+    Ban,
+    Silence,
+    ChangeRole,
+    ChangeSettings,
+    ResolveReport,
+    DismissReport,
+    /// Approved a message that was held by a content filter.
+    ApproveMessage,
+    AddFilterRule,
+    RemoveFilterRule,
+    /// A content filter masked part of a message.
+    FilterMasked,
+    /// A content filter held a message for review.
+    FilterHeld,
+    /// A content filter rejected a message.
+    FilterRejected,
+}
+
+impl ModAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ModAction::DeleteMessage => "delete_message",
+            ModAction::Kick => "kick",
+            ModAction::Ban => "ban",
+            ModAction::Silence => "silence",
+            ModAction::ChangeRole => "change_role",
+            ModAction::ChangeSettings => "change_settings",
+            ModAction::ResolveReport => "resolve_report",
+            ModAction::DismissReport => "dismiss_report",
+            ModAction::ApproveMessage => "approve_message",
+            ModAction::AddFilterRule => "add_filter_rule",
+            ModAction::RemoveFilterRule => "remove_filter_rule",
+            ModAction::FilterMasked => "filter_masked",
+            ModAction::FilterHeld => "filter_held",
+            ModAction::FilterRejected => "filter_rejected",
+        }
+    }
+}
+
+impl ToSql for ModAction {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for ModAction {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "delete_message" => Ok(ModAction::DeleteMessage),
+            "kick" => Ok(ModAction::Kick),
+            "ban" => Ok(ModAction::Ban),
+            "silence" => Ok(ModAction::Silence),
+            "change_role" => Ok(ModAction::ChangeRole),
+            "change_settings" => Ok(ModAction::ChangeSettings),
+            "resolve_report" => Ok(ModAction::ResolveReport),
+            "dismiss_report" => Ok(ModAction::DismissReport),
+            "approve_message" => Ok(ModAction::ApproveMessage),
+            "add_filter_rule" => Ok(ModAction::AddFilterRule),
+            "remove_filter_rule" => Ok(ModAction::RemoveFilterRule),
+            "filter_masked" => Ok(ModAction::FilterMasked),
+            "filter_held" => Ok(ModAction::FilterHeld),
+            "filter_rejected" => Ok(ModAction::FilterRejected),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// An entry in a room's moderation log.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct LogEntry {
+    pub id: Snowflake,
+    pub room_id: room::Id,
+    /// Who did it. If `None`, it was done automatically (e.g. by a content filter).
+    pub actor: Option<user::Id>,
+    /// What the action was done to: a message, presence, user, report or filter rule id
+    /// (or nothing, for room settings).
+    pub target: Option<Snowflake>,
+    pub action: ModAction,
+    pub reason: Option<String>,
+    pub created_at: Timestamp,
+}
@@ -0,0 +1,132 @@
+use super::message;
+
+/// An aggregated reaction on a message.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: u32,
+}
+
+/// The most characters that a single reaction can have.
+///
+/// This leaves room for multi-codepoint emoji (like flags and families).
+pub const MAX_EMOJI_LEN: usize = 16;
+
+/// Joins emoji into one (like the people in a family).
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+/// Asks for a character to be shown as an emoji, rather than as text.
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+const COMBINING_KEYCAP: char = '\u{20E3}';
+
+/// Whether a reaction is a single emoji, so that reactions can't be used to post text.
+///
+/// Sequences that are shown as one emoji count: flags, keycaps, skin tones and
+/// [ZWJ sequences](https://unicode.org/reports/tr51/#Emoji_ZWJ_Sequences) (like families).
+pub fn is_emoji(emoji: &str) -> bool {
+    let chars = emoji.chars().collect::<Vec<_>>();
+    if chars.len() > MAX_EMOJI_LEN {
+        return false;
+    }
+
+    match chars.as_slice() {
+        // Flags are pairs of regional indicators
+        [a, b] if is_regional_indicator(*a) && is_regional_indicator(*b) => true,
+        [key, EMOJI_PRESENTATION, COMBINING_KEYCAP] | [key, COMBINING_KEYCAP] => {
+            key.is_ascii_digit() || *key == '#' || *key == '*'
+        }
+        _ => {
+            !chars.is_empty()
+                && chars
+                    .split(|c| *c == ZERO_WIDTH_JOINER)
+                    .all(is_single_emoji)
+        }
+    }
+}
+
+/// A pictograph, which may be followed by a variation selector, skin tone or tags
+/// (for subdivision flags, like England's).
+fn is_single_emoji(chars: &[char]) -> bool {
+    let Some((base, modifiers)) = chars.split_first() else {
+        return false;
+    };
+    is_pictograph(*base)
+        && modifiers
+            .iter()
+            .all(|c| *c == EMOJI_PRESENTATION || is_skin_tone(*c) || is_tag(*c))
+}
+
+fn is_pictograph(c: char) -> bool {
+    matches!(c,
+        '\u{1F000}'..='\u{1FAFF}'
+        | '\u{2300}'..='\u{23FF}'
+        | '\u{2600}'..='\u{27BF}'
+        | '\u{2B00}'..='\u{2BFF}'
+        | '\u{2190}'..='\u{21FF}'
+        | '\u{25A0}'..='\u{25FF}'
+        | '\u{2934}' | '\u{2935}'
+        | '\u{00A9}' | '\u{00AE}' | '\u{203C}' | '\u{2049}' | '\u{2122}' | '\u{2139}'
+        | '\u{24C2}' | '\u{3030}' | '\u{303D}' | '\u{3297}' | '\u{3299}')
+        && !is_regional_indicator(c)
+        && !is_skin_tone(c)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+fn is_skin_tone(c: char) -> bool {
+    ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+fn is_tag(c: char) -> bool {
+    ('\u{E0020}'..='\u{E007F}').contains(&c)
+}
+
+/// The reactions of a message changed.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ReactionsChanged {
+    pub message_id: message::Id,
+    pub reactions: Vec<Reaction>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_emoji_are_allowed() {
+        for emoji in [
+            "👍",
+            "❤️",
+            "👍🏽",
+            "🇳🇿",
+            "1️⃣",
+            "#⃣",
+            "👨‍👩‍👧‍👦",
+            "🏳️‍🌈",
+            "🏴\u{E0067}\u{E0062}\u{E0065}\u{E006E}\u{E0067}\u{E007F}",
+            "©️",
+        ] {
+            assert!(is_emoji(emoji), "{:?}", emoji);
+        }
+    }
+
+    #[test]
+    fn text_is_not_an_emoji() {
+        for emoji in [
+            "",
+            "a",
+            "lol",
+            "1",
+            "👍👍",
+            "👍 ",
+            "🇳",
+            "🇳🇿🇳🇿",
+            "\u{200D}👍",
+            "👍\u{200D}",
+            "🏽",
+        ] {
+            assert!(!is_emoji(emoji), "{:?}", emoji);
+        }
+    }
+}
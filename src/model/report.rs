@@ -0,0 +1,65 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+
+use super::{message, room, user, Snowflake, Timestamp};
+
+pub type Id = Snowflake;
+
+/// The most characters a report's reason can have.
+pub const MAX_REASON_CHARS: usize = 500;
+
+/// A message that was flagged for the room's moderators.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Report {
+    pub id: Id,
+    pub room_id: room::Id,
+    pub message_id: message::Id,
+    pub reporter: user::Id,
+    pub reason: String,
+    pub status: Status,
+    /// The moderator that claimed (or closed) the report.
+    pub moderator: Option<user::Id>,
+    pub created_at: Timestamp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Status {
+    Open,
+    /// A moderator is looking into it.
+    Claimed,
+    Resolved,
+    Dismissed,
+}
+
+impl Status {
+    /// Whether a moderator still has to deal with the report.
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Status::Open | Status::Claimed)
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Open => "open",
+            Status::Claimed => "claimed",
+            Status::Resolved => "resolved",
+            Status::Dismissed => "dismissed",
+        }
+    }
+}
+
+impl ToSql for Status {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Status {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "open" => Ok(Status::Open),
+            "claimed" => Ok(Status::Claimed),
+            "resolved" => Ok(Status::Resolved),
+            "dismissed" => Ok(Status::Dismissed),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+
+/// A user's role in a room.
+///
+/// Roles are ordered, so a higher role has (at least) all the permissions of a lower one.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+pub enum Role {
+    Guest,
+    Member,
+    Moderator,
+    Owner,
+}
+
+/// Something that a role may be allowed to do in a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub enum Permission {
+    Post,
+    EditOwn,
+    DeleteOwn,
+    EditOthers,
+    DeleteOthers,
+    Kick,
+    Ban,
+    Silence,
+    HandleReports,
+    Invite,
+    ChangeSettings,
+    ChangeRoles,
+}
+
+impl Role {
+    /// The role of a registered user in a room when they haven't been given one.
+    pub const DEFAULT_USER: Role = Role::Member;
+    /// The role of an anonymous presence.
+    pub const ANONYMOUS: Role = Role::Guest;
+
+    pub fn can(&self, permission: Permission) -> bool {
+        use Permission::*;
+
+        let required = match permission {
+            Post => Role::Guest,
+            EditOwn | DeleteOwn => Role::Member,
+            DeleteOthers | Kick | Ban | Silence | HandleReports | Invite => Role::Moderator,
+            EditOthers | ChangeSettings | ChangeRoles => Role::Owner,
+        };
+        *self >= required
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Role::Guest => "guest",
+            Role::Member => "member",
+            Role::Moderator => "moderator",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Role {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "guest" => Ok(Role::Guest),
+            "member" => Ok(Role::Member),
+            "moderator" => Ok(Role::Moderator),
+            "owner" => Ok(Role::Owner),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission::*, *};
+
+    #[test]
+    fn roles_have_the_permissions_of_lower_ones() {
+        let permissions = [
+            Post,
+            EditOwn,
+            DeleteOwn,
+            EditOthers,
+            DeleteOthers,
+            Kick,
+            Ban,
+            Silence,
+            HandleReports,
+            Invite,
+            ChangeSettings,
+            ChangeRoles,
+        ];
+        let roles = [Role::Guest, Role::Member, Role::Moderator, Role::Owner];
+        for permission in permissions {
+            for pair in roles.windows(2) {
+                if pair[0].can(permission) {
+                    assert!(pair[1].can(permission), "{:?} {:?}", pair[1], permission);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn permissions() {
+        assert!(Role::Guest.can(Post));
+        assert!(!Role::Guest.can(EditOwn));
+        assert!(Role::Member.can(DeleteOwn));
+        assert!(!Role::Member.can(DeleteOthers));
+        assert!(Role::Moderator.can(Ban));
+        assert!(Role::Moderator.can(HandleReports));
+        assert!(!Role::Moderator.can(EditOthers));
+        assert!(!Role::Moderator.can(ChangeRoles));
+        assert!(Role::Owner.can(ChangeRoles));
+        assert!(Role::ANONYMOUS < Role::DEFAULT_USER);
+    }
+}
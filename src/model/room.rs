@@ -1,6 +1,88 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+
 pub type Id = super::Snowflake;
 
+/// The id of the room that is created when the database is built.
+pub const MAIN_ID: i64 = 0;
+
+/// The id of the room that is created when the database is built.
+pub fn main_id() -> Id {
+    Id::try_from(MAIN_ID).expect("main room id is a valid snowflake")
+}
+
+/// The most users a direct conversation can have, including the one that starts it.
+pub const MAX_DIRECT_MEMBERS: usize = 8;
+
+/// Direct conversations are named after their id, with this prefix.
+/// Other rooms can't have names that start with it.
+pub const DIRECT_NAME_PREFIX: &str = "dm-";
+
+#[derive(Clone, Debug, serde::Serialize)]
 pub struct Room {
     pub id: Id,
     pub name: String,
+    /// Whether anonymous (unauthenticated) presences can react to messages.
+    pub anonymous_reactions: bool,
+    pub visibility: Visibility,
+}
+
+/// A direct conversation, and the users in it.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct DirectRoom {
+    #[serde(flatten)]
+    pub room: Room,
+    pub members: Vec<super::user::Id>,
+}
+
+/// Who can find and join a room.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Visibility {
+    /// Anyone can join, and the room is listed.
+    #[default]
+    Public,
+    /// Anyone who knows the room's name or id can join, but the room isn't listed.
+    Unlisted,
+    /// Only members (users with a role in the room) can join. Users become members with an invite.
+    Private,
+    /// A direct conversation between a few users. Only they can join, and nobody can be added
+    /// (or removed) later.
+    Direct,
+}
+
+impl Visibility {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Private => "private",
+            Visibility::Direct => "direct",
+        }
+    }
+}
+
+impl ToSql for Visibility {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Visibility {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "public" => Ok(Visibility::Public),
+            "unlisted" => Ok(Visibility::Unlisted),
+            "private" => Ok(Visibility::Private),
+            "direct" => Ok(Visibility::Direct),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// The settings of a room that can be changed.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Settings {
+    pub anonymous_reactions: bool,
+    /// If not given, the visibility isn't changed.
+    #[serde(default)]
+    pub visibility: Option<Visibility>,
 }
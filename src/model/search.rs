@@ -0,0 +1,113 @@
+use super::{message, user, Message, Snowflake};
+
+/// The default amount of results for a search.
+pub const DEFAULT_LIMIT: u8 = 25;
+
+/// Marks the start of a match in a snippet (before the snippet is escaped).
+pub(super) const MATCH_START: char = '\u{E000}';
+/// Marks the end of a match in a snippet (before the snippet is escaped).
+pub(super) const MATCH_END: char = '\u{E001}';
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    #[serde(default)]
+    pub author: Option<user::Id>,
+    /// Only find messages before this snowflake.
+    #[serde(default)]
+    pub before: Option<Snowflake>,
+    /// Only find messages after this snowflake.
+    #[serde(default)]
+    pub after: Option<Snowflake>,
+    #[serde(default)]
+    pub limit: Option<u8>,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct SearchResult {
+    pub message: Message,
+    /// The matching part of the message, HTML escaped, with matches wrapped in `<mark>`.
+    pub snippet: String,
+    /// The ancestors of the message, starting with the top level message.
+    pub path: Vec<message::Id>,
+}
+
+/// Convert a user's search into an FTS5 query.
+///
+/// Each word is quoted so that FTS5 syntax (like `AND` or `"`) is searched for literally.
+/// A trailing `*` on a word is kept, to allow prefix searches.
+///
+/// Returns `None` if there's nothing to search for.
+pub fn to_fts_query(q: &str) -> Option<String> {
+    let terms: Vec<String> = q
+        .split_whitespace()
+        .filter_map(|word| {
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(word) => (word, "*"),
+                None => (word, ""),
+            };
+            if word.is_empty() {
+                return None;
+            }
+            Some(format!("\"{}\"{}", word.replace('"', "\"\""), prefix))
+        })
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// HTML escape a snippet from FTS5, and turn the match markers into `<mark>` tags.
+pub(super) fn escape_snippet(snippet: &str) -> String {
+    let mut escaped = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            MATCH_START => escaped.push_str("<mark>"),
+            MATCH_END => escaped.push_str("</mark>"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_quoted() {
+        assert_eq!(to_fts_query("hello world").unwrap(), r#""hello" "world""#);
+        assert_eq!(
+            to_fts_query(r#"a AND "b" NEAR(c)"#).unwrap(),
+            r#""a" "AND" """b""" "NEAR(c)""#
+        );
+    }
+
+    #[test]
+    fn prefixes_are_kept() {
+        assert_eq!(to_fts_query("hel* wor*ld").unwrap(), r#""hel"* "wor*ld""#);
+    }
+
+    #[test]
+    fn nothing_to_search_for() {
+        assert_eq!(to_fts_query(""), None);
+        assert_eq!(to_fts_query("  * \t*"), None);
+    }
+
+    #[test]
+    fn snippets_are_escaped() {
+        let snippet = format!("<b>&\"'</b> {}match{}", MATCH_START, MATCH_END);
+        assert_eq!(
+            escape_snippet(&snippet),
+            "&lt;b&gt;&amp;&quot;&#39;&lt;/b&gt; <mark>match</mark>"
+        );
+    }
+}
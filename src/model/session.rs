@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::auth;
 
 use super::Snowflake;
@@ -5,7 +7,7 @@ use super::Snowflake;
 pub type Id = Snowflake;
 pub type Token = i64;
 
-#[derive(Clone, Debug, serde::Serialize)]
+#[derive(Clone, serde::Serialize)]
 pub struct Session {
     pub id: Id,
     #[serde(skip)] // Don't expose token to client
@@ -13,6 +15,17 @@ pub struct Session {
     pub user_id: super::user::Id,
 }
 
+// The token is a secret, so it's left out (e.g. of logs)
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("id", &self.id)
+            .field("token", &"<redacted>")
+            .field("user_id", &self.user_id)
+            .finish()
+    }
+}
+
 impl Session {
     pub fn new(id: Id, token: Token, user_id: super::user::Id) -> Session {
         Session { id, token, user_id }
@@ -1,8 +1,10 @@
 use std::{
     fmt::{Display, Formatter},
     str::FromStr,
+    time::{Duration, SystemTime},
 };
 
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
 use serde::de::Error;
 
 type InnerSnowflake = snowcloud::Snowflake<43, 8, 12>;
@@ -14,6 +16,30 @@ impl Snowflake {
     pub fn id(&self) -> i64 {
         self.0.id()
     }
+
+    /// When the snowflake was generated, from its timestamp bits.
+    pub fn created_at(&self) -> SystemTime {
+        epoch() + Duration::from_millis(*self.0.timestamp() as u64)
+    }
+
+    /// The first snowflake that could have been generated at the given time.
+    ///
+    /// Since snowflakes are ordered by time, this can be used as a bound for time-range queries.
+    /// Times before the epoch are clamped to the epoch.
+    pub fn first_at(time: SystemTime) -> Snowflake {
+        let millis = time
+            .duration_since(epoch())
+            .unwrap_or_default()
+            .as_millis()
+            .min(InnerSnowflake::MAX_TIMESTAMP as u128) as i64;
+
+        Snowflake(InnerSnowflake::from_parts(millis, 0, 0).expect("timestamp is clamped to range"))
+    }
+}
+
+/// The time that snowflake timestamps are relative to.
+fn epoch() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_millis(crate::EPOCH)
 }
 
 impl TryFrom<i64> for Snowflake {
@@ -30,6 +56,12 @@ impl From<InnerSnowflake> for Snowflake {
     }
 }
 
+impl FromSql for Snowflake {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Snowflake::try_from(value.as_i64()?).map_err(|err| FromSqlError::Other(Box::new(err)))
+    }
+}
+
 impl FromStr for Snowflake {
     type Err = Box<dyn std::error::Error>;
 
@@ -54,7 +86,7 @@ impl serde::Serialize for Snowflake {
 impl<'de> serde::Deserialize<'de> for Snowflake {
     fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let num = String::deserialize(deserializer)?;
-        Ok(Snowflake::from_str(&num).map_err(D::Error::custom)?)
+        Snowflake::from_str(&num).map_err(D::Error::custom)
     }
 
     fn deserialize_in_place<D>(deserializer: D, place: &mut Self) -> Result<(), D::Error>
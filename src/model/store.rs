@@ -0,0 +1,596 @@
+use std::{fmt, net::IpAddr, sync::Arc, time::SystemTime};
+
+use async_trait::async_trait;
+
+use super::{
+    database::{migrations, Database, Pool},
+    filter,
+    invite::Invite,
+    message,
+    moderation::{Ban, LogEntry, Silence},
+    report::{self, Report},
+    room,
+    search::{SearchQuery, SearchResult},
+    session, user, Mention, Message, Reaction, Role, Room, Session, Snowflake, User,
+};
+use crate::config::Config;
+
+mod memory;
+
+pub use memory::MemoryStore;
+
+pub type StoreResult<T> = std::result::Result<T, Error>;
+pub type Result<T> = StoreResult<Option<T>>;
+
+/// Where the server's data (users, sessions, rooms, messages, ...) is kept.
+///
+/// Handlers only use this trait, so the backend can be changed with the `store` option.
+#[async_trait]
+pub trait Store: Send + Sync {
+    // User stuff
+    async fn add_user(&self, user: User) -> StoreResult<()>;
+
+    async fn get_user(&self, id: &user::Id) -> Result<User>;
+
+    async fn get_user_by_name(&self, name: &str) -> Result<User>;
+
+    async fn get_user_name(&self, id: &user::Id) -> Result<String>;
+
+    /// How many users have registered.
+    async fn count_users(&self) -> StoreResult<u64>;
+
+    // Block stuff
+    /// Block a user. Blocking a user twice does nothing.
+    async fn add_block(&self, user_id: &user::Id, blocked: &user::Id) -> StoreResult<()>;
+
+    /// Unblock a user. Returns whether they were blocked.
+    async fn remove_block(&self, user_id: &user::Id, blocked: &user::Id) -> StoreResult<bool>;
+
+    /// Get the users that a user has blocked.
+    async fn get_blocked_users(&self, user_id: &user::Id) -> StoreResult<Vec<user::Id>>;
+
+    /// Get every block, as (user, blocked user) pairs.
+    async fn get_blocks(&self) -> StoreResult<Vec<(user::Id, user::Id)>>;
+
+    // Messages stuff
+    async fn get_recent_messages(&self, room_id: &room::Id) -> StoreResult<Vec<Message>>;
+
+    /// Get the `amount` messages before the given message.
+    /// If `before` is `None`, get the `amount` most recent messages.
+    ///
+    /// This will get at *least* `amount` (*top level*) messages, but may get more.
+    ///
+    /// This will only get top level messages.
+    async fn get_some_messages(
+        &self,
+        room_id: &room::Id,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> StoreResult<Vec<Message>>;
+
+    /// Get every message in a room, oldest first.
+    async fn get_room_messages(&self, room_id: &room::Id) -> StoreResult<Vec<Message>>;
+
+    /// Get every reply to a message (and their replies, and so on) that is in the room.
+    async fn get_children_of(
+        &self,
+        room_id: &room::Id,
+        parent: Option<&Snowflake>,
+    ) -> StoreResult<Vec<Message>>;
+
+    async fn get_message(&self, id: &message::Id) -> Result<Message>;
+
+    async fn add_message(&self, message: &Message) -> StoreResult<()>;
+
+    /// Get the first `limit` messages in a room that were sent in the given time range
+    /// (from `start`, up to but not including `end`), oldest first.
+    async fn get_messages_between(
+        &self,
+        room_id: &room::Id,
+        start: SystemTime,
+        end: SystemTime,
+        limit: u8,
+    ) -> StoreResult<Vec<Message>>;
+
+    /// Search the messages in a room, best matches first.
+    async fn search_messages(
+        &self,
+        room_id: &room::Id,
+        query: &SearchQuery,
+    ) -> StoreResult<Vec<SearchResult>>;
+
+    async fn update_message_content(&self, id: &message::Id, content: &str) -> StoreResult<()>;
+
+    /// Delete a message.
+    ///
+    /// The message is kept (with empty content) so that its replies still have a parent,
+    /// but its reactions and mentions are removed.
+    async fn delete_message(&self, id: &message::Id) -> StoreResult<()>;
+
+    /// Get the messages in a room that are held for review, oldest first.
+    async fn get_held_messages(&self, room_id: &room::Id) -> StoreResult<Vec<Message>>;
+
+    /// Show a message that was held for review.
+    async fn approve_message(&self, id: &message::Id) -> StoreResult<()>;
+
+    // Reaction stuff
+    /// Add a reaction to a message.
+    ///
+    /// Returns whether the reaction was added (i.e. it didn't already exist).
+    async fn add_reaction(
+        &self,
+        message_id: &message::Id,
+        user_id: &user::Id,
+        emoji: &str,
+    ) -> StoreResult<bool>;
+
+    /// Remove a reaction from a message.
+    ///
+    /// Returns whether the reaction was removed (i.e. it existed).
+    async fn remove_reaction(
+        &self,
+        message_id: &message::Id,
+        user_id: &user::Id,
+        emoji: &str,
+    ) -> StoreResult<bool>;
+
+    /// Get the reactions on a message, aggregated by emoji.
+    /// They are in the order that each emoji was first used.
+    async fn get_reactions(&self, message_id: &message::Id) -> StoreResult<Vec<Reaction>>;
+
+    // Mention stuff
+    async fn add_mention(&self, message: &Message, user_id: &user::Id) -> StoreResult<()>;
+
+    /// Remove the mention of a user from a message (e.g. because it was edited out).
+    async fn remove_mention(&self, message_id: &message::Id, user_id: &user::Id)
+        -> StoreResult<()>;
+
+    /// Get all of the mentions of a user that haven't been read yet, newest first.
+    async fn get_unread_mentions(&self, user_id: &user::Id) -> StoreResult<Vec<Mention>>;
+
+    /// Mark all of a user's mentions up to (and including) the given message as read.
+    async fn mark_mentions_read(&self, user_id: &user::Id, up_to: &message::Id) -> StoreResult<()>;
+
+    // Room stuff
+    async fn add_room(&self, room: &Room) -> StoreResult<()>;
+
+    async fn get_room(&self, id: &room::Id) -> Result<Room>;
+
+    async fn get_room_by_name(&self, name: &str) -> Result<Room>;
+
+    async fn update_room_settings(
+        &self,
+        id: &room::Id,
+        settings: &room::Settings,
+    ) -> StoreResult<()>;
+
+    /// Get the rooms that are listed (i.e. public), by name.
+    async fn get_public_rooms(&self) -> StoreResult<Vec<Room>>;
+
+    /// Get the direct conversations that a user is in, newest first.
+    async fn get_direct_rooms(&self, user_id: &user::Id) -> StoreResult<Vec<Room>>;
+
+    /// Whether a user (or an anonymous presence, if `None`) can see and join a room.
+    async fn can_access_room(&self, room: &Room, user_id: Option<&user::Id>) -> StoreResult<bool>;
+
+    // Room member stuff
+    /// Get the role of a user in a room.
+    /// If there is no user (i.e. they're anonymous), or they haven't been given a role,
+    /// the default role is returned.
+    async fn get_role(&self, room_id: &room::Id, user_id: Option<&user::Id>) -> StoreResult<Role>;
+
+    async fn set_role(&self, room_id: &room::Id, user_id: &user::Id, role: Role)
+        -> StoreResult<()>;
+
+    /// Whether a user has been given a role in a room (e.g. by accepting an invite).
+    async fn is_member(&self, room_id: &room::Id, user_id: &user::Id) -> StoreResult<bool>;
+
+    /// Get the users that have been given a role in a room.
+    async fn get_members(&self, room_id: &room::Id) -> StoreResult<Vec<user::Id>>;
+
+    /// Get the owners of a room.
+    async fn get_owners(&self, room_id: &room::Id) -> StoreResult<Vec<user::Id>>;
+
+    /// Make a user a member of a room, unless they already have a role in it.
+    async fn add_member(&self, room_id: &room::Id, user_id: &user::Id) -> StoreResult<()>;
+
+    // Invite stuff
+    async fn add_invite(&self, invite: &Invite) -> StoreResult<()>;
+
+    async fn get_invite(&self, token: &str) -> Result<Invite>;
+
+    async fn get_invites(&self, room_id: &room::Id) -> StoreResult<Vec<Invite>>;
+
+    /// Use an invite, if it hasn't expired or run out of uses.
+    ///
+    /// Returns whether it was used.
+    async fn use_invite(&self, token: &str) -> StoreResult<bool>;
+
+    /// Remove an invite from a room.
+    ///
+    /// Returns whether the invite existed.
+    async fn delete_invite(&self, room_id: &room::Id, token: &str) -> StoreResult<bool>;
+
+    // Moderation stuff
+    async fn add_ban(&self, ban: &Ban) -> StoreResult<()>;
+
+    /// Get a ban (that hasn't ended) of the user or address from a room, if there is one.
+    ///
+    /// Addresses are only checked for anonymous users (when `user_id` is `None`),
+    /// so registered users aren't banned for sharing an address.
+    async fn get_active_ban(
+        &self,
+        room_id: &room::Id,
+        user_id: Option<&user::Id>,
+        ip: IpAddr,
+    ) -> Result<Ban>;
+
+    async fn set_silence(&self, silence: &Silence) -> StoreResult<()>;
+
+    /// Get the silence (that hasn't ended) of a user in a room, if there is one.
+    async fn get_active_silence(&self, room_id: &room::Id, user_id: &user::Id) -> Result<Silence>;
+
+    // Moderation log stuff
+    async fn add_log_entry(&self, entry: &LogEntry) -> StoreResult<()>;
+
+    /// Get the newest `amount` moderation log entries of a room (before `before`), newest first.
+    async fn get_mod_log(
+        &self,
+        room_id: &room::Id,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> StoreResult<Vec<LogEntry>>;
+
+    // Report stuff
+    async fn add_report(&self, report: &Report) -> StoreResult<()>;
+
+    async fn get_report(&self, id: &report::Id) -> Result<Report>;
+
+    /// Get the newest `amount` reports in a room (before `before`), newest first.
+    ///
+    /// If `status` is `None`, only the reports that are still pending are returned.
+    async fn get_reports(
+        &self,
+        room_id: &room::Id,
+        status: Option<report::Status>,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> StoreResult<Vec<Report>>;
+
+    async fn update_report_status(
+        &self,
+        id: &report::Id,
+        status: report::Status,
+        moderator: &user::Id,
+    ) -> StoreResult<()>;
+
+    // Content filter stuff
+    async fn add_filter_rule(&self, rule: &filter::Rule) -> StoreResult<()>;
+
+    /// Get the filter rules of a room, or of every room if `room_id` is `None`.
+    async fn get_filter_rules(&self, room_id: Option<&room::Id>) -> StoreResult<Vec<filter::Rule>>;
+
+    /// Remove a filter rule from a room.
+    ///
+    /// Returns whether the rule existed.
+    async fn remove_filter_rule(&self, room_id: &room::Id, id: &filter::Id) -> StoreResult<bool>;
+
+    // Session stuff
+    async fn add_session(&self, session: Session) -> StoreResult<()>;
+
+    async fn get_session_from_token(&self, token: &session::Token) -> Result<Session>;
+
+    async fn delete_session(&self, id: &session::Id) -> StoreResult<()>;
+
+    /// The version of the schema (see [`migrations`]), or `None` if the store doesn't have one.
+    ///
+    /// It's a trivial query, so it also checks that the store answers.
+    async fn schema_version(&self) -> StoreResult<Option<u32>>;
+
+    /// Wait for the writes that are in progress to finish, before the server exits.
+    async fn close(&self) -> StoreResult<()>;
+}
+
+/// The backends that a [`Store`] can be.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// An SQLite database file (see [`Pool`]).
+    #[default]
+    Sqlite,
+    /// Kept in memory, and lost when the server stops (see [`MemoryStore`]).
+    Memory,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Sqlite(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Sqlite(err)
+    }
+}
+
+/// Open the store that the config asks for.
+///
+/// An SQLite database is built (and migrated) first.
+pub fn open(config: &Config) -> std::result::Result<Arc<dyn Store>, migrations::Error> {
+    match config.store {
+        Backend::Sqlite => {
+            let database = Database::build(&config.database)?;
+            let pool = Pool::new(database, &config.database, config.database_readers)?;
+            Ok(Arc::new(pool))
+        }
+        Backend::Memory => Ok(Arc::new(MemoryStore::new())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{database::testing::TempDatabase, room::Visibility};
+
+    /// Each backend, so that tests check that they behave the same.
+    fn backends(temp: &TempDatabase) -> Vec<(&'static str, Box<dyn Store>)> {
+        let database = Database::build(temp.path()).unwrap();
+        let pool = Pool::new(database, temp.path(), 2).unwrap();
+        vec![
+            ("memory", Box::new(MemoryStore::new())),
+            ("sqlite", Box::new(pool)),
+        ]
+    }
+
+    fn id(id: i64) -> Snowflake {
+        Snowflake::try_from(id).unwrap()
+    }
+
+    fn user(user_id: i64, name: &str) -> User {
+        User {
+            id: id(user_id),
+            name: name.to_string(),
+            password: "hash".to_string(),
+        }
+    }
+
+    fn message(message_id: i64, parent: i64, room: i64) -> Message {
+        Message {
+            id: id(message_id),
+            author: id(1),
+            author_name: "alice".to_string(),
+            parent: id(parent),
+            room: id(room),
+            content: format!("message {}", message_id),
+            deleted: false,
+            held: false,
+            blocked: false,
+            created_at: id(message_id).created_at().into(),
+            reactions: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn children_of_direct_messages_stay_in_their_room() {
+        let temp = TempDatabase::new();
+        for (backend, store) in backends(&temp) {
+            let dm = Room {
+                id: id(100),
+                name: "dm-100".to_string(),
+                anonymous_reactions: false,
+                visibility: Visibility::Direct,
+            };
+            for (user_id, name) in [(1, "alice"), (2, "bob")] {
+                store.add_user(user(user_id, name)).await.unwrap();
+            }
+            store.add_room(&dm).await.unwrap();
+            store.add_member(&dm.id, &id(1)).await.unwrap();
+            store.add_member(&dm.id, &id(2)).await.unwrap();
+            store.add_message(&message(10, 100, 100)).await.unwrap();
+            store.add_message(&message(11, 10, 100)).await.unwrap();
+
+            let from_main = store
+                .get_children_of(&room::main_id(), Some(&id(10)))
+                .await
+                .unwrap();
+            assert!(from_main.is_empty(), "{}", backend);
+
+            let from_dm = store.get_children_of(&dm.id, Some(&id(10))).await.unwrap();
+            let ids = from_dm
+                .iter()
+                .map(|message| message.id.id())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, [11], "{}", backend);
+        }
+    }
+
+    fn ids(messages: &[Message]) -> Vec<i64> {
+        messages.iter().map(|message| message.id.id()).collect()
+    }
+
+    /// Two users, and a room (100) besides the main one.
+    async fn setup(store: &dyn Store) {
+        for (user_id, name) in [(1, "alice"), (2, "bob")] {
+            store.add_user(user(user_id, name)).await.unwrap();
+        }
+        let room = Room {
+            id: id(100),
+            name: "other".to_string(),
+            anonymous_reactions: false,
+            visibility: Visibility::Public,
+        };
+        store.add_room(&room).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn messages_stay_in_their_room() {
+        let temp = TempDatabase::new();
+        for (backend, store) in backends(&temp) {
+            setup(store.as_ref()).await;
+            let held = Message {
+                held: true,
+                ..message(12, 100, 100)
+            };
+            for message in [message(10, 0, 0), message(11, 100, 100), held] {
+                store.add_message(&message).await.unwrap();
+            }
+
+            let messages = store.get_room_messages(&id(100)).await.unwrap();
+            assert_eq!(ids(&messages), [11], "{}", backend);
+            let messages = store.get_recent_messages(&room::main_id()).await.unwrap();
+            assert_eq!(ids(&messages), [10], "{}", backend);
+            let messages = store.get_held_messages(&id(100)).await.unwrap();
+            assert_eq!(ids(&messages), [12], "{}", backend);
+
+            let query = SearchQuery {
+                q: "message".to_string(),
+                author: None,
+                before: None,
+                after: None,
+                limit: None,
+            };
+            let results = store.search_messages(&id(100), &query).await.unwrap();
+            let found = results
+                .iter()
+                .map(|result| result.message.id.id())
+                .collect::<Vec<_>>();
+            assert_eq!(found, [11], "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_between_are_limited() {
+        let temp = TempDatabase::new();
+        for (backend, store) in backends(&temp) {
+            setup(store.as_ref()).await;
+            for message_id in 10..15 {
+                store
+                    .add_message(&message(message_id, 100, 100))
+                    .await
+                    .unwrap();
+            }
+            store.add_message(&message(15, 0, 0)).await.unwrap();
+
+            let (start, end) = (SystemTime::UNIX_EPOCH, SystemTime::now());
+            let messages = store
+                .get_messages_between(&id(100), start, end, 3)
+                .await
+                .unwrap();
+            assert_eq!(ids(&messages), [10, 11, 12], "{}", backend);
+            let messages = store
+                .get_messages_between(&id(100), start, end, 100)
+                .await
+                .unwrap();
+            assert_eq!(ids(&messages), [10, 11, 12, 13, 14], "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn reactions_are_aggregated() {
+        let temp = TempDatabase::new();
+        for (backend, store) in backends(&temp) {
+            setup(store.as_ref()).await;
+            store.add_message(&message(10, 0, 0)).await.unwrap();
+            let message_id = id(10);
+
+            assert!(store.add_reaction(&message_id, &id(1), "👍").await.unwrap());
+            assert!(!store.add_reaction(&message_id, &id(1), "👍").await.unwrap());
+            assert!(store.add_reaction(&message_id, &id(2), "❤️").await.unwrap());
+            assert!(store.add_reaction(&message_id, &id(2), "👍").await.unwrap());
+
+            let expected = [
+                Reaction {
+                    emoji: "👍".to_string(),
+                    count: 2,
+                },
+                Reaction {
+                    emoji: "❤️".to_string(),
+                    count: 1,
+                },
+            ];
+            let reactions = store.get_reactions(&message_id).await.unwrap();
+            assert_eq!(reactions, expected, "{}", backend);
+            // Loading messages loads their reactions too
+            let messages = store.get_room_messages(&room::main_id()).await.unwrap();
+            assert_eq!(messages[0].reactions, expected, "{}", backend);
+
+            assert!(store
+                .remove_reaction(&message_id, &id(2), "❤️")
+                .await
+                .unwrap());
+            assert!(!store
+                .remove_reaction(&message_id, &id(2), "❤️")
+                .await
+                .unwrap());
+            let reactions = store.get_reactions(&message_id).await.unwrap();
+            assert_eq!(reactions, expected[..1], "{}", backend);
+        }
+    }
+
+    #[tokio::test]
+    async fn mentions_are_unread_until_read() {
+        let temp = TempDatabase::new();
+        for (backend, store) in backends(&temp) {
+            setup(store.as_ref()).await;
+            for message_id in 10..13 {
+                let message = message(message_id, 0, 0);
+                store.add_message(&message).await.unwrap();
+                store.add_mention(&message, &id(2)).await.unwrap();
+            }
+            store.remove_mention(&id(12), &id(2)).await.unwrap();
+
+            let unread = |mentions: Vec<Mention>| {
+                mentions
+                    .iter()
+                    .map(|mention| mention.message.id.id())
+                    .collect::<Vec<_>>()
+            };
+            let mentions = store.get_unread_mentions(&id(2)).await.unwrap();
+            assert_eq!(unread(mentions), [11, 10], "{}", backend);
+
+            store.mark_mentions_read(&id(2), &id(10)).await.unwrap();
+            let mentions = store.get_unread_mentions(&id(2)).await.unwrap();
+            assert_eq!(unread(mentions), [11], "{}", backend);
+            assert!(store.get_unread_mentions(&id(1)).await.unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn roles() {
+        let temp = TempDatabase::new();
+        for (backend, store) in backends(&temp) {
+            setup(store.as_ref()).await;
+            let room_id = id(100);
+            assert_eq!(store.count_users().await.unwrap(), 2, "{}", backend);
+
+            let role = store.get_role(&room_id, None).await.unwrap();
+            assert_eq!(role, Role::ANONYMOUS, "{}", backend);
+            let role = store.get_role(&room_id, Some(&id(1))).await.unwrap();
+            assert_eq!(role, Role::DEFAULT_USER, "{}", backend);
+            assert!(!store.is_member(&room_id, &id(1)).await.unwrap());
+
+            store.set_role(&room_id, &id(1), Role::Owner).await.unwrap();
+            store.add_member(&room_id, &id(2)).await.unwrap();
+            // Adding a member doesn't change the role they already have
+            store.add_member(&room_id, &id(1)).await.unwrap();
+
+            let role = store.get_role(&room_id, Some(&id(1))).await.unwrap();
+            assert_eq!(role, Role::Owner, "{}", backend);
+            assert!(store.is_member(&room_id, &id(2)).await.unwrap());
+            let owners = store.get_owners(&room_id).await.unwrap();
+            assert_eq!(owners, [id(1)], "{}", backend);
+            let owners = store.get_owners(&room::main_id()).await.unwrap();
+            assert!(owners.is_empty(), "{}", backend);
+        }
+    }
+}
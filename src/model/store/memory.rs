@@ -0,0 +1,947 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::IpAddr,
+    ops::Range,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use log::{debug, info};
+
+use super::{Result, Store, StoreResult};
+use crate::model::{
+    filter,
+    invite::Invite,
+    message,
+    moderation::{Ban, LogEntry, Silence},
+    report::{self, Report},
+    room::{self, Visibility},
+    search::{self, SearchQuery, SearchResult},
+    session, user, Mention, Message, Reaction, Role, Room, Session, Snowflake, Timestamp, User,
+};
+
+/// How many words (around the first match) a search snippet has, like SQLite's `snippet()`.
+const SNIPPET_WORDS: usize = 16;
+
+/// A [`Store`] that keeps everything in memory, for tests and ephemeral deployments.
+///
+/// It behaves like the SQLite store, but everything is lost when the server stops.
+#[derive(Debug)]
+pub struct MemoryStore {
+    data: RwLock<Data>,
+}
+
+/// The "tables", keyed by id (snowflakes are ordered by time, so maps are oldest first).
+#[derive(Debug, Default)]
+struct Data {
+    users: BTreeMap<i64, User>,
+    /// (user, blocked user)
+    blocks: BTreeSet<(i64, i64)>,
+    /// Messages are stored without their reactions, which are added when they're read.
+    messages: BTreeMap<i64, Message>,
+    /// (message, user, emoji), in the order they were added.
+    reactions: Vec<(i64, i64, String)>,
+    /// Whether each (message, user) mention has been read.
+    mentions: BTreeMap<(i64, i64), bool>,
+    rooms: BTreeMap<i64, Room>,
+    /// The role of each (room, user).
+    members: BTreeMap<(i64, i64), Role>,
+    /// In the order they were added.
+    invites: Vec<Invite>,
+    bans: Vec<Ban>,
+    silences: HashMap<(i64, i64), Silence>,
+    mod_log: BTreeMap<i64, LogEntry>,
+    reports: BTreeMap<i64, Report>,
+    filter_rules: BTreeMap<i64, filter::Rule>,
+    sessions: BTreeMap<i64, Session>,
+}
+
+impl MemoryStore {
+    /// An empty store, with only the main room.
+    pub fn new() -> MemoryStore {
+        let mut data = Data::default();
+        data.rooms.insert(
+            room::MAIN_ID,
+            Room {
+                id: room::main_id(),
+                name: "main".to_string(),
+                anonymous_reactions: false,
+                visibility: Visibility::Public,
+            },
+        );
+        info!("Created main room.");
+
+        MemoryStore {
+            data: RwLock::new(data),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Data> {
+        self.data.read().expect("memory store lock isn't poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Data> {
+        self.data.write().expect("memory store lock isn't poisoned")
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl Data {
+    /// A message, with its reactions.
+    fn message(&self, message: &Message) -> Message {
+        Message {
+            reactions: self.reactions(message.id.id()),
+            ..message.clone()
+        }
+    }
+
+    /// The messages that match, oldest first.
+    fn messages(&self, filter: impl Fn(&Message) -> bool) -> Vec<Message> {
+        self.messages
+            .values()
+            .filter(|message| filter(message))
+            .map(|message| self.message(message))
+            .collect()
+    }
+
+    fn reactions(&self, message_id: i64) -> Vec<Reaction> {
+        let mut reactions: Vec<Reaction> = Vec::new();
+        for (_, _, emoji) in self
+            .reactions
+            .iter()
+            .filter(|(message, _, _)| *message == message_id)
+        {
+            match reactions
+                .iter_mut()
+                .find(|reaction| reaction.emoji == *emoji)
+            {
+                Some(reaction) => reaction.count += 1,
+                None => reactions.push(Reaction {
+                    emoji: emoji.clone(),
+                    count: 1,
+                }),
+            }
+        }
+        reactions
+    }
+
+    fn is_member(&self, room_id: &room::Id, user_id: &user::Id) -> bool {
+        self.members.contains_key(&(room_id.id(), user_id.id()))
+    }
+
+    /// The ancestors of a message, starting with the top level message and ending with its parent.
+    fn ancestors(&self, message: &Message) -> Vec<message::Id> {
+        let mut ancestors = Vec::new();
+        let mut parent = &message.parent;
+        while let Some(message) = self.messages.get(&parent.id()) {
+            ancestors.push(parent.clone());
+            parent = &message.parent;
+        }
+        ancestors.reverse();
+        ancestors
+    }
+}
+
+/// A snowflake from an id that the store was given (so it's valid).
+fn snowflake(id: i64) -> Snowflake {
+    Snowflake::try_from(id).expect("id in the store is a valid snowflake")
+}
+
+/// Whether something that lasts until `until` (or forever) is still going.
+fn is_active(until: Option<Timestamp>) -> bool {
+    until.is_none_or(|until| until > Timestamp::now())
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    // User stuff
+    async fn add_user(&self, user: User) -> StoreResult<()> {
+        debug!("Adding user {} to memory store", user.id.id());
+        self.write().users.insert(user.id.id(), user);
+        Ok(())
+    }
+
+    async fn get_user(&self, id: &user::Id) -> Result<User> {
+        Ok(self.read().users.get(&id.id()).cloned())
+    }
+
+    async fn get_user_by_name(&self, name: &str) -> Result<User> {
+        Ok(self
+            .read()
+            .users
+            .values()
+            .find(|user| user.name == name)
+            .cloned())
+    }
+
+    async fn get_user_name(&self, id: &user::Id) -> Result<String> {
+        Ok(self
+            .read()
+            .users
+            .get(&id.id())
+            .map(|user| user.name.clone()))
+    }
+
+    async fn count_users(&self) -> StoreResult<u64> {
+        Ok(self.read().users.len() as u64)
+    }
+
+    // Block stuff
+    async fn add_block(&self, user_id: &user::Id, blocked: &user::Id) -> StoreResult<()> {
+        self.write().blocks.insert((user_id.id(), blocked.id()));
+        Ok(())
+    }
+
+    async fn remove_block(&self, user_id: &user::Id, blocked: &user::Id) -> StoreResult<bool> {
+        Ok(self.write().blocks.remove(&(user_id.id(), blocked.id())))
+    }
+
+    async fn get_blocked_users(&self, user_id: &user::Id) -> StoreResult<Vec<user::Id>> {
+        let blocked = self
+            .read()
+            .blocks
+            .iter()
+            .filter(|(user, _)| *user == user_id.id())
+            .map(|(_, blocked)| snowflake(*blocked))
+            .collect();
+        Ok(blocked)
+    }
+
+    async fn get_blocks(&self) -> StoreResult<Vec<(user::Id, user::Id)>> {
+        let blocks = self
+            .read()
+            .blocks
+            .iter()
+            .map(|(user, blocked)| (snowflake(*user), snowflake(*blocked)))
+            .collect();
+        Ok(blocks)
+    }
+
+    // Messages stuff
+    async fn get_recent_messages(&self, room_id: &room::Id) -> StoreResult<Vec<Message>> {
+        self.get_some_messages(room_id, None, 100).await
+    }
+
+    async fn get_some_messages(
+        &self,
+        room_id: &room::Id,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> StoreResult<Vec<Message>> {
+        let data = self.read();
+        let messages = data
+            .messages
+            .values()
+            .rev()
+            .filter(|message| {
+                message.room == *room_id
+                    && message.parent == *room_id
+                    && !message.held
+                    && before
+                        .as_ref()
+                        .is_none_or(|before| message.id.id() < before.id())
+            })
+            .take(amount as usize)
+            .map(|message| data.message(message))
+            .collect();
+        Ok(messages)
+    }
+
+    async fn get_room_messages(&self, room_id: &room::Id) -> StoreResult<Vec<Message>> {
+        Ok(self
+            .read()
+            .messages(|message| message.room == *room_id && !message.held))
+    }
+
+    async fn get_children_of(
+        &self,
+        room_id: &room::Id,
+        parent: Option<&Snowflake>,
+    ) -> StoreResult<Vec<Message>> {
+        let Some(parent) = parent else {
+            return Ok(Vec::new());
+        };
+
+        let data = self.read();
+        // Messages are oldest first, and replies are newer than their parents,
+        // so one pass finds every descendant
+        let mut ancestors = BTreeSet::from([parent.id()]);
+        let mut children = Vec::new();
+        for message in data.messages.values() {
+            if message.room == *room_id && !message.held && ancestors.contains(&message.parent.id())
+            {
+                ancestors.insert(message.id.id());
+                children.push(data.message(message));
+            }
+        }
+        Ok(children)
+    }
+
+    async fn get_message(&self, id: &message::Id) -> Result<Message> {
+        let data = self.read();
+        Ok(data
+            .messages
+            .get(&id.id())
+            .map(|message| data.message(message)))
+    }
+
+    async fn add_message(&self, message: &Message) -> StoreResult<()> {
+        debug!("Adding message {} to memory store", message.id.id());
+        let message = Message {
+            deleted: false,
+            blocked: false,
+            reactions: Vec::new(),
+            ..message.clone()
+        };
+        self.write().messages.insert(message.id.id(), message);
+        Ok(())
+    }
+
+    async fn get_messages_between(
+        &self,
+        room_id: &room::Id,
+        start: SystemTime,
+        end: SystemTime,
+        limit: u8,
+    ) -> StoreResult<Vec<Message>> {
+        let range = Snowflake::first_at(start).id()..Snowflake::first_at(end).id();
+        let mut messages = self.read().messages(|message| {
+            message.room == *room_id && !message.held && range.contains(&message.id.id())
+        });
+        messages.truncate(limit as usize);
+        Ok(messages)
+    }
+
+    async fn search_messages(
+        &self,
+        room_id: &room::Id,
+        query: &SearchQuery,
+    ) -> StoreResult<Vec<SearchResult>> {
+        let terms = search_terms(&query.q);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let limit = query.limit.unwrap_or(search::DEFAULT_LIMIT) as usize;
+
+        let data = self.read();
+        let mut matches = data
+            .messages
+            .values()
+            .filter(|message| {
+                message.room == *room_id
+                    && !message.held
+                    && query
+                        .author
+                        .as_ref()
+                        .is_none_or(|author| message.author == *author)
+                    && query
+                        .before
+                        .as_ref()
+                        .is_none_or(|before| message.id.id() < before.id())
+                    && query
+                        .after
+                        .as_ref()
+                        .is_none_or(|after| message.id.id() > after.id())
+            })
+            .filter_map(|message| {
+                let matched = match_terms(&message.content, &terms)?;
+                Some((message, matched))
+            })
+            .collect::<Vec<_>>();
+
+        // The messages with the most matching words first, then the newest
+        matches.sort_by(|(a, a_matched), (b, b_matched)| {
+            b_matched
+                .len()
+                .cmp(&a_matched.len())
+                .then(b.id.id().cmp(&a.id.id()))
+        });
+
+        let results = matches
+            .into_iter()
+            .take(limit)
+            .map(|(message, matched)| SearchResult {
+                snippet: search::escape_snippet(&snippet(&message.content, &matched)),
+                path: data.ancestors(message),
+                message: data.message(message),
+            })
+            .collect();
+        Ok(results)
+    }
+
+    async fn update_message_content(&self, id: &message::Id, content: &str) -> StoreResult<()> {
+        if let Some(message) = self.write().messages.get_mut(&id.id()) {
+            message.content = content.to_string();
+        }
+        Ok(())
+    }
+
+    async fn delete_message(&self, id: &message::Id) -> StoreResult<()> {
+        debug!("Deleting message {}", id.id());
+        let mut data = self.write();
+        if let Some(message) = data.messages.get_mut(&id.id()) {
+            message.content = String::new();
+            message.deleted = true;
+        }
+        data.reactions.retain(|(message, _, _)| *message != id.id());
+        data.mentions.retain(|(message, _), _| *message != id.id());
+        Ok(())
+    }
+
+    async fn get_held_messages(&self, room_id: &room::Id) -> StoreResult<Vec<Message>> {
+        Ok(self
+            .read()
+            .messages(|message| message.room == *room_id && message.held))
+    }
+
+    async fn approve_message(&self, id: &message::Id) -> StoreResult<()> {
+        if let Some(message) = self.write().messages.get_mut(&id.id()) {
+            message.held = false;
+        }
+        Ok(())
+    }
+
+    // Reaction stuff
+    async fn add_reaction(
+        &self,
+        message_id: &message::Id,
+        user_id: &user::Id,
+        emoji: &str,
+    ) -> StoreResult<bool> {
+        let mut data = self.write();
+        let reaction = (message_id.id(), user_id.id(), emoji.to_string());
+        if data.reactions.contains(&reaction) {
+            return Ok(false);
+        }
+        data.reactions.push(reaction);
+        Ok(true)
+    }
+
+    async fn remove_reaction(
+        &self,
+        message_id: &message::Id,
+        user_id: &user::Id,
+        emoji: &str,
+    ) -> StoreResult<bool> {
+        let mut data = self.write();
+        let before = data.reactions.len();
+        data.reactions.retain(|(message, user, reaction)| {
+            !(*message == message_id.id() && *user == user_id.id() && reaction == emoji)
+        });
+        Ok(data.reactions.len() < before)
+    }
+
+    async fn get_reactions(&self, message_id: &message::Id) -> StoreResult<Vec<Reaction>> {
+        Ok(self.read().reactions(message_id.id()))
+    }
+
+    // Mention stuff
+    async fn add_mention(&self, message: &Message, user_id: &user::Id) -> StoreResult<()> {
+        self.write()
+            .mentions
+            .entry((message.id.id(), user_id.id()))
+            .or_insert(false);
+        Ok(())
+    }
+
+    async fn remove_mention(
+        &self,
+        message_id: &message::Id,
+        user_id: &user::Id,
+    ) -> StoreResult<()> {
+        self.write()
+            .mentions
+            .remove(&(message_id.id(), user_id.id()));
+        Ok(())
+    }
+
+    async fn get_unread_mentions(&self, user_id: &user::Id) -> StoreResult<Vec<Mention>> {
+        let data = self.read();
+        let mentions = data
+            .mentions
+            .iter()
+            .rev()
+            .filter(|((_, user), read)| *user == user_id.id() && !**read)
+            .filter_map(|((message, _), _)| data.messages.get(message))
+            .map(|message| Mention {
+                user_id: user_id.clone(),
+                message: data.message(message),
+            })
+            .collect();
+        Ok(mentions)
+    }
+
+    async fn mark_mentions_read(&self, user_id: &user::Id, up_to: &message::Id) -> StoreResult<()> {
+        for ((message, user), read) in self.write().mentions.iter_mut() {
+            if *user == user_id.id() && *message <= up_to.id() {
+                *read = true;
+            }
+        }
+        Ok(())
+    }
+
+    // Room stuff
+    async fn add_room(&self, room: &Room) -> StoreResult<()> {
+        self.write().rooms.insert(room.id.id(), room.clone());
+        info!("Created room {}: {}", room.id, room.name);
+        Ok(())
+    }
+
+    async fn get_room(&self, id: &room::Id) -> Result<Room> {
+        Ok(self.read().rooms.get(&id.id()).cloned())
+    }
+
+    async fn get_room_by_name(&self, name: &str) -> Result<Room> {
+        Ok(self
+            .read()
+            .rooms
+            .values()
+            .find(|room| room.name == name)
+            .cloned())
+    }
+
+    async fn update_room_settings(
+        &self,
+        id: &room::Id,
+        settings: &room::Settings,
+    ) -> StoreResult<()> {
+        if let Some(room) = self.write().rooms.get_mut(&id.id()) {
+            room.anonymous_reactions = settings.anonymous_reactions;
+            if let Some(visibility) = settings.visibility {
+                room.visibility = visibility;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_public_rooms(&self) -> StoreResult<Vec<Room>> {
+        let mut rooms = self
+            .read()
+            .rooms
+            .values()
+            .filter(|room| room.visibility == Visibility::Public)
+            .cloned()
+            .collect::<Vec<_>>();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(rooms)
+    }
+
+    async fn get_direct_rooms(&self, user_id: &user::Id) -> StoreResult<Vec<Room>> {
+        let data = self.read();
+        let rooms = data
+            .rooms
+            .values()
+            .rev()
+            .filter(|room| {
+                room.visibility == Visibility::Direct && data.is_member(&room.id, user_id)
+            })
+            .cloned()
+            .collect();
+        Ok(rooms)
+    }
+
+    async fn can_access_room(&self, room: &Room, user_id: Option<&user::Id>) -> StoreResult<bool> {
+        match (room.visibility, user_id) {
+            (Visibility::Public | Visibility::Unlisted, _) => Ok(true),
+            (Visibility::Private | Visibility::Direct, None) => Ok(false),
+            (Visibility::Private | Visibility::Direct, Some(user_id)) => {
+                Ok(self.read().is_member(&room.id, user_id))
+            }
+        }
+    }
+
+    // Room member stuff
+    async fn get_role(&self, room_id: &room::Id, user_id: Option<&user::Id>) -> StoreResult<Role> {
+        let Some(user_id) = user_id else {
+            return Ok(Role::ANONYMOUS);
+        };
+
+        let role = self
+            .read()
+            .members
+            .get(&(room_id.id(), user_id.id()))
+            .copied();
+        Ok(role.unwrap_or(Role::DEFAULT_USER))
+    }
+
+    async fn set_role(
+        &self,
+        room_id: &room::Id,
+        user_id: &user::Id,
+        role: Role,
+    ) -> StoreResult<()> {
+        self.write()
+            .members
+            .insert((room_id.id(), user_id.id()), role);
+        Ok(())
+    }
+
+    async fn is_member(&self, room_id: &room::Id, user_id: &user::Id) -> StoreResult<bool> {
+        Ok(self.read().is_member(room_id, user_id))
+    }
+
+    async fn get_members(&self, room_id: &room::Id) -> StoreResult<Vec<user::Id>> {
+        let members = self
+            .read()
+            .members
+            .keys()
+            .filter(|(room, _)| *room == room_id.id())
+            .map(|(_, user)| snowflake(*user))
+            .collect();
+        Ok(members)
+    }
+
+    async fn get_owners(&self, room_id: &room::Id) -> StoreResult<Vec<user::Id>> {
+        let owners = self
+            .read()
+            .members
+            .iter()
+            .filter(|((room, _), role)| *room == room_id.id() && **role == Role::Owner)
+            .map(|((_, user), _)| snowflake(*user))
+            .collect();
+        Ok(owners)
+    }
+
+    async fn add_member(&self, room_id: &room::Id, user_id: &user::Id) -> StoreResult<()> {
+        self.write()
+            .members
+            .entry((room_id.id(), user_id.id()))
+            .or_insert(Role::DEFAULT_USER);
+        Ok(())
+    }
+
+    // Invite stuff
+    async fn add_invite(&self, invite: &Invite) -> StoreResult<()> {
+        self.write().invites.push(invite.clone());
+        Ok(())
+    }
+
+    async fn get_invite(&self, token: &str) -> Result<Invite> {
+        Ok(self
+            .read()
+            .invites
+            .iter()
+            .find(|invite| invite.token == token)
+            .cloned())
+    }
+
+    async fn get_invites(&self, room_id: &room::Id) -> StoreResult<Vec<Invite>> {
+        Ok(self
+            .read()
+            .invites
+            .iter()
+            .filter(|invite| invite.room_id == *room_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn use_invite(&self, token: &str) -> StoreResult<bool> {
+        let mut data = self.write();
+        let Some(invite) = data.invites.iter_mut().find(|invite| invite.token == token) else {
+            return Ok(false);
+        };
+        if invite
+            .max_uses
+            .is_some_and(|max_uses| invite.uses >= max_uses)
+            || !is_active(invite.expires_at)
+        {
+            return Ok(false);
+        }
+        invite.uses += 1;
+        Ok(true)
+    }
+
+    async fn delete_invite(&self, room_id: &room::Id, token: &str) -> StoreResult<bool> {
+        let mut data = self.write();
+        let before = data.invites.len();
+        data.invites
+            .retain(|invite| !(invite.token == token && invite.room_id == *room_id));
+        Ok(data.invites.len() < before)
+    }
+
+    // Moderation stuff
+    async fn add_ban(&self, ban: &Ban) -> StoreResult<()> {
+        self.write().bans.push(ban.clone());
+        Ok(())
+    }
+
+    async fn get_active_ban(
+        &self,
+        room_id: &room::Id,
+        user_id: Option<&user::Id>,
+        ip: IpAddr,
+    ) -> Result<Ban> {
+        let data = self.read();
+        let ban = data
+            .bans
+            .iter()
+            .filter(|ban| {
+                let banned = match user_id {
+                    Some(user_id) => ban.user_id == *user_id,
+                    None => ban.ip == Some(ip),
+                };
+                ban.room_id == *room_id && banned && is_active(ban.until)
+            })
+            // The ban that lasts the longest (forever, if there is one)
+            .max_by_key(|ban| (ban.until.is_none(), ban.until))
+            .cloned();
+        Ok(ban)
+    }
+
+    async fn set_silence(&self, silence: &Silence) -> StoreResult<()> {
+        self.write().silences.insert(
+            (silence.room_id.id(), silence.user_id.id()),
+            silence.clone(),
+        );
+        Ok(())
+    }
+
+    async fn get_active_silence(&self, room_id: &room::Id, user_id: &user::Id) -> Result<Silence> {
+        Ok(self
+            .read()
+            .silences
+            .get(&(room_id.id(), user_id.id()))
+            .filter(|silence| is_active(silence.until))
+            .cloned())
+    }
+
+    // Moderation log stuff
+    async fn add_log_entry(&self, entry: &LogEntry) -> StoreResult<()> {
+        self.write().mod_log.insert(entry.id.id(), entry.clone());
+        Ok(())
+    }
+
+    async fn get_mod_log(
+        &self,
+        room_id: &room::Id,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> StoreResult<Vec<LogEntry>> {
+        let end = before.map_or(i64::MAX, |before| before.id());
+        Ok(self
+            .read()
+            .mod_log
+            .range(..end)
+            .rev()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.room_id == *room_id)
+            .take(amount as usize)
+            .cloned()
+            .collect())
+    }
+
+    // Report stuff
+    async fn add_report(&self, report: &Report) -> StoreResult<()> {
+        self.write().reports.insert(report.id.id(), report.clone());
+        Ok(())
+    }
+
+    async fn get_report(&self, id: &report::Id) -> Result<Report> {
+        Ok(self.read().reports.get(&id.id()).cloned())
+    }
+
+    async fn get_reports(
+        &self,
+        room_id: &room::Id,
+        status: Option<report::Status>,
+        before: Option<Snowflake>,
+        amount: u8,
+    ) -> StoreResult<Vec<Report>> {
+        let end = before.map_or(i64::MAX, |before| before.id());
+        Ok(self
+            .read()
+            .reports
+            .range(..end)
+            .rev()
+            .map(|(_, report)| report)
+            .filter(|report| {
+                report.room_id == *room_id
+                    && match status {
+                        Some(status) => report.status == status,
+                        None => report.status.is_pending(),
+                    }
+            })
+            .take(amount as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_report_status(
+        &self,
+        id: &report::Id,
+        status: report::Status,
+        moderator: &user::Id,
+    ) -> StoreResult<()> {
+        if let Some(report) = self.write().reports.get_mut(&id.id()) {
+            report.status = status;
+            report.moderator = Some(moderator.clone());
+        }
+        Ok(())
+    }
+
+    // Content filter stuff
+    async fn add_filter_rule(&self, rule: &filter::Rule) -> StoreResult<()> {
+        self.write().filter_rules.insert(rule.id.id(), rule.clone());
+        Ok(())
+    }
+
+    async fn get_filter_rules(&self, room_id: Option<&room::Id>) -> StoreResult<Vec<filter::Rule>> {
+        Ok(self
+            .read()
+            .filter_rules
+            .values()
+            .filter(|rule| room_id.is_none_or(|room_id| rule.room_id == *room_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn remove_filter_rule(&self, room_id: &room::Id, id: &filter::Id) -> StoreResult<bool> {
+        let mut data = self.write();
+        if !data
+            .filter_rules
+            .get(&id.id())
+            .is_some_and(|rule| rule.room_id == *room_id)
+        {
+            return Ok(false);
+        }
+        data.filter_rules.remove(&id.id());
+        Ok(true)
+    }
+
+    // Session stuff
+    async fn add_session(&self, session: Session) -> StoreResult<()> {
+        self.write().sessions.insert(session.id.id(), session);
+        Ok(())
+    }
+
+    async fn get_session_from_token(&self, token: &session::Token) -> Result<Session> {
+        Ok(self
+            .read()
+            .sessions
+            .values()
+            .find(|session| session.token == *token)
+            .cloned())
+    }
+
+    async fn delete_session(&self, id: &session::Id) -> StoreResult<()> {
+        self.write().sessions.remove(&id.id());
+        Ok(())
+    }
+
+    async fn schema_version(&self) -> StoreResult<Option<u32>> {
+        Ok(None)
+    }
+
+    async fn close(&self) -> StoreResult<()> {
+        // Writes happen while the lock is held, so there are none in progress
+        Ok(())
+    }
+}
+
+/// A word of a search, and whether it's a prefix (it ended with `*`).
+type Term = (Vec<String>, bool);
+
+/// Split a search into terms, like [`search::to_fts_query`]. Each term is a phrase of words.
+fn search_terms(q: &str) -> Vec<Term> {
+    q.split_whitespace()
+        .filter_map(|word| {
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(word) => (word, true),
+                None => (word, false),
+            };
+            let words = words(word)
+                .into_iter()
+                .map(|range| word[range].to_lowercase())
+                .collect::<Vec<_>>();
+            (!words.is_empty()).then_some((words, prefix))
+        })
+        .collect()
+}
+
+/// The byte ranges of the words in some text: runs of letters and numbers, like FTS5 tokenizes.
+fn words(text: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                words.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push(s..text.len());
+    }
+    words
+}
+
+/// If every term is in the content, the indices of the words that matched.
+fn match_terms(content: &str, terms: &[Term]) -> Option<BTreeSet<usize>> {
+    let words = words(content)
+        .into_iter()
+        .map(|range| content[range].to_lowercase())
+        .collect::<Vec<_>>();
+
+    let mut matched = BTreeSet::new();
+    for (phrase, prefix) in terms {
+        let mut found = false;
+        for start in 0..words.len().saturating_sub(phrase.len() - 1) {
+            let is_match = phrase.iter().enumerate().all(|(i, term)| {
+                let word = &words[start + i];
+                if *prefix && i == phrase.len() - 1 {
+                    word.starts_with(term.as_str())
+                } else {
+                    word == term
+                }
+            });
+            if is_match {
+                found = true;
+                matched.extend(start..start + phrase.len());
+            }
+        }
+        if !found {
+            return None;
+        }
+    }
+    Some(matched)
+}
+
+/// The part of the content around the first match, with the matching words marked.
+fn snippet(content: &str, matched: &BTreeSet<usize>) -> String {
+    let words = words(content);
+    let first = matched.first().copied().unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_WORDS / 4);
+    let end = (start + SNIPPET_WORDS).min(words.len());
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut position = words.get(start).map_or(0, |word| word.start);
+    for (i, word) in words.iter().enumerate().take(end).skip(start) {
+        snippet.push_str(&content[position..word.start]);
+        if matched.contains(&i) {
+            snippet.push(search::MATCH_START);
+            snippet.push_str(&content[word.clone()]);
+            snippet.push(search::MATCH_END);
+        } else {
+            snippet.push_str(&content[word.clone()]);
+        }
+        position = word.end;
+    }
+    if end < words.len() {
+        snippet.push('…');
+    } else {
+        snippet.push_str(&content[position..]);
+    }
+    snippet
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, SystemTime};
+
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::de::Error;
+
+/// A point in time, (de)serialized as an RFC 3339 string in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp(pub SystemTime);
+
+impl Timestamp {
+    pub fn now() -> Timestamp {
+        Timestamp(SystemTime::now())
+    }
+
+    /// The time `duration` from now.
+    pub fn after(duration: Duration) -> Timestamp {
+        Timestamp(SystemTime::now() + duration)
+    }
+
+    /// Milliseconds since the Unix epoch, which is how timestamps are stored in the database.
+    fn as_millis(&self) -> i64 {
+        self.0
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64
+    }
+}
+
+impl ToSql for Timestamp {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_millis().into())
+    }
+}
+
+impl FromSql for Timestamp {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let millis = value.as_i64()?;
+        Ok(Timestamp(
+            SystemTime::UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64),
+        ))
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(value: SystemTime) -> Self {
+        Timestamp(value)
+    }
+}
+
+impl serde::Serialize for Timestamp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        humantime::format_rfc3339_millis(self.0)
+            .to_string()
+            .serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Timestamp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        humantime::parse_rfc3339_weak(&time)
+            .map(Timestamp)
+            .map_err(D::Error::custom)
+    }
+}
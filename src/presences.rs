@@ -0,0 +1,58 @@
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+
+use crate::model::{room, user, Snowflake};
+
+/// A presence that is currently connected.
+#[derive(Clone, Debug)]
+pub struct Online {
+    pub id: Snowflake,
+    pub room_id: room::Id,
+    pub user_id: Option<user::Id>,
+    pub ip: IpAddr,
+}
+
+impl Online {
+    /// The id that messages sent by this presence are authored by.
+    pub fn author_id(&self) -> user::Id {
+        self.user_id.clone().unwrap_or_else(|| self.id.clone())
+    }
+}
+
+/// The presences that are currently connected, in every room.
+#[derive(Debug, Default)]
+pub struct Presences {
+    online: Mutex<HashMap<i64, Online>>,
+}
+
+impl Presences {
+    pub fn insert(&self, online: Online) {
+        self.lock().insert(online.id.id(), online);
+    }
+
+    pub fn remove(&self, id: &Snowflake) {
+        self.lock().remove(&id.id());
+    }
+
+    /// Update the user that a presence is authenticated as.
+    pub fn set_user(&self, id: &Snowflake, user_id: Option<user::Id>) {
+        if let Some(online) = self.lock().get_mut(&id.id()) {
+            online.user_id = user_id;
+        }
+    }
+
+    pub fn get(&self, id: &Snowflake) -> Option<Online> {
+        self.lock().get(&id.id()).cloned()
+    }
+
+    pub fn in_room(&self, room_id: &room::Id) -> Vec<Online> {
+        self.lock()
+            .values()
+            .filter(|online| &online.room_id == room_id)
+            .cloned()
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<i64, Online>> {
+        self.online.lock().expect("presences lock isn't poisoned")
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::model::{limits::Rate, user, Limits};
+
+/// The most shared (per-user and per-address) buckets to keep before forgetting the full ones.
+const MAX_SHARED_BUCKETS: usize = 1024;
+
+/// Something that is rate limited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Post,
+    ChangeName,
+    LoadHistory,
+    Authenticate,
+}
+
+impl Action {
+    fn rate(&self, limits: &Limits) -> Rate {
+        match self {
+            Action::Post => limits.post_rate,
+            Action::ChangeName => limits.name_change_rate,
+            Action::LoadHistory => limits.load_rate,
+            Action::Authenticate => limits.auth_rate,
+        }
+    }
+}
+
+/// Who a shared bucket is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Key {
+    User(i64),
+    Ip(IpAddr),
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn full(rate: Rate) -> Bucket {
+        Bucket {
+            tokens: rate.burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last);
+        let refilled = elapsed.as_secs_f64() / rate.refill.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(rate.burst as f64);
+        self.last = now;
+    }
+
+    /// Check if the bucket has a token, without taking it.
+    /// If it doesn't, returns how long until it will.
+    fn check(&mut self, rate: Rate, now: Instant) -> Result<(), Duration> {
+        self.refill(rate, now);
+        if self.tokens >= 1.0 {
+            Ok(())
+        } else {
+            Err(rate.refill.mul_f64(1.0 - self.tokens))
+        }
+    }
+
+    /// Take a token from the bucket.
+    /// If there aren't any, returns how long until there will be one.
+    fn take(&mut self, rate: Rate, now: Instant) -> Result<(), Duration> {
+        self.check(rate, now)?;
+        self.tokens -= 1.0;
+        Ok(())
+    }
+
+    fn is_full(&self, rate: Rate, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last);
+        self.tokens + elapsed.as_secs_f64() / rate.refill.as_secs_f64() >= rate.burst as f64
+    }
+}
+
+/// The rate limits that are shared by all connections, for authenticated users
+/// (and for addresses, when authenticating).
+pub struct RateLimiter {
+    limits: Limits,
+    buckets: Mutex<HashMap<(Key, Action), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Limits) -> RateLimiter {
+        RateLimiter {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn take(&self, key: Key, action: Action, now: Instant) -> Result<(), Duration> {
+        let rate = action.rate(&self.limits);
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("rate limiter lock isn't poisoned");
+
+        if buckets.len() > MAX_SHARED_BUCKETS {
+            buckets.retain(|(_, action), bucket| !bucket.is_full(action.rate(&self.limits), now));
+        }
+
+        buckets
+            .entry((key, action))
+            .or_insert_with(|| Bucket::full(rate))
+            .take(rate, now)
+    }
+}
+
+/// The rate limits of a single connection (presence).
+pub struct ConnectionLimiter {
+    shared: Arc<RateLimiter>,
+    ip: IpAddr,
+    buckets: HashMap<Action, Bucket>,
+    strikes: Bucket,
+}
+
+impl ConnectionLimiter {
+    pub fn new(shared: Arc<RateLimiter>, ip: IpAddr) -> ConnectionLimiter {
+        let strikes = Bucket::full(shared.limits.strike_rate);
+        ConnectionLimiter {
+            shared,
+            ip,
+            buckets: HashMap::new(),
+            strikes,
+        }
+    }
+
+    /// Check if the connection can do an action right now.
+    /// This counts against both the connection's and the user's (if any) limits,
+    /// or the address's limits when authenticating (which anyone can try).
+    ///
+    /// If it can't, returns how long until it can.
+    pub fn check(&mut self, action: Action, user_id: Option<&user::Id>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let rate = action.rate(&self.shared.limits);
+        let bucket = self
+            .buckets
+            .entry(action)
+            .or_insert_with(|| Bucket::full(rate));
+
+        // Only take the connection's token if the shared limits allow it too
+        bucket.check(rate, now)?;
+        let key = match action {
+            Action::Authenticate => Some(Key::Ip(self.ip)),
+            _ => user_id.map(|user_id| Key::User(user_id.id())),
+        };
+        if let Some(key) = key {
+            self.shared.take(key, action, now)?;
+        }
+        bucket.take(rate, now)
+    }
+
+    /// Record that the connection was rate limited.
+    ///
+    /// Returns `false` if it has been rate limited too often, and should be disconnected.
+    pub fn strike(&mut self) -> bool {
+        self.strikes
+            .take(self.shared.limits.strike_rate, Instant::now())
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    /// Limits that don't refill during a test.
+    fn limits() -> Limits {
+        Limits {
+            post_rate: Rate::new(2, HOUR),
+            auth_rate: Rate::new(1, HOUR),
+            ..Limits::default()
+        }
+    }
+
+    fn user(id: i64) -> user::Id {
+        user::Id::try_from(id).unwrap()
+    }
+
+    #[test]
+    fn buckets_refill() {
+        let rate = Rate::new(2, Duration::from_secs(1));
+        let start = Instant::now();
+        let mut bucket = Bucket::full(rate);
+
+        assert!(bucket.take(rate, start).is_ok());
+        assert!(bucket.take(rate, start).is_ok());
+        assert_eq!(bucket.take(rate, start), Err(Duration::from_secs(1)));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.take(rate, later), Err(Duration::from_millis(500)));
+        assert!(bucket.take(rate, start + Duration::from_secs(1)).is_ok());
+
+        // It doesn't fill past the burst
+        let much_later = start + Duration::from_secs(60);
+        assert!(bucket.is_full(rate, much_later));
+        for _ in 0..2 {
+            assert!(bucket.take(rate, much_later).is_ok());
+        }
+        assert!(bucket.take(rate, much_later).is_err());
+    }
+
+    #[test]
+    fn users_are_limited_across_connections() {
+        let shared = Arc::new(RateLimiter::new(limits()));
+        let ip = "127.0.0.1".parse().unwrap();
+        let mut first = ConnectionLimiter::new(shared.clone(), ip);
+        let mut second = ConnectionLimiter::new(shared.clone(), ip);
+
+        assert!(first.check(Action::Post, Some(&user(1))).is_ok());
+        assert!(second.check(Action::Post, Some(&user(1))).is_ok());
+        assert!(second.check(Action::Post, Some(&user(1))).is_err());
+        // Other users (and anonymous presences) have their own limits
+        assert!(second.check(Action::Post, Some(&user(2))).is_ok());
+        assert!(first.check(Action::Post, None).is_ok());
+    }
+
+    #[test]
+    fn connections_keep_their_tokens_when_the_user_is_limited() {
+        let shared = Arc::new(RateLimiter::new(limits()));
+        let ip = "127.0.0.1".parse().unwrap();
+        let mut first = ConnectionLimiter::new(shared.clone(), ip);
+        let mut second = ConnectionLimiter::new(shared.clone(), ip);
+
+        assert!(first.check(Action::Post, Some(&user(1))).is_ok());
+        assert!(first.check(Action::Post, Some(&user(1))).is_ok());
+        assert!(second.check(Action::Post, Some(&user(1))).is_err());
+        // The second connection's tokens weren't taken by the rejected post
+        assert!(second.check(Action::Post, Some(&user(2))).is_ok());
+        assert!(second.check(Action::Post, Some(&user(3))).is_ok());
+    }
+
+    #[test]
+    fn authenticating_is_limited_by_address() {
+        let shared = Arc::new(RateLimiter::new(limits()));
+        let ip = "127.0.0.1".parse().unwrap();
+        let mut first = ConnectionLimiter::new(shared.clone(), ip);
+        let mut second = ConnectionLimiter::new(shared.clone(), ip);
+        let mut elsewhere = ConnectionLimiter::new(shared.clone(), "10.0.0.1".parse().unwrap());
+
+        assert!(first.check(Action::Authenticate, None).is_ok());
+        assert!(first.check(Action::Authenticate, None).is_err());
+        assert!(second.check(Action::Authenticate, None).is_err());
+        assert!(elsewhere.check(Action::Authenticate, None).is_ok());
+    }
+
+    #[test]
+    fn connections_are_disconnected_after_too_many_strikes() {
+        let limits = Limits {
+            strike_rate: Rate::new(2, HOUR),
+            ..limits()
+        };
+        let mut limiter =
+            ConnectionLimiter::new(Arc::new(RateLimiter::new(limits)), "::1".parse().unwrap());
+
+        assert!(limiter.strike());
+        assert!(limiter.strike());
+        assert!(!limiter.strike());
+    }
+}
@@ -1,15 +1,27 @@
-use crate::model::{AppState, User};
+use crate::model::{
+    moderation::{LogEntry, ModAction},
+    room, user, AppState, Role, Session, Snowflake, Store, User,
+};
 use axum::{
     extract::{Json, Path, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
 };
 use axum_macros::debug_handler;
 use log::{debug, error};
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
 pub mod auth;
+pub mod blocks;
+pub mod dm;
+pub mod filters;
+pub mod health;
+pub mod invites;
 pub mod messages;
+pub mod metrics;
+pub mod notifications;
 pub mod register;
+pub mod reports;
+pub mod rooms;
 pub mod sessions;
 pub mod ws;
 
@@ -34,9 +46,9 @@ pub async fn get_user(
     State(state): State<Arc<AppState>>,
     Path(id): Path<super::model::user::Id>,
 ) -> Result<Json<User>, StatusCode> {
-    let database = state.database.lock().await;
+    let database = state.database.as_ref();
 
-    let user = match database.get_user(&id) {
+    let user = match database.get_user(&id).await {
         Ok(Some(user)) => user,
         Ok(None) => {
             debug!("User {:?} not found in database.", id);
@@ -50,3 +62,157 @@ pub async fn get_user(
 
     Ok(Json(user))
 }
+
+/// The address of a client. If it connected through a trusted proxy, this is the last address in
+/// `X-Forwarded-For` that isn't a trusted proxy (the ones before it could be made up by the client).
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    for addr in forwarded.into_iter().rev() {
+        match addr.parse::<IpAddr>() {
+            Ok(ip) if trusted_proxies.contains(&ip) => continue,
+            Ok(ip) => return ip,
+            Err(_) => {
+                debug!("Invalid address in X-Forwarded-For: {:?}", addr);
+                break;
+            }
+        }
+    }
+    peer
+}
+
+/// Check that the user of a request has (at least) a role in a room.
+pub(crate) async fn require_role(
+    database: &dyn Store,
+    room_id: &room::Id,
+    session: &Session,
+    required: Role,
+) -> Result<(), StatusCode> {
+    match database.get_role(room_id, Some(&session.user_id)).await {
+        Ok(role) if role >= required => Ok(()),
+        Ok(role) => {
+            debug!(
+                "User {} ({:?}) isn't a {:?} of room {}",
+                session.user_id, role, required, room_id
+            );
+            Err(StatusCode::FORBIDDEN)
+        }
+        Err(err) => {
+            error!("Failed to get role from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Record an action in the room's moderation log.
+///
+/// The action has already happened, so failing to record it is only logged.
+pub(crate) async fn log_action(
+    state: &AppState,
+    database: &dyn Store,
+    room_id: &room::Id,
+    actor: Option<user::Id>,
+    action: ModAction,
+    target: Option<Snowflake>,
+    reason: Option<String>,
+) {
+    let id = state.next_snowflake();
+    let entry = LogEntry {
+        created_at: id.created_at().into(),
+        id,
+        room_id: room_id.clone(),
+        actor,
+        target,
+        action,
+        reason,
+    };
+
+    if let Err(err) = database.add_log_entry(&entry).await {
+        error!("Failed to add moderation log entry to database: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let headers = forwarded_for("1.2.3.4");
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &[]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn trusted_proxies_forward_the_client() {
+        let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+        // The client can make up the addresses before its own
+        let headers = forwarded_for("6.6.6.6, 1.2.3.4, 10.0.0.2");
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &proxies), ip("1.2.3.4"));
+
+        let headers = HeaderMap::new();
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &proxies),
+            ip("10.0.0.1")
+        );
+        let headers = forwarded_for("1.2.3.4, nonsense");
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &proxies),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[tokio::test]
+    async fn roles_are_required() {
+        let database = crate::model::store::MemoryStore::new();
+        let main = room::main_id();
+        let owner = User {
+            id: Snowflake::try_from(1).unwrap(),
+            name: "alice".to_string(),
+            password: "hash".to_string(),
+        };
+        database.add_user(owner.clone()).await.unwrap();
+        database
+            .set_role(&main, &owner.id, Role::Owner)
+            .await
+            .unwrap();
+        let session = Session::generate(Snowflake::try_from(2).unwrap(), owner.id);
+
+        assert_eq!(
+            require_role(&database, &main, &session, Role::Moderator).await,
+            Ok(())
+        );
+        assert_eq!(
+            require_role(&database, &main, &session, Role::Owner).await,
+            Ok(())
+        );
+
+        let member = Session::generate(
+            Snowflake::try_from(3).unwrap(),
+            Snowflake::try_from(4).unwrap(),
+        );
+        assert_eq!(
+            require_role(&database, &main, &member, Role::Moderator).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+    }
+}
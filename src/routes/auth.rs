@@ -5,11 +5,11 @@ use axum::{
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use log::trace;
+use log::{error, trace};
 
 use crate::{
     auth,
-    model::{session::Token, AppState},
+    model::{session::Token, AppState, Session, Store},
 };
 
 pub async fn authenticate<B>(
@@ -29,8 +29,7 @@ pub async fn authenticate<B>(
         return StatusCode::UNAUTHORIZED.into_response();
     };
 
-    let database = state.database.lock().await;
-    let session = match auth::verify_session(token, database) {
+    let session = match auth::verify_session(token, state.database.as_ref()).await {
         Ok(session) => session,
         Err(crate::auth::verify_session::Error::SessionNotFound) => {
             return StatusCode::UNAUTHORIZED.into_response()
@@ -40,15 +39,34 @@ pub async fn authenticate<B>(
         }
     };
 
+    let session_id = session.id.id();
     request.extensions_mut().insert(session);
 
-    trace!("Request authenticated with a session token of {}", token);
+    trace!("Request authenticated as session {}", session_id);
 
     // Continue
     let response = next.run(request).await;
     response
 }
 
+/// Get the session of a request that doesn't have to be authenticated.
+pub async fn optional_session(
+    cookies: Option<TypedHeader<Cookie>>,
+    database: &dyn Store,
+) -> Result<Option<Session>, StatusCode> {
+    let Some(token) = cookies.and_then(|TypedHeader(cookies)| get_session_token(cookies)) else {
+        return Ok(None);
+    };
+
+    database
+        .get_session_from_token(&token)
+        .await
+        .map_err(|err| {
+            error!("Failed to get session from database: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
 pub fn get_session_token(cookies: Cookie) -> Option<crate::model::session::Token> {
     parse_token(cookies.get("token")?)
 }
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use axum_macros::debug_handler;
+use log::{debug, error};
+
+use crate::model::{user, AppState, Session};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Block {
+    user_id: user::Id,
+}
+
+/// Get the users that the user has blocked.
+#[debug_handler]
+pub async fn get_blocks(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<Json<Vec<user::Id>>, StatusCode> {
+    let database = state.database.as_ref();
+    match database.get_blocked_users(&session.user_id).await {
+        Ok(blocked) => Ok(Json(blocked)),
+        Err(err) => {
+            error!("Failed to get blocked users from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Block a user, hiding their messages from the user.
+#[debug_handler]
+pub async fn block_user(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Json(body): Json<Block>,
+) -> StatusCode {
+    if body.user_id == session.user_id {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let database = state.database.as_ref();
+
+    match database.get_user(&body.user_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            debug!("User {} not found in database", body.user_id);
+            return StatusCode::NOT_FOUND;
+        }
+        Err(err) => {
+            error!("Failed to get user from database: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+
+    if let Err(err) = database.add_block(&session.user_id, &body.user_id).await {
+        error!("Failed to add block to database: {:?}", err);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    state.blocks.add(&session.user_id, &body.user_id);
+
+    StatusCode::NO_CONTENT
+}
+
+#[debug_handler]
+pub async fn unblock_user(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Json(body): Json<Block>,
+) -> StatusCode {
+    let database = state.database.as_ref();
+
+    match database.remove_block(&session.user_id, &body.user_id).await {
+        Ok(true) => {
+            state.blocks.remove(&session.user_id, &body.user_id);
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => {
+            debug!(
+                "User {} hasn't blocked user {}",
+                session.user_id, body.user_id
+            );
+            StatusCode::NOT_FOUND
+        }
+        Err(err) => {
+            error!("Failed to remove block from database: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use axum_macros::debug_handler;
+use log::{debug, error};
+
+use crate::model::{
+    room::{self, DirectRoom, Visibility},
+    user, AppState, Room, Session, Store,
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CreateDirectRoom {
+    /// The other users in the conversation.
+    users: Vec<user::Id>,
+}
+
+/// Start a direct conversation with some users.
+///
+/// If the user is already in a conversation with exactly those users, that one is returned.
+#[debug_handler]
+pub async fn create_direct_room(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Json(body): Json<CreateDirectRoom>,
+) -> Result<Json<DirectRoom>, StatusCode> {
+    let mut members = body.users;
+    members.push(session.user_id.clone());
+    members.sort_by_key(|id| id.id());
+    members.dedup_by_key(|id| id.id());
+    if members.len() < 2 || members.len() > room::MAX_DIRECT_MEMBERS {
+        debug!(
+            "Can't start a direct conversation with {} users",
+            members.len()
+        );
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let database = state.database.as_ref();
+
+    for user_id in &members {
+        match database.get_user(user_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                debug!("User {} not found in database", user_id);
+                return Err(StatusCode::NOT_FOUND);
+            }
+            Err(err) => {
+                error!("Failed to get user from database: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+
+    // Users can't be put in a conversation with someone they blocked
+    for user_id in &members {
+        if let Some(blocked) = members
+            .iter()
+            .find(|other| state.blocks.has_blocked(user_id, other))
+        {
+            debug!("User {} has blocked user {}", user_id, blocked);
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+
+    for direct_room in get_direct_rooms(database, &session).await? {
+        let same_members = direct_room.members.len() == members.len()
+            && direct_room
+                .members
+                .iter()
+                .zip(&members)
+                .all(|(a, b)| a.id() == b.id());
+        if same_members {
+            return Ok(Json(direct_room));
+        }
+    }
+
+    let id = state.next_snowflake();
+    let room = Room {
+        name: format!("{}{}", room::DIRECT_NAME_PREFIX, id),
+        id,
+        anonymous_reactions: false,
+        visibility: Visibility::Direct,
+    };
+
+    if let Err(err) = database.add_room(&room).await {
+        error!("Failed to add room to database: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    for user_id in &members {
+        if let Err(err) = database.add_member(&room.id, user_id).await {
+            error!("Failed to add member to room: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    Ok(Json(DirectRoom { room, members }))
+}
+
+/// Get the direct conversations that the user is in, newest first.
+#[debug_handler]
+pub async fn get_direct_rooms_of_user(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<Json<Vec<DirectRoom>>, StatusCode> {
+    let database = state.database.as_ref();
+    get_direct_rooms(database, &session).await.map(Json)
+}
+
+async fn get_direct_rooms(
+    database: &dyn Store,
+    session: &Session,
+) -> Result<Vec<DirectRoom>, StatusCode> {
+    let rooms = database
+        .get_direct_rooms(&session.user_id)
+        .await
+        .map_err(|err| {
+            error!("Failed to get direct rooms from database: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut direct_rooms = Vec::with_capacity(rooms.len());
+    for room in rooms {
+        match database.get_members(&room.id).await {
+            Ok(members) => direct_rooms.push(DirectRoom { room, members }),
+            Err(err) => {
+                error!("Failed to get members of room from database: {:?}", err);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+    Ok(direct_rooms)
+}
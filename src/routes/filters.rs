@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use log::{debug, error};
+
+use crate::{
+    content_filter,
+    model::{
+        filter::{self, Action, Rule},
+        moderation::ModAction,
+        room, AppState, Role, Session,
+    },
+};
+
+use super::{log_action, require_role};
+
+/// Get a room's blocklist. Only the room's owners can read it.
+#[debug_handler]
+pub async fn get_filter_rules(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(room_id): Path<room::Id>,
+) -> Result<Json<Vec<Rule>>, StatusCode> {
+    let database = state.database.as_ref();
+
+    require_role(database, &room_id, &session, Role::Owner).await?;
+
+    match database.get_filter_rules(Some(&room_id)).await {
+        Ok(rules) => Ok(Json(rules)),
+        Err(err) => {
+            error!("Failed to get filter rules from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct AddRule {
+    pattern: String,
+    action: Action,
+}
+
+/// Add a regex to a room's blocklist.
+#[debug_handler]
+pub async fn add_filter_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(room_id): Path<room::Id>,
+    Json(body): Json<AddRule>,
+) -> Result<Json<Rule>, StatusCode> {
+    if body.pattern.is_empty() || body.pattern.len() > filter::MAX_PATTERN_LEN {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let regex = match content_filter::compile(&body.pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            debug!("Invalid filter pattern {:?}: {}", body.pattern, err);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    let database = state.database.as_ref();
+
+    require_role(database, &room_id, &session, Role::Owner).await?;
+
+    let rule = Rule {
+        id: state.next_snowflake(),
+        room_id,
+        pattern: body.pattern,
+        action: body.action,
+    };
+
+    if let Err(err) = database.add_filter_rule(&rule).await {
+        error!("Failed to add filter rule to database: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    state.content_filters.blocklist().add(rule.clone(), regex);
+    log_action(
+        &state,
+        database,
+        &rule.room_id,
+        Some(session.user_id.clone()),
+        ModAction::AddFilterRule,
+        Some(rule.id.clone()),
+        Some(describe(&rule)),
+    )
+    .await;
+
+    Ok(Json(rule))
+}
+
+/// Remove a regex from a room's blocklist.
+#[debug_handler]
+pub async fn remove_filter_rule(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path((room_id, rule_id)): Path<(room::Id, filter::Id)>,
+) -> StatusCode {
+    let database = state.database.as_ref();
+
+    if let Err(status) = require_role(database, &room_id, &session, Role::Owner).await {
+        return status;
+    }
+
+    let rule = match database.get_filter_rules(Some(&room_id)).await {
+        Ok(rules) => rules.into_iter().find(|rule| rule.id == rule_id),
+        Err(err) => {
+            error!("Failed to get filter rules from database: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let Some(rule) = rule else {
+        debug!("Filter rule {} not found in room {}", rule_id, room_id);
+        return StatusCode::NOT_FOUND;
+    };
+
+    if let Err(err) = database.remove_filter_rule(&room_id, &rule.id).await {
+        error!("Failed to remove filter rule from database: {:?}", err);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    state.content_filters.blocklist().remove(&room_id, &rule.id);
+    log_action(
+        &state,
+        database,
+        &room_id,
+        Some(session.user_id.clone()),
+        ModAction::RemoveFilterRule,
+        Some(rule.id.clone()),
+        Some(describe(&rule)),
+    )
+    .await;
+
+    StatusCode::NO_CONTENT
+}
+
+/// How a rule is described in the moderation log.
+fn describe(rule: &Rule) -> String {
+    format!("{:?} /{}/", rule.action, rule.pattern)
+}
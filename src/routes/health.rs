@@ -0,0 +1,146 @@
+use crate::model::{database::migrations, AppState};
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+};
+use axum_macros::debug_handler;
+use log::warn;
+use std::{sync::Arc, time::Instant};
+
+#[derive(Debug, serde::Serialize)]
+pub struct Health {
+    status: &'static str,
+    version: &'static str,
+}
+
+/// Whether the server is live, which it is if it can answer.
+#[debug_handler]
+pub async fn healthz() -> Json<Health> {
+    Json(Health {
+        status: "live",
+        version: env!("CARGO_PKG_VERSION"),
+    })
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Readiness {
+    ready: bool,
+    database: DatabaseCheck,
+    migrations: MigrationsCheck,
+    snowcloud: Check,
+    shutting_down: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Check {
+    ok: bool,
+    /// Why the check failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DatabaseCheck {
+    ok: bool,
+    /// How long the database took to answer.
+    millis: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct MigrationsCheck {
+    ok: bool,
+    /// The version of the database's schema (`None` if the store doesn't have one).
+    version: Option<u32>,
+    /// The version it should be at.
+    latest: Option<u32>,
+}
+
+/// Whether the server is ready for traffic: the database answers within the timeout (with the
+/// latest schema), snowflakes can be generated, and it isn't shutting down.
+///
+/// It's `503 Service Unavailable` if not, with the same details.
+#[debug_handler]
+pub async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    let timeout = state.health.database_timeout;
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, state.database.schema_version()).await;
+    let millis = start.elapsed().as_millis();
+
+    let (database, version) = match result {
+        Ok(Ok(version)) => (
+            DatabaseCheck {
+                ok: true,
+                millis,
+                error: None,
+            },
+            Some(version),
+        ),
+        Ok(Err(err)) => (
+            DatabaseCheck {
+                ok: false,
+                millis,
+                error: Some(err.to_string()),
+            },
+            None,
+        ),
+        Err(_) => (
+            DatabaseCheck {
+                ok: false,
+                millis,
+                error: Some(format!("didn't answer within {:?}", timeout)),
+            },
+            None,
+        ),
+    };
+
+    let migrations = match version {
+        Some(Some(version)) => MigrationsCheck {
+            ok: version == migrations::latest_version(),
+            version: Some(version),
+            latest: Some(migrations::latest_version()),
+        },
+        // The store doesn't have migrations
+        Some(None) => MigrationsCheck {
+            ok: true,
+            version: None,
+            latest: None,
+        },
+        // They can't be checked without the database
+        None => MigrationsCheck {
+            ok: false,
+            version: None,
+            latest: Some(migrations::latest_version()),
+        },
+    };
+
+    let snowcloud = match state.snowcloud.next_id() {
+        Ok(_) => Check {
+            ok: true,
+            error: None,
+        },
+        Err(err) => Check {
+            ok: false,
+            error: Some(err.to_string()),
+        },
+    };
+
+    let shutting_down = state.shutdown.is_started();
+
+    let ready = database.ok && migrations.ok && snowcloud.ok && !shutting_down;
+    let readiness = Readiness {
+        ready,
+        database,
+        migrations,
+        snowcloud,
+        shutting_down,
+    };
+
+    if ready {
+        (StatusCode::OK, Json(readiness))
+    } else {
+        warn!("Not ready: {:?}", readiness);
+        (StatusCode::SERVICE_UNAVAILABLE, Json(readiness))
+    }
+}
@@ -0,0 +1,166 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use log::{debug, error};
+
+use crate::{
+    auth,
+    model::{
+        invite::{self, Invite},
+        room, AppState, Role, Room, Session, Timestamp,
+    },
+};
+
+use super::require_role;
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct CreateInvite {
+    /// How many times the invite can be used. If not given, there is no limit.
+    max_uses: Option<u32>,
+    /// How many seconds until the invite expires. If not given, it doesn't.
+    expires_in: Option<u64>,
+}
+
+/// Create an invite to a room.
+#[debug_handler]
+pub async fn create_invite(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(room_id): Path<room::Id>,
+    body: Option<Json<CreateInvite>>,
+) -> Result<Json<Invite>, StatusCode> {
+    let Json(body) = body.unwrap_or_default();
+    if body.max_uses == Some(0) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let database = state.database.as_ref();
+
+    require_role(database, &room_id, &session, Role::Moderator).await?;
+
+    let invite = Invite {
+        token: auth::token::generate_invite_token(),
+        room_id,
+        creator: session.user_id,
+        expires_at: body
+            .expires_in
+            .map(|secs| Timestamp::after(Duration::from_secs(secs))),
+        max_uses: body.max_uses,
+        uses: 0,
+    };
+
+    match database.add_invite(&invite).await {
+        Ok(()) => Ok(Json(invite)),
+        Err(err) => {
+            error!("Failed to add invite to database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn get_invites(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(room_id): Path<room::Id>,
+) -> Result<Json<Vec<Invite>>, StatusCode> {
+    let database = state.database.as_ref();
+
+    require_role(database, &room_id, &session, Role::Moderator).await?;
+
+    match database.get_invites(&room_id).await {
+        Ok(invites) => Ok(Json(invites)),
+        Err(err) => {
+            error!("Failed to get invites from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn delete_invite(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path((room_id, token)): Path<(room::Id, invite::Token)>,
+) -> StatusCode {
+    let database = state.database.as_ref();
+
+    if let Err(status) = require_role(database, &room_id, &session, Role::Moderator).await {
+        return status;
+    }
+
+    match database.delete_invite(&room_id, &token).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            error!("Failed to delete invite from database: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Use an invite, making the user a member of its room.
+#[debug_handler]
+pub async fn accept_invite(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(token): Path<invite::Token>,
+) -> Result<Json<Room>, StatusCode> {
+    let database = state.database.as_ref();
+
+    let invite = match database.get_invite(&token).await {
+        Ok(Some(invite)) => invite,
+        Ok(None) => {
+            debug!("Invite not found in database");
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get invite from database: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let room = match database.get_room(&invite.room_id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            error!("Failed to get room from database: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    // Members don't use up the invite
+    match database.is_member(&room.id, &session.user_id).await {
+        Ok(true) => return Ok(Json(room)),
+        Ok(false) => {}
+        Err(err) => {
+            error!("Failed to get membership from database: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    match database.use_invite(&token).await {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!("Invite to room {} has expired or run out of uses", room.id);
+            return Err(StatusCode::GONE);
+        }
+        Err(err) => {
+            error!("Failed to use invite in database: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    match database.add_member(&room.id, &session.user_id).await {
+        Ok(()) => Ok(Json(room)),
+        Err(err) => {
+            error!("Failed to add member to database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
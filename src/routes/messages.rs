@@ -1,22 +1,71 @@
 use std::sync::Arc;
 
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{extract::State, headers::Cookie, http::StatusCode, Json, TypedHeader};
 use axum_macros::debug_handler;
 use log::error;
 
-use crate::model::{AppState, Message};
+use crate::model::{room, AppState, Message};
+
+use super::rooms;
 
 #[debug_handler]
 pub async fn get_snapshot(
     State(state): State<Arc<AppState>>,
+    cookies: Option<TypedHeader<Cookie>>,
 ) -> Result<Json<Vec<Message>>, StatusCode> {
     // Fetch the last 100 messages from the database
-    let database = state.database.lock().await;
-    match database.get_recent_messages() {
-        Ok(messages) => Ok(Json(messages)),
+    let database = state.database.as_ref();
+    // The main room can be made private too
+    let user_id = rooms::check_access(database, &room::main_id(), cookies).await?;
+    match database.get_recent_messages(&room::main_id()).await {
+        Ok(mut messages) => {
+            state.blocks.collapse(user_id.as_ref(), &mut messages);
+            Ok(Json(messages))
+        }
         Err(err) => {
             error!("Failed to get messages from database: {:?}", err);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        model::{room::Visibility, store::Backend},
+    };
+
+    async fn state() -> Arc<AppState> {
+        let config = Config {
+            store: Backend::Memory,
+            ..Config::default()
+        };
+        Arc::new(AppState::new(&config).await)
+    }
+
+    #[tokio::test]
+    async fn snapshots_of_the_main_room() {
+        let state = state().await;
+        let snapshot = get_snapshot(State(state), None).await;
+        assert!(matches!(snapshot, Ok(Json(messages)) if messages.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn no_snapshots_of_a_private_main_room() {
+        let state = state().await;
+        let settings = room::Settings {
+            anonymous_reactions: false,
+            visibility: Some(Visibility::Private),
+        };
+        state
+            .database
+            .update_room_settings(&room::main_id(), &settings)
+            .await
+            .unwrap();
+
+        let snapshot = get_snapshot(State(state), None).await;
+        assert!(matches!(snapshot, Err(StatusCode::NOT_FOUND)));
+    }
+}
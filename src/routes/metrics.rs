@@ -0,0 +1,54 @@
+use crate::{metrics, model::AppState};
+use axum::{
+    extract::{ConnectInfo, State, TypedHeader},
+    headers::{authorization::Bearer, Authorization},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_macros::debug_handler;
+use log::{debug, error};
+use std::{net::SocketAddr, sync::Arc};
+
+/// The Prometheus metrics, if they're enabled and the request is allowed to get them.
+#[debug_handler]
+pub async fn metrics(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Response {
+    let config = &state.metrics;
+    if !config.enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    // Behind a reverse proxy on the same host, every peer is loopback
+    let ip = super::client_ip(addr.ip(), &headers, &state.trusted_proxies);
+    let allowed = match authorization {
+        _ if config.token.is_empty() => ip.is_loopback(),
+        Some(TypedHeader(authorization)) => tokens_match(&config.token, authorization.token()),
+        None => false,
+    };
+    if !allowed {
+        debug!("Refusing to send metrics to {}", ip);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match metrics::render() {
+        Ok(text) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response(),
+        Err(err) => {
+            error!("Failed to render metrics: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Compare tokens in a time that doesn't depend on where they differ.
+fn tokens_match(expected: &str, got: &str) -> bool {
+    expected.len() == got.len()
+        && expected
+            .bytes()
+            .zip(got.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use axum_macros::debug_handler;
+use log::error;
+
+use crate::model::{message, AppState, Mention, Session};
+
+#[debug_handler]
+pub async fn get_notifications(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<Json<Vec<Mention>>, StatusCode> {
+    let database = state.database.as_ref();
+    match database.get_unread_mentions(&session.user_id).await {
+        Ok(mut mentions) => {
+            // Mentions by users that were blocked after mentioning are dropped
+            mentions.retain(|mention| {
+                !state
+                    .blocks
+                    .has_blocked(&session.user_id, &mention.message.author)
+            });
+            Ok(Json(mentions))
+        }
+        Err(err) => {
+            error!("Failed to get mentions from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ReadNotifications {
+    /// Mark everything up to (and including) this message as read.
+    up_to: message::Id,
+}
+
+#[debug_handler]
+pub async fn read_notifications(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Json(body): Json<ReadNotifications>,
+) -> StatusCode {
+    let database = state.database.as_ref();
+    match database
+        .mark_mentions_read(&session.user_id, &body.up_to)
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(err) => {
+            error!("Failed to mark mentions as read: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -6,7 +6,7 @@ use log::{error, info, warn};
 
 use crate::{
     auth,
-    model::{AppState, Snowflake, User},
+    model::{room, AppState, Role, Snowflake, User},
 };
 
 #[derive(Debug, serde::Deserialize)]
@@ -42,14 +42,32 @@ pub async fn register(
         password,
     };
 
-    let database = state.database.lock().await;
+    let database = state.database.as_ref();
 
-    if let Err(err) = database.add_user(user) {
+    if let Err(err) = database.add_user(user).await {
         error!("Failed to add user to database: {:?}", err);
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
 
     info!("User {} created.", snowflake.id());
 
+    // The first user owns the main room (which was made without an owner)
+    match database.count_users().await {
+        Ok(1) => {
+            match database
+                .set_role(&room::main_id(), &snowflake, Role::Owner)
+                .await
+            {
+                Ok(()) => info!("User {} is the owner of the main room.", snowflake.id()),
+                Err(err) => error!(
+                    "Failed to make the first user the owner of the main room: {:?}",
+                    err
+                ),
+            }
+        }
+        Ok(_) => {}
+        Err(err) => error!("Failed to count users: {:?}", err),
+    }
+
     Ok(snowflake.id().to_string())
 }
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use log::{debug, error};
+
+use crate::model::{
+    moderation::ModAction,
+    report::{self, Status},
+    room, AppState, Message, Report, Role, Session, Snowflake, Store,
+};
+
+use super::{log_action, require_role};
+
+/// How many reports are returned if the amount isn't given.
+const DEFAULT_AMOUNT: u8 = 50;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ReportsPage {
+    /// Only get reports with this status. If not given, the reports that are still pending.
+    status: Option<Status>,
+    before: Option<Snowflake>,
+    amount: Option<u8>,
+}
+
+/// Get the reports in a room, newest first. Only the room's moderators can read them.
+#[debug_handler]
+pub async fn get_reports(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(room_id): Path<room::Id>,
+    Query(page): Query<ReportsPage>,
+) -> Result<Json<Vec<Report>>, StatusCode> {
+    let database = state.database.as_ref();
+
+    require_role(database, &room_id, &session, Role::Moderator).await?;
+
+    let amount = page.amount.unwrap_or(DEFAULT_AMOUNT);
+    match database
+        .get_reports(&room_id, page.status, page.before, amount)
+        .await
+    {
+        Ok(reports) => Ok(Json(reports)),
+        Err(err) => {
+            error!("Failed to get reports from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Get the messages in a room that were held for review by a content filter, oldest first.
+///
+/// Only the room's moderators can read them. They're approved over the websocket (`Approve`),
+/// or deleted.
+#[debug_handler]
+pub async fn get_held_messages(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(room_id): Path<room::Id>,
+) -> Result<Json<Vec<Message>>, StatusCode> {
+    let database = state.database.as_ref();
+
+    require_role(database, &room_id, &session, Role::Moderator).await?;
+
+    match database.get_held_messages(&room_id).await {
+        Ok(messages) => Ok(Json(messages)),
+        Err(err) => {
+            error!("Failed to get held messages from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Claim an open report, so other moderators know it's being looked into.
+#[debug_handler]
+pub async fn claim_report(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<report::Id>,
+) -> Result<Json<Report>, StatusCode> {
+    let database = state.database.as_ref();
+
+    let report = get_report(database, &id, &session).await?;
+    if report.status != Status::Open {
+        debug!("Report {} isn't open ({:?})", id, report.status);
+        return Err(StatusCode::CONFLICT);
+    }
+
+    update_status(database, report, Status::Claimed, &session)
+        .await
+        .map(Json)
+}
+
+#[debug_handler]
+pub async fn resolve_report(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<report::Id>,
+) -> Result<Json<Report>, StatusCode> {
+    close_report(&state, &session, &id, Status::Resolved).await
+}
+
+#[debug_handler]
+pub async fn dismiss_report(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<report::Id>,
+) -> Result<Json<Report>, StatusCode> {
+    close_report(&state, &session, &id, Status::Dismissed).await
+}
+
+/// Resolve or dismiss a report that is still pending, and record it in the moderation log.
+async fn close_report(
+    state: &AppState,
+    session: &Session,
+    id: &report::Id,
+    status: Status,
+) -> Result<Json<Report>, StatusCode> {
+    let database = state.database.as_ref();
+
+    let report = get_report(database, id, session).await?;
+    if !report.status.is_pending() {
+        debug!("Report {} was already closed ({:?})", id, report.status);
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let report = update_status(database, report, status, session).await?;
+
+    let action = match status {
+        Status::Dismissed => ModAction::DismissReport,
+        _ => ModAction::ResolveReport,
+    };
+    log_action(
+        state,
+        database,
+        &report.room_id,
+        Some(session.user_id.clone()),
+        action,
+        Some(report.id.clone()),
+        None,
+    )
+    .await;
+
+    Ok(Json(report))
+}
+
+/// Get a report, making sure that the user is a moderator of its room.
+async fn get_report(
+    database: &dyn Store,
+    id: &report::Id,
+    session: &Session,
+) -> Result<Report, StatusCode> {
+    let report = match database.get_report(id).await {
+        Ok(Some(report)) => report,
+        Ok(None) => {
+            debug!("Report {} not found in database", id);
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Err(err) => {
+            error!("Failed to get report from database: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    require_role(database, &report.room_id, session, Role::Moderator).await?;
+    Ok(report)
+}
+
+async fn update_status(
+    database: &dyn Store,
+    mut report: Report,
+    status: Status,
+    session: &Session,
+) -> Result<Report, StatusCode> {
+    match database
+        .update_report_status(&report.id, status, &session.user_id)
+        .await
+    {
+        Ok(()) => {
+            report.status = status;
+            report.moderator = Some(session.user_id.clone());
+            Ok(report)
+        }
+        Err(err) => {
+            error!("Failed to update report in database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
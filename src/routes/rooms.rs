@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    headers::Cookie,
+    http::StatusCode,
+    Extension, Json, TypedHeader,
+};
+use axum_macros::debug_handler;
+use log::{debug, error};
+
+use crate::model::{
+    moderation::LogEntry,
+    room::{self, Visibility},
+    search::{SearchQuery, SearchResult},
+    user, AppState, Message, Role, Room, Session, Snowflake, Store, Timestamp,
+};
+
+use super::{auth, require_role};
+
+/// How many moderation log entries are returned if the amount isn't given.
+const DEFAULT_MOD_LOG_AMOUNT: u8 = 50;
+/// How many messages are returned by time if the limit isn't given.
+const DEFAULT_MESSAGES_LIMIT: u8 = 100;
+/// The most messages that are returned by time.
+const MAX_MESSAGES_LIMIT: u8 = 200;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CreateRoom {
+    name: String,
+    #[serde(default)]
+    visibility: Visibility,
+}
+
+/// Create a room. The user that creates it becomes its owner.
+#[debug_handler]
+pub async fn create_room(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Json(body): Json<CreateRoom>,
+) -> Result<Json<Room>, StatusCode> {
+    let name = body.name.trim().to_string();
+    if name.is_empty() || name.contains('/') || name.starts_with(room::DIRECT_NAME_PREFIX) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Direct conversations are started with `POST /api/dm`
+    if body.visibility == Visibility::Direct {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let database = state.database.as_ref();
+
+    match database.get_room_by_name(&name).await {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            debug!("Room {} already exists", name);
+            return Err(StatusCode::CONFLICT);
+        }
+        Err(err) => {
+            error!("Failed to get room from database: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let room = Room {
+        id: state.next_snowflake(),
+        name,
+        anonymous_reactions: false,
+        visibility: body.visibility,
+    };
+
+    if let Err(err) = database.add_room(&room).await {
+        error!("Failed to add room to database: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    if let Err(err) = database
+        .set_role(&room.id, &session.user_id, Role::Owner)
+        .await
+    {
+        error!("Failed to make user the owner of room: {:?}", err);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    Ok(Json(room))
+}
+
+/// Get the public rooms, by name.
+#[debug_handler]
+pub async fn get_rooms(State(state): State<Arc<AppState>>) -> Result<Json<Vec<Room>>, StatusCode> {
+    let database = state.database.as_ref();
+    match database.get_public_rooms().await {
+        Ok(rooms) => Ok(Json(rooms)),
+        Err(err) => {
+            error!("Failed to get rooms from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn search(
+    State(state): State<Arc<AppState>>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Path(room_id): Path<room::Id>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<SearchResult>>, StatusCode> {
+    let database = state.database.as_ref();
+
+    let user_id = check_access(database, &room_id, cookies).await?;
+    match database.search_messages(&room_id, &query).await {
+        Ok(mut results) => {
+            state.blocks.filter_results(user_id.as_ref(), &mut results);
+            Ok(Json(results))
+        }
+        Err(err) => {
+            error!("Failed to search messages: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TimeRange {
+    from: Timestamp,
+    to: Timestamp,
+    limit: Option<u32>,
+}
+
+#[debug_handler]
+pub async fn get_messages_between(
+    State(state): State<Arc<AppState>>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Path(room_id): Path<room::Id>,
+    Query(range): Query<TimeRange>,
+) -> Result<Json<Vec<Message>>, StatusCode> {
+    if range.from > range.to {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let database = state.database.as_ref();
+
+    let user_id = check_access(database, &room_id, cookies).await?;
+    match database
+        .get_messages_between(
+            &room_id,
+            range.from.0,
+            range.to.0,
+            range.limit.map_or(DEFAULT_MESSAGES_LIMIT, |limit| {
+                limit.min(MAX_MESSAGES_LIMIT.into()) as u8
+            }),
+        )
+        .await
+    {
+        Ok(mut messages) => {
+            state.blocks.collapse(user_id.as_ref(), &mut messages);
+            Ok(Json(messages))
+        }
+        Err(err) => {
+            error!("Failed to get messages from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ModLogPage {
+    before: Option<Snowflake>,
+    amount: Option<u8>,
+}
+
+/// Get a room's moderation log, newest first. Only the room's owners can read it.
+#[debug_handler]
+pub async fn get_mod_log(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(room_id): Path<room::Id>,
+    Query(page): Query<ModLogPage>,
+) -> Result<Json<Vec<LogEntry>>, StatusCode> {
+    let database = state.database.as_ref();
+
+    require_role(database, &room_id, &session, Role::Owner).await?;
+
+    let amount = page.amount.unwrap_or(DEFAULT_MOD_LOG_AMOUNT);
+    match database.get_mod_log(&room_id, page.before, amount).await {
+        Ok(entries) => Ok(Json(entries)),
+        Err(err) => {
+            error!("Failed to get moderation log from database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Check that the (maybe logged in) user of a request can see a room.
+///
+/// Rooms they can't see are treated as if they don't exist. Returns the user, if they're logged in.
+pub(super) async fn check_access(
+    database: &dyn Store,
+    room_id: &room::Id,
+    cookies: Option<TypedHeader<Cookie>>,
+) -> Result<Option<user::Id>, StatusCode> {
+    let room = match database.get_room(room_id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            error!("Failed to get room from database: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let user_id = auth::optional_session(cookies, database)
+        .await?
+        .map(|session| session.user_id);
+    match database.can_access_room(&room, user_id.as_ref()).await {
+        Ok(true) => Ok(user_id),
+        Ok(false) => {
+            debug!("Request can't access room {}", room_id);
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(err) => {
+            error!("Failed to check room access in database: {:?}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
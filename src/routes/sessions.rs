@@ -1,5 +1,5 @@
 use crate::{
-    auth,
+    auth, metrics,
     model::{AppState, Session},
 };
 use axum::{
@@ -26,11 +26,12 @@ pub async fn login(
     debug!("Got login request for user: {}", user_body.name);
 
     // Get id
-    let db = state.database.lock().await;
-    let user_db = match db.get_user_by_name(&user_body.name) {
+    let db = state.database.as_ref();
+    let user_db = match db.get_user_by_name(&user_body.name).await {
         Ok(Some(user)) => user,
         Ok(None) => {
             debug!("User not found: {}", user_body.name);
+            metrics::login(false);
             return StatusCode::NOT_FOUND.into_response();
         }
         Err(err) => {
@@ -40,9 +41,17 @@ pub async fn login(
     };
 
     // Check password
-    if !auth::hash::check_passwords(user_body.password, user_db.password) {
-        debug!("Password incorrect for user: {}", user_db.name);
-        return StatusCode::UNAUTHORIZED.into_response();
+    match auth::hash::check_passwords(user_body.password, user_db.password) {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!("Password incorrect for user: {}", user_db.name);
+            metrics::login(false);
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        Err(err) => {
+            error!("Invalid password hash for user {}: {}", user_db.id, err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
     }
 
     // Generate token
@@ -53,14 +62,14 @@ pub async fn login(
     debug!("Logging in user with session {}", session.id.id());
 
     // Add token to database
-    if let Err(err) = db.add_session(session) {
+    if let Err(err) = db.add_session(session).await {
         error!("Failed to add token to database: {}", err);
         return StatusCode::INTERNAL_SERVER_ERROR.into_response();
     }
 
+    metrics::login(true);
     let cookie = make_cookie(token);
-    let response = make_response(cookie);
-    response
+    make_response(cookie)
 }
 
 fn make_cookie(token: crate::model::session::Token) -> String {
@@ -88,10 +97,10 @@ pub async fn logout(
 ) -> StatusCode {
     debug!("Logging out session: {:?}", session.id.id());
 
-    let database = state.database.lock().await;
+    let database = state.database.as_ref();
     let id = session.id;
 
-    match database.delete_session(&id) {
+    match database.delete_session(&id).await {
         Ok(_) => StatusCode::RESET_CONTENT,
         Err(err) => {
             error!("Failed to delete session from database: {}", err);
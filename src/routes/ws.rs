@@ -1,21 +1,25 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::{ws::WebSocket, Path, State, WebSocketUpgrade},
+    extract::{ws::WebSocket, ConnectInfo, Path, State, WebSocketUpgrade},
     headers::Cookie,
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     routing::get,
     Router, TypedHeader,
 };
 use axum_macros::debug_handler;
 use futures::StreamExt;
-use log::trace;
-use tokio::sync::broadcast;
+use log::{debug, error, trace};
+use tokio::sync::{broadcast, watch};
 
 use crate::{
-    auth,
-    model::{AppState, Message, Session},
+    auth, logger, metrics,
+    model::{
+        message, reaction::ReactionsChanged, report, role::Permission, search::SearchResult, user,
+        AppState, Message, Report, Role, Room, Session, Snowflake, Timestamp,
+    },
+    presences::Online,
     routes::ws::broadcast_handler::broadcast_handler,
 };
 
@@ -44,21 +48,26 @@ pub fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
 
 #[debug_handler]
 async fn handler(
-    TypedHeader(cookies): TypedHeader<Cookie>,
+    cookies: Option<TypedHeader<Cookie>>,
     Path(room_id): Path<crate::model::room::Id>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
     State(state): State<Arc<WsState>>,
 ) -> Response {
     trace!("ws connection requested");
 
-    let database = state.appstate.database.lock().await;
-    let session = match crate::routes::auth::get_session_token(cookies) {
-        Some(token) => match auth::verify_session(token, database) {
+    if state.appstate.shutdown.is_started() {
+        debug!("Refusing ws connection, because the server is shutting down");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    let token =
+        cookies.and_then(|TypedHeader(cookies)| crate::routes::auth::get_session_token(cookies));
+    let session = match token {
+        Some(token) => match auth::verify_session(token, state.appstate.database.as_ref()).await {
             Ok(session) => {
-                trace!(
-                    "Request authenticated with a session token of {}",
-                    session.token
-                );
+                trace!("Request authenticated as session {}", session.id.id());
                 Some(session)
             }
             Err(crate::auth::verify_session::Error::SessionNotFound) => {
@@ -75,12 +84,53 @@ async fn handler(
         }
     };
 
+    let database = state.appstate.database.as_ref();
+    let user_id = session.as_ref().map(|session| &session.user_id);
+    let ip = super::client_ip(addr.ip(), &headers, &state.appstate.trusted_proxies);
+
+    // Check that the room exists, and that they can join it
+    let room = match database.get_room(&room_id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            error!("Failed to get room from database: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match database.can_access_room(&room, user_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!("Client can't access private room {}", room_id);
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Err(err) => {
+            error!("Failed to check room access in database: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    // Check that they aren't banned
+    match database.get_active_ban(&room_id, user_id, ip).await {
+        Ok(None) => {}
+        Ok(Some(ban)) => {
+            debug!(
+                "Banned client tried to join room {} (ban {})",
+                room_id, ban.id
+            );
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        Err(err) => {
+            error!("Failed to get bans from database: {:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
     // Attempt to resolve name
-    let database = state.appstate.database.lock().await;
     // FIXME: This feels unnecessarily complicated
     let name = if let Some(session) = &session {
         database
             .get_user_name(&session.user_id)
+            .await
             .unwrap_or(Some("Anonymous".to_string()))
             .unwrap_or("Anonymous".to_string())
     } else {
@@ -89,13 +139,21 @@ async fn handler(
 
     let presence = Presence {
         id: state.appstate.next_snowflake(),
-        session: session,
+        session,
         name,
+        ip,
     };
 
     let appstate = state.appstate.clone();
     let tx = state.tx.clone();
-    ws.on_upgrade(move |ws| handle_ws(ws, appstate, presence, tx, room_id))
+    let max_frame_bytes = appstate.limits.max_ws_frame_bytes;
+    ws.max_frame_size(max_frame_bytes)
+        .max_message_size(max_frame_bytes)
+        .on_upgrade(move |ws| {
+            // The connection keeps the span of the request that opened it
+            let span = logger::current_span().unwrap_or_else(logger::new_span);
+            logger::in_span(span, handle_ws(ws, appstate, presence, tx, room_id))
+        })
 }
 
 // Naming note (for types and variables):
@@ -106,7 +164,13 @@ async fn handler(
 // - Use `message` when you're referring to a
 //   message in the database/chat
 
-async fn handle_ws(ws: WebSocket, state: Arc<AppState>, presence: Presence, tx: Sender, room_id: crate::model::room::Id) {
+async fn handle_ws(
+    ws: WebSocket,
+    state: Arc<AppState>,
+    presence: Presence,
+    tx: Sender,
+    room_id: crate::model::room::Id,
+) {
     trace!("ws connection opened");
 
     let id = state.next_snowflake().id();
@@ -115,12 +179,41 @@ async fn handle_ws(ws: WebSocket, state: Arc<AppState>, presence: Presence, tx:
     let (sender, receiver) = ws.split();
     let rx = tx.subscribe();
 
-    // Send messages
-    let mut send_task = tokio::spawn(broadcast_handler(rx, id, sender));
+    state.presences.insert(Online {
+        id: presence.id.clone(),
+        room_id: room_id.clone(),
+        user_id: presence.user_id(),
+        ip: presence.ip,
+    });
+
+    // The server waits for this to be dropped when shutting down
+    let shutdown = state.shutdown.connection();
+    let _connection = metrics::Connection::open(&room_id);
 
-    let tx = tx.clone();
+    // Keep track of the latest presence (e.g. who it's authenticated as),
+    // so messages for a specific user can be sent to it
+    let (presence_tx, presence_rx) = watch::channel(presence.clone());
+
+    // Send messages
+    let mut send_task = logger::spawn(broadcast_handler(
+        rx,
+        id,
+        room_id.clone(),
+        presence_rx.clone(),
+        state.blocks.clone(),
+        state.shutdown.clone(),
+        sender,
+    ));
 
-    let mut recv_task = tokio::spawn(recv::recv_ws(receiver, presence, state, id, tx, room_id));
+    let mut recv_task = logger::spawn(recv::recv_ws(
+        receiver,
+        presence,
+        state.clone(),
+        id,
+        tx.clone(),
+        presence_tx,
+        room_id.clone(),
+    ));
 
     // If any one of the tasks run to completion, we abort the other.
     tokio::select! {
@@ -128,23 +221,144 @@ async fn handle_ws(ws: WebSocket, state: Arc<AppState>, presence: Presence, tx:
         _ = (&mut recv_task) => send_task.abort(),
     };
 
+    // Send leave message
+    let presence = presence_rx.borrow().clone();
+    state.presences.remove(&presence.id);
+
+    let msg = BroadcastMsg {
+        target: broadcast_msg::Target::Room(room_id),
+        content: ServerMsg::Leave(presence),
+    };
+    if let Err(err) = tx.send(msg) {
+        debug!("Failed to send leave message: {}", err);
+    }
+    drop(shutdown);
+
     trace!("ws connection closed");
 }
 
 #[derive(Clone, Debug, serde::Serialize)]
 pub enum ServerMsg {
-    Authenticate { success: bool, presence_id: String },
+    Authenticate {
+        success: bool,
+        presence_id: String,
+    },
     NewMessage(Message),
+    Mentioned(Message),
+    /// A message was sent in a direct conversation that you're in.
+    /// Sent to all of your connections, whichever room they're in.
+    DirectMessage(Message),
     Error,
     Messages(Vec<Message>),
     Duplicate(String),
+    /// Too many messages were sent. `retry_after` is in milliseconds.
+    RateLimited {
+        retry_after: u64,
+    },
+    MessageRejected {
+        dedup_id: Option<String>,
+        reason: RejectReason,
+    },
     Join(Presence),
     Leave(Presence),
     Update(Presence),
+    ReactionsChanged(ReactionsChanged),
+    SearchResults(Vec<SearchResult>),
+    MessageEdited(Message),
+    MessageDeleted(message::Id),
+    RoomUpdated(Room),
+    RoleChanged {
+        user_id: user::Id,
+        role: Role,
+    },
+    PermissionDenied(Permission),
+    /// Your message was held for review by a content filter.
+    /// It's sent to the room once a moderator approves it.
+    MessageHeld {
+        dedup_id: Option<String>,
+        message_id: message::Id,
+    },
+    /// A message was held for review. Only sent to moderators.
+    HeldForReview(Message),
+    /// Your report was filed.
+    Reported(report::Id),
+    /// A message in the room was reported. Only sent to moderators.
+    ReportFiled(Report),
+    /// You can't post, because you've been silenced (until the given time, if any).
+    Silenced {
+        until: Option<Timestamp>,
+    },
+    /// The connection is about to be closed.
+    Disconnected(DisconnectReason),
+    /// The server is shutting down, so the connection is about to be closed.
+    /// Reconnect after `reconnect_after` milliseconds.
+    ServerShutdown {
+        reconnect_after: u64,
+    },
+    /// Something happened in the room (e.g. a moderator did something).
+    System(SystemEvent),
+}
+
+impl ServerMsg {
+    /// The author of the message that this is about, if it's about one that was just sent (or edited).
+    fn author(&self) -> Option<&user::Id> {
+        match self {
+            ServerMsg::NewMessage(message)
+            | ServerMsg::Mentioned(message)
+            | ServerMsg::DirectMessage(message)
+            | ServerMsg::MessageEdited(message) => Some(&message.author),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub enum DisconnectReason {
+    Kicked,
+    Banned {
+        until: Option<Timestamp>,
+        reason: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub enum SystemEvent {
+    Kicked {
+        presence_id: Snowflake,
+        by: user::Id,
+    },
+    Banned {
+        user_id: user::Id,
+        until: Option<Timestamp>,
+        reason: Option<String>,
+        by: user::Id,
+    },
+    Silenced {
+        user_id: user::Id,
+        until: Option<Timestamp>,
+        by: user::Id,
+    },
+}
+
+/// Why a message was rejected.
+#[derive(Clone, Debug, serde::Serialize)]
+pub enum RejectReason {
+    /// The parent isn't the room, or a message in the room.
+    InvalidParent,
+    /// The content is empty, or only whitespace.
+    Empty,
+    /// The content has more characters than the limit.
+    TooLong { max: usize },
+    /// The content has control characters (other than newlines and tabs).
+    ControlCharacters,
+    /// The message has been deleted, so it can't be edited.
+    Deleted,
+    /// A content filter blocked the message.
+    Filtered,
 }
 
-impl Into<String> for ServerMsg {
-    fn into(self) -> String {
-        serde_json::to_string(&self).unwrap()
+impl From<ServerMsg> for String {
+    fn from(msg: ServerMsg) -> String {
+        serde_json::to_string(&msg).unwrap()
     }
 }
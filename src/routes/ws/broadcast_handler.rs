@@ -1,23 +1,58 @@
-use axum::extract::ws::{self, WebSocket};
+use std::sync::Arc;
+
+use axum::extract::ws::{self, CloseFrame, WebSocket};
 use futures::SinkExt;
 use log::debug;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch};
+
+use crate::{blocks::Blocks, metrics, model::room, shutdown::Shutdown};
 
-use super::{broadcast_msg, Broadcast};
+use super::{broadcast_msg, presence::Presence, Broadcast, ServerMsg};
+
+/// The close code for connections that are closed by a moderator
+/// (the same as a [policy violation](https://www.rfc-editor.org/rfc/rfc6455#section-7.4.1)).
+const CLOSE_POLICY: u16 = 1008;
+/// The close code for connections that are closed because the server is shutting down.
+const CLOSE_GOING_AWAY: u16 = 1001;
 
 pub(super) async fn broadcast_handler(
     mut rx: broadcast::Receiver<Broadcast>,
     id: i64,
+    room_id: room::Id,
+    presence: watch::Receiver<Presence>,
+    blocks: Arc<Blocks>,
+    shutdown: Arc<Shutdown>,
     mut sender: futures::stream::SplitSink<WebSocket, ws::Message>,
 ) {
-    while let Ok(msg) = rx.recv().await {
+    loop {
+        let msg = tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(msg) => msg,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("ws {} lagged behind, and missed {} messages", id, skipped);
+                    metrics::broadcast_lagged();
+                    break;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = shutdown.wait() => {
+                debug!("closing ws {} for shutdown", id);
+                let msg = ServerMsg::ServerShutdown {
+                    reconnect_after: shutdown.reconnect_after.as_millis() as u64,
+                };
+                // The connection is closing either way
+                let _ = sender.send(Into::<String>::into(msg).into()).await;
+                let _ = sender
+                    .send(close(CLOSE_GOING_AWAY, "the server is shutting down"))
+                    .await;
+                break;
+            }
+        };
+
         // Check the target
-        // If it's all or the current id, send it
+        // If it's for this connection, send it
         // Otherwise ignore it
         match msg.target {
-            broadcast_msg::Target::All => {
-                debug!("sending message from broadcast");
-            }
             broadcast_msg::Target::One(target_id) => {
                 if id == target_id {
                     // Yup! A special message just for us!
@@ -27,7 +62,41 @@ pub(super) async fn broadcast_handler(
                     continue;
                 }
             }
+            broadcast_msg::Target::Room(target_id) => {
+                if room_id == target_id {
+                    debug!("sending message to room {}", room_id);
+                } else {
+                    continue;
+                }
+            }
+            broadcast_msg::Target::Presence(target_id) => {
+                if presence.borrow().id == target_id {
+                    debug!("sending message to presence {}", target_id);
+                } else {
+                    continue;
+                }
+            }
+            broadcast_msg::Target::User(target_id) => {
+                if presence.borrow().user_id().as_ref() == Some(&target_id) {
+                    debug!("sending message for user {} to ws {}", target_id, id);
+                } else {
+                    continue;
+                }
+            }
+        }
+        // Don't send the messages of users that we've blocked
+        if let (Some(author), Some(user_id)) = (msg.content.author(), presence.borrow().user_id()) {
+            if blocks.has_blocked(&user_id, author) {
+                debug!(
+                    "not sending message by blocked user {} to ws {}",
+                    author, id
+                );
+                continue;
+            }
         }
+
+        let disconnect = matches!(msg.content, ServerMsg::Disconnected(_));
+
         if sender
             .send(Into::<String>::into(msg.content).into())
             .await
@@ -36,5 +105,21 @@ pub(super) async fn broadcast_handler(
             // client disconnected
             break;
         }
+
+        if disconnect {
+            debug!("closing ws {}", id);
+            // The connection is closing either way
+            let _ = sender
+                .send(close(CLOSE_POLICY, "disconnected by a moderator"))
+                .await;
+            break;
+        }
     }
 }
+
+fn close(code: u16, reason: &'static str) -> ws::Message {
+    ws::Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.into(),
+    }))
+}
@@ -6,6 +6,11 @@ pub struct BroadcastMsg<T> {
 
 #[derive(Clone)]
 pub enum Target {
-    All,
     One(i64),
+    /// Every connection in the room.
+    Room(crate::model::room::Id),
+    /// The connection of a presence.
+    Presence(crate::model::Snowflake),
+    /// Every connection that is authenticated as the user, in any room.
+    User(crate::model::user::Id),
 }
@@ -1,8 +1,25 @@
-use crate::model::{Session, Snowflake};
+use std::net::IpAddr;
+
+use crate::model::{user, Session, Snowflake};
 
 #[derive(Clone, Debug, serde::Serialize)]
 pub struct Presence {
     pub id: Snowflake,
     pub session: Option<Session>,
     pub name: String,
+    #[serde(skip)] // Don't expose other people's addresses
+    pub ip: IpAddr,
+}
+
+impl Presence {
+    /// The id of the user this presence is authenticated as, if any.
+    pub fn user_id(&self) -> Option<user::Id> {
+        self.session.as_ref().map(|session| session.user_id.clone())
+    }
+
+    /// The id that messages sent by this presence are authored by.
+    /// This is the user id if it is authenticated, otherwise the presence id.
+    pub fn author_id(&self) -> user::Id {
+        self.user_id().unwrap_or_else(|| self.id.clone())
+    }
 }
@@ -2,10 +2,13 @@ use std::sync::Arc;
 
 use axum::extract::ws::{self, WebSocket};
 use futures::StreamExt;
-use log::{debug, trace};
-use tokio::sync::broadcast;
+use log::{debug, info, trace};
+use tokio::sync::{broadcast, watch};
 
-use crate::model::AppState;
+use crate::{
+    model::AppState,
+    rate_limit::{Action, ConnectionLimiter},
+};
 
 use super::{
     broadcast_msg::{self, BroadcastMsg},
@@ -25,9 +28,11 @@ pub(super) async fn recv_ws(
     state: Arc<AppState>,
     id: i64,
     tx: broadcast::Sender<Broadcast>,
+    presence_tx: watch::Sender<Presence>,
     room_id: crate::model::room::Id,
 ) {
     let mut dedup_ids = Vec::new();
+    let mut limiter = ConnectionLimiter::new(state.rate_limiter.clone(), presence.ip);
 
     // Check if already authenticated
     if presence.session.is_some() {
@@ -50,7 +55,7 @@ pub(super) async fn recv_ws(
 
     // Send join message
     let msg = BroadcastMsg {
-        target: broadcast_msg::Target::All,
+        target: broadcast_msg::Target::Room(room_id.clone()),
         content: super::ServerMsg::Join(presence.clone()),
     };
 
@@ -69,17 +74,44 @@ pub(super) async fn recv_ws(
             trace!("Client sent pong");
         }
 
-        let msg = match ClientMsg::build(msg.clone()) {
+        let len = frame_len(&msg);
+        let msg = match ClientMsg::build(msg) {
             Ok(msg) => msg,
             Err(err) => {
                 // client sent invalid message, ignore
-                debug!("client sent invalid message: {:?}\nError: {:?}", msg, err);
+                // (without logging it, as it may have a password)
+                debug!("client sent invalid message ({} bytes): {}", len, err);
                 continue;
             }
         };
 
         debug!("received message: {:?}", msg);
 
+        if let Some(action) = rate_limit_action(&msg) {
+            if let Err(retry_after) = limiter.check(action, presence.user_id().as_ref()) {
+                debug!("Client {} was rate limited ({:?})", id, action);
+
+                let msg = BroadcastMsg {
+                    target: broadcast_msg::Target::One(id),
+                    content: super::ServerMsg::RateLimited {
+                        retry_after: retry_after.as_millis() as u64,
+                    },
+                };
+                if tx.send(msg).is_err() {
+                    break;
+                }
+
+                if !limiter.strike() {
+                    info!(
+                        "Disconnecting client {} for being rate limited too often",
+                        id
+                    );
+                    break;
+                }
+                continue;
+            }
+        }
+
         let msg_responses = msg_handler::handle_message(
             msg,
             &mut presence,
@@ -89,6 +121,10 @@ pub(super) async fn recv_ws(
         )
         .await;
 
+        // The presence may have (un)authenticated or changed its name
+        state.presences.set_user(&presence.id, presence.user_id());
+        presence_tx.send_replace(presence.clone());
+
         match msg_responses {
             Some(msg_responses) => {
                 for response in msg_responses {
@@ -108,7 +144,27 @@ pub(super) async fn recv_ws(
                             trace!("broadcasting message");
                             trace!("broadcasting message: {:?}", msg); // Trace because logging the whole message is too verbose
                             let msg = BroadcastMsg {
-                                target: broadcast_msg::Target::All,
+                                target: broadcast_msg::Target::Room(room_id.clone()),
+                                content: msg,
+                            };
+                            if tx.send(msg).is_err() {
+                                break;
+                            }
+                        }
+                        HandlerResult::ToPresence(presence_id, msg) => {
+                            trace!("sending message to presence {}: {:?}", presence_id, msg);
+                            let msg = BroadcastMsg {
+                                target: broadcast_msg::Target::Presence(presence_id),
+                                content: msg,
+                            };
+                            if tx.send(msg).is_err() {
+                                break;
+                            }
+                        }
+                        HandlerResult::ToUser(user_id, msg) => {
+                            trace!("sending message to user {}: {:?}", user_id, msg);
+                            let msg = BroadcastMsg {
+                                target: broadcast_msg::Target::User(user_id),
                                 content: msg,
                             };
                             if tx.send(msg).is_err() {
@@ -122,15 +178,39 @@ pub(super) async fn recv_ws(
         }
     }
 
-    // Send leave message
-    let msg = BroadcastMsg {
-        target: broadcast_msg::Target::All,
-        content: super::ServerMsg::Leave(presence),
-    };
+    debug!("Client {} disconnected", id);
+}
 
-    if let Err(err) = tx.send(msg) {
-        debug!("Failed to send leave message: {}", err);
+/// How long a frame's payload is, in bytes.
+fn frame_len(msg: &ws::Message) -> usize {
+    match msg {
+        ws::Message::Text(text) => text.len(),
+        ws::Message::Binary(data) | ws::Message::Ping(data) | ws::Message::Pong(data) => data.len(),
+        ws::Message::Close(_) => 0,
     }
+}
 
-    debug!("Client {} disconnected", id);
+/// What a message counts as for rate limiting, if anything.
+fn rate_limit_action(msg: &ClientMsg) -> Option<Action> {
+    match msg {
+        ClientMsg::Message(_)
+        | ClientMsg::React { .. }
+        | ClientMsg::Unreact { .. }
+        | ClientMsg::Edit { .. }
+        | ClientMsg::Delete { .. }
+        | ClientMsg::ChangeSettings(_)
+        | ClientMsg::SetRole { .. }
+        | ClientMsg::Report { .. }
+        | ClientMsg::Approve { .. }
+        | ClientMsg::Kick { .. }
+        | ClientMsg::Ban { .. }
+        | ClientMsg::Silence { .. } => Some(Action::Post),
+        ClientMsg::ChangeName(_) => Some(Action::ChangeName),
+        ClientMsg::LoadAllMessages
+        | ClientMsg::LoadMessages { .. }
+        | ClientMsg::LoadChildren { .. }
+        | ClientMsg::Search(_) => Some(Action::LoadHistory),
+        ClientMsg::Authenticate(_) => Some(Action::Authenticate),
+        ClientMsg::Pong => None,
+    }
 }
@@ -40,6 +40,55 @@ pub enum ClientMsg {
         parent: crate::model::message::Id,
     },
     ChangeName(String),
+    React {
+        message_id: crate::model::message::Id,
+        emoji: String,
+    },
+    Unreact {
+        message_id: crate::model::message::Id,
+        emoji: String,
+    },
+    Search(crate::model::search::SearchQuery),
+    Edit {
+        message_id: crate::model::message::Id,
+        content: String,
+    },
+    Delete {
+        message_id: crate::model::message::Id,
+    },
+    ChangeSettings(crate::model::room::Settings),
+    SetRole {
+        user_id: crate::model::user::Id,
+        role: crate::model::Role,
+    },
+    /// Flag a message for the room's moderators.
+    Report {
+        message_id: crate::model::message::Id,
+        reason: String,
+    },
+    /// Approve a message that was held for review.
+    Approve {
+        message_id: crate::model::message::Id,
+    },
+    Kick {
+        presence_id: crate::model::Snowflake,
+    },
+    /// Ban a user (or anonymous presence) from the room.
+    /// `duration` is in seconds; without it the ban is permanent.
+    Ban {
+        user_id: crate::model::user::Id,
+        #[serde(default)]
+        duration: Option<u64>,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    /// Stop a user (or anonymous presence) from posting in the room.
+    /// `duration` is in seconds; without it the silence is permanent.
+    Silence {
+        user_id: crate::model::user::Id,
+        #[serde(default)]
+        duration: Option<u64>,
+    },
 }
 
 impl ClientMsg {
@@ -62,3 +111,19 @@ pub enum BuildError {
     MsgType,
     Serde(serde_json::error::Error),
 }
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::MsgType => write!(f, "message is not text"),
+            // Not serde's message, which can quote the frame (like an unknown variant)
+            BuildError::Serde(err) => write!(
+                f,
+                "failed to deserialize message: {:?} error at line {} column {}",
+                err.classify(),
+                err.line(),
+                err.column()
+            ),
+        }
+    }
+}
@@ -1,19 +1,37 @@
+use std::net::IpAddr;
 use std::sync::Arc;
 use std::vec;
 
 use log::{debug, error, trace};
+use unicode_normalization::UnicodeNormalization;
 
-use crate::model::Snowflake;
-use crate::routes::ws::presence::Presence;
-use crate::{auth, model::Message};
+use crate::model::{
+    filter, mention,
+    moderation::ModAction,
+    reaction,
+    report::{self, Report},
+    role::Permission,
+    room::Visibility,
+    search::SearchQuery,
+    store::StoreResult,
+    user, Limits, Role, Snowflake, Store,
+};
+use crate::routes::{log_action, ws::presence::Presence};
+use crate::{auth, metrics, model::Message};
 
-use super::super::{AppState, ServerMsg, Session};
+use super::super::{AppState, DisconnectReason, RejectReason, ServerMsg, Session};
 use super::msg::{ClientMsg, PartialUser, SendMessage};
 
+mod moderation;
+
 #[derive(Debug)]
 pub(super) enum HandlerResult {
     Reply(ServerMsg),
     Broadcast(ServerMsg),
+    /// Send to one presence, in any room.
+    ToPresence(Snowflake, ServerMsg),
+    /// Send to every connection of a user, in any room.
+    ToUser(user::Id, ServerMsg),
 }
 
 use HandlerResult::*;
@@ -23,20 +41,59 @@ type Response = Vec<HandlerResult>;
 pub(super) async fn handle_message(
     msg: ClientMsg,
     presence: &mut Presence,
-    mut dedup_ids: &mut Vec<Option<String>>,
+    dedup_ids: &mut Vec<Option<String>>,
     state: Arc<AppState>,
     room_id: &crate::model::room::Id,
 ) -> Option<Response> {
     Some(match msg {
-        ClientMsg::Authenticate(user) => authenticate(&state, user, presence).await,
+        ClientMsg::Authenticate(user) => authenticate(&state, user, presence, room_id).await,
         ClientMsg::Pong => return None,
         ClientMsg::Message(send_message) => {
-            message(&state, presence.clone(), &mut dedup_ids, send_message).await
+            message(&state, presence.clone(), dedup_ids, send_message, room_id).await
         }
-        ClientMsg::LoadAllMessages => load_all_messages(&state, room_id).await,
-        ClientMsg::LoadMessages { before, amount } => load_messages(&state, before, amount).await,
-        ClientMsg::LoadChildren { parent } => load_children(state, parent).await,
+        ClientMsg::LoadAllMessages => load_all_messages(&state, presence, room_id).await,
+        ClientMsg::LoadMessages { before, amount } => {
+            load_messages(&state, presence, room_id, before, amount).await
+        }
+        ClientMsg::LoadChildren { parent } => load_children(state, presence, room_id, parent).await,
         ClientMsg::ChangeName(name) => change_name(&state, presence, name).await,
+        ClientMsg::React { message_id, emoji } => {
+            react(&state, presence, room_id, message_id, emoji).await
+        }
+        ClientMsg::Unreact { message_id, emoji } => {
+            unreact(&state, presence, room_id, message_id, emoji).await
+        }
+        ClientMsg::Search(query) => search(&state, presence, room_id, query).await,
+        ClientMsg::Edit {
+            message_id,
+            content,
+        } => moderation::edit(&state, presence, room_id, message_id, content).await,
+        ClientMsg::Delete { message_id } => {
+            moderation::delete(&state, presence, room_id, message_id).await
+        }
+        ClientMsg::ChangeSettings(settings) => {
+            moderation::change_settings(&state, presence, room_id, settings).await
+        }
+        ClientMsg::SetRole { user_id, role } => {
+            moderation::set_role(&state, presence, room_id, user_id, role).await
+        }
+        ClientMsg::Report { message_id, reason } => {
+            report(&state, presence, room_id, message_id, reason).await
+        }
+        ClientMsg::Approve { message_id } => {
+            moderation::approve(&state, presence, room_id, message_id).await
+        }
+        ClientMsg::Kick { presence_id } => {
+            moderation::kick(&state, presence, room_id, presence_id).await
+        }
+        ClientMsg::Ban {
+            user_id,
+            duration,
+            reason,
+        } => moderation::ban(&state, presence, room_id, user_id, duration, reason).await,
+        ClientMsg::Silence { user_id, duration } => {
+            moderation::silence(&state, presence, room_id, user_id, duration).await
+        }
     })
 }
 
@@ -44,13 +101,14 @@ async fn authenticate(
     state: &Arc<AppState>,
     user: PartialUser,
     presence: &mut Presence,
+    room_id: &crate::model::room::Id,
 ) -> Response {
     trace!("Authenticating user from credentials");
 
     let presence_id = presence.id.to_string();
 
-    let database = state.database.lock().await;
-    let user_db = match database.get_user_by_name(&user.name) {
+    let database = state.database.as_ref();
+    let user_db = match database.get_user_by_name(&user.name).await {
         Ok(Some(user)) => user,
         Ok(None) => {
             // User doesn't exist
@@ -66,24 +124,71 @@ async fn authenticate(
         }
     };
 
-    if !auth::hash::check_passwords(user.password, user_db.password) {
-        // Password incorrect
-        return vec![Reply(ServerMsg::Authenticate {
-            success: false,
-            presence_id,
-        })];
+    match auth::hash::check_passwords(user.password, user_db.password) {
+        Ok(true) => {}
+        Ok(false) => {
+            // Password incorrect
+            return vec![Reply(ServerMsg::Authenticate {
+                success: false,
+                presence_id,
+            })];
+        }
+        Err(err) => {
+            error!("Invalid password hash for user {}: {}", user_db.id, err);
+            return vec![Reply(ServerMsg::Error)];
+        }
+    }
+
+    // The connection may have joined anonymously, so the user has to be let in too
+    match refusal(database, room_id, &user_db.id, presence.ip).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            debug!(
+                "Refusing to authenticate client {} as user {} in room {}",
+                presence.id, user_db.id, room_id
+            );
+            return vec![Reply(ServerMsg::Disconnected(reason))];
+        }
+        Err(err) => {
+            error!("Failed to check room access in database: {:?}", err);
+            return vec![Reply(ServerMsg::Error)];
+        }
     }
 
     presence.session = Some(Session::generate(state.next_snowflake(), user_db.id));
     presence.name = user.name;
 
-    return vec![
+    vec![
         Broadcast(ServerMsg::Update(presence.clone())),
         Reply(ServerMsg::Authenticate {
             success: true,
             presence_id,
         }),
-    ];
+    ]
+}
+
+/// Why a user can't be in a room (they're banned, or it's private), if they can't.
+async fn refusal(
+    database: &dyn Store,
+    room_id: &crate::model::room::Id,
+    user_id: &user::Id,
+    ip: IpAddr,
+) -> StoreResult<Option<DisconnectReason>> {
+    if let Some(ban) = database.get_active_ban(room_id, Some(user_id), ip).await? {
+        return Ok(Some(DisconnectReason::Banned {
+            until: ban.until,
+            reason: ban.reason,
+        }));
+    }
+
+    let access = match database.get_room(room_id).await? {
+        Some(room) => database.can_access_room(&room, Some(user_id)).await?,
+        None => false,
+    };
+    Ok((!access).then_some(DisconnectReason::Banned {
+        until: None,
+        reason: None,
+    }))
 }
 
 async fn message(
@@ -91,48 +196,436 @@ async fn message(
     presence: Presence,
     dedup_ids: &mut Vec<Option<String>>,
     message: SendMessage,
+    room_id: &crate::model::room::Id,
 ) -> Response {
     debug!("Received message from client {}", presence.id);
 
     let id = state.next_snowflake();
 
     let dedup_id = message.dedup_id.clone();
-    if dedup_id.is_some() && dedup_ids.contains(&dedup_id) {
+    if let Some(dup_id) = dedup_id.as_ref().filter(|_| dedup_ids.contains(&dedup_id)) {
         // Message is a duplicate
         debug!(
             "Duplicate message detected: {:?} from client {}",
-            dedup_id, presence.id
+            dup_id, presence.id
         );
-        return vec![Reply(ServerMsg::Duplicate(dedup_id.unwrap()))];
+        return vec![Reply(ServerMsg::Duplicate(dup_id.clone()))];
+    }
+
+    let content = match normalize_content(&message.content, &state.limits) {
+        Ok(content) => content,
+        Err(reason) => {
+            debug!(
+                "Rejecting message from client {}: {:?}",
+                presence.id, reason
+            );
+            return vec![Reply(ServerMsg::MessageRejected { dedup_id, reason })];
+        }
+    };
+
+    let database = state.database.as_ref();
+
+    if let Err(response) = require(database, room_id, &presence, Permission::Post).await {
+        return response;
+    }
+    if let Err(response) = check_silenced(database, room_id, &presence).await {
+        return response;
+    }
+
+    match parent_in_room(database, &message.parent, room_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!(
+                "Client {} sent a message with an invalid parent {}",
+                presence.id, message.parent
+            );
+            return vec![Reply(ServerMsg::MessageRejected {
+                dedup_id,
+                reason: RejectReason::InvalidParent,
+            })];
+        }
+        Err(err) => {
+            error!("Failed to get parent message from database: {:?}", err);
+            return vec![Reply(ServerMsg::Error)];
+        }
     }
 
-    let database = state.database.lock().await;
+    let outcome = state.content_filters.check(room_id, &content);
+    for hit in &outcome.hits {
+        let (action, target) = match hit.action {
+            filter::Action::Mask => (ModAction::FilterMasked, id.clone()),
+            filter::Action::Hold => (ModAction::FilterHeld, id.clone()),
+            // The message isn't stored, so record who sent it instead
+            filter::Action::Reject => (ModAction::FilterRejected, presence.author_id()),
+        };
+        log_action(
+            state,
+            database,
+            room_id,
+            None,
+            action,
+            Some(target),
+            Some(hit.reason.clone()),
+        )
+        .await;
+    }
+    if outcome.action() == Some(filter::Action::Reject) {
+        debug!(
+            "Message from client {} was rejected by a filter",
+            presence.id
+        );
+        return vec![Reply(ServerMsg::MessageRejected {
+            dedup_id,
+            reason: RejectReason::Filtered,
+        })];
+    }
 
     let message = Message {
+        created_at: id.created_at().into(),
         id,
-        author: presence.session.map(|s| s.user_id).unwrap_or(presence.id),
+        author: presence.author_id(),
         author_name: presence.name,
         parent: message.parent,
-        content: message.content,
+        room: room_id.clone(),
+        held: outcome.action() == Some(filter::Action::Hold),
+        content: outcome.content,
+        deleted: false,
+        blocked: false,
+        reactions: Vec::new(),
     };
 
-    match database.add_message(&message) {
+    let result = database.add_message(&message).await;
+    if result.is_ok() {
+        metrics::message_posted();
+    }
+
+    match result {
+        Ok(()) if message.held => {
+            dedup_ids.push(dedup_id.clone());
+
+            let mut response = vec![Reply(ServerMsg::MessageHeld {
+                dedup_id,
+                message_id: message.id.clone(),
+            })];
+            response.extend(
+                to_moderators(state, database, room_id, ServerMsg::HeldForReview(message)).await,
+            );
+            response
+        }
         Ok(()) => {
             dedup_ids.push(dedup_id);
-            vec![Broadcast(ServerMsg::NewMessage(message))]
+            new_message(state, database, message).await
         }
         Err(err) => {
             error!("Failed to add message to database: {:?}", err);
-            return vec![Reply(ServerMsg::Error)];
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
+/// Announce a message that was added (or approved), and notify the users it mentions.
+async fn new_message(state: &AppState, database: &dyn Store, message: Message) -> Response {
+    let names = mention::parse_names(&message.content);
+    let mentioned = add_mentions(state, database, &message, &names)
+        .await
+        .unwrap_or_else(|err| {
+            error!("Failed to add mentions to database: {:?}", err);
+            Vec::new()
+        });
+
+    let mut response = vec![Broadcast(ServerMsg::NewMessage(message.clone()))];
+    response.extend(
+        mentioned
+            .into_iter()
+            .map(|user_id| ToUser(user_id, ServerMsg::Mentioned(message.clone()))),
+    );
+    response.extend(
+        direct_recipients(database, &message)
+            .await
+            .into_iter()
+            .map(|user_id| ToUser(user_id, ServerMsg::DirectMessage(message.clone()))),
+    );
+    response
+}
+
+/// The users that a message in a direct conversation is sent to (everyone but its author).
+/// Empty for messages in other rooms.
+async fn direct_recipients(database: &dyn Store, message: &Message) -> Vec<user::Id> {
+    match database.get_room(&message.room).await {
+        Ok(Some(room)) if room.visibility == Visibility::Direct => {}
+        Ok(_) => return Vec::new(),
+        Err(err) => {
+            error!("Failed to get room from database: {:?}", err);
+            return Vec::new();
+        }
+    }
+
+    match database.get_members(&message.room).await {
+        Ok(members) => members
+            .into_iter()
+            .filter(|user_id| user_id.id() != message.author.id())
+            .collect(),
+        Err(err) => {
+            error!("Failed to get members of room from database: {:?}", err);
+            Vec::new()
         }
     }
 }
 
-async fn load_all_messages(state: &Arc<AppState>, room_id: &crate::model::room::Id) -> Response {
+/// Send a message to the moderators that are in the room.
+async fn to_moderators(
+    state: &AppState,
+    database: &dyn Store,
+    room_id: &crate::model::room::Id,
+    msg: ServerMsg,
+) -> Response {
+    let mut response = Vec::new();
+
+    for online in state.presences.in_room(room_id) {
+        let Some(user_id) = &online.user_id else {
+            continue;
+        };
+        match database.get_role(room_id, Some(user_id)).await {
+            Ok(role) if role.can(Permission::HandleReports) => {
+                response.push(ToPresence(online.id, msg.clone()))
+            }
+            Ok(_) => {}
+            Err(err) => error!("Failed to get role from database: {:?}", err),
+        }
+    }
+
+    response
+}
+
+/// Normalize a message's content (to NFC, with `\n` line endings), and check it is allowed.
+fn normalize_content(content: &str, limits: &Limits) -> Result<String, RejectReason> {
+    let content: String = content.replace("\r\n", "\n").nfc().collect();
+
+    if content.trim().is_empty() {
+        return Err(RejectReason::Empty);
+    }
+    if content.chars().count() > limits.max_message_chars {
+        return Err(RejectReason::TooLong {
+            max: limits.max_message_chars,
+        });
+    }
+    if content
+        .chars()
+        .any(|c| c.is_control() && c != '\n' && c != '\t')
+    {
+        return Err(RejectReason::ControlCharacters);
+    }
+
+    Ok(content)
+}
+
+/// Check that a presence has a permission in a room, returning its role.
+///
+/// If it doesn't (or the check fails), returns the response to send instead.
+async fn require(
+    database: &dyn Store,
+    room_id: &crate::model::room::Id,
+    presence: &Presence,
+    permission: Permission,
+) -> Result<Role, Response> {
+    match database
+        .get_role(room_id, presence.user_id().as_ref())
+        .await
+    {
+        Ok(role) if role.can(permission) => Ok(role),
+        Ok(role) => {
+            debug!(
+                "Client {} ({:?}) doesn't have permission to {:?}",
+                presence.id, role, permission
+            );
+            Err(vec![Reply(ServerMsg::PermissionDenied(permission))])
+        }
+        Err(err) => {
+            error!("Failed to get role from database: {:?}", err);
+            Err(vec![Reply(ServerMsg::Error)])
+        }
+    }
+}
+
+/// Check that a presence hasn't been silenced in a room.
+///
+/// If it has (or the check fails), returns the response to send instead.
+async fn check_silenced(
+    database: &dyn Store,
+    room_id: &crate::model::room::Id,
+    presence: &Presence,
+) -> Result<(), Response> {
+    match database
+        .get_active_silence(room_id, &presence.author_id())
+        .await
+    {
+        Ok(None) => Ok(()),
+        Ok(Some(silence)) => {
+            debug!("Client {} is silenced in room {}", presence.id, room_id);
+            Err(vec![Reply(ServerMsg::Silenced {
+                until: silence.until,
+            })])
+        }
+        Err(err) => {
+            error!("Failed to get silence from database: {:?}", err);
+            Err(vec![Reply(ServerMsg::Error)])
+        }
+    }
+}
+
+/// Get a message, making sure that it is in the room.
+///
+/// Messages that are held for review are treated as if they don't exist.
+/// If it isn't in the room (or getting it fails), returns the response to send instead.
+async fn get_message_in_room(
+    database: &dyn Store,
+    id: &crate::model::message::Id,
+    room_id: &crate::model::room::Id,
+) -> Result<Message, Response> {
+    match get_message_for_review(database, id, room_id).await? {
+        message if message.held => {
+            debug!("Message {} is held for review", id);
+            Err(vec![Reply(ServerMsg::Error)])
+        }
+        message => Ok(message),
+    }
+}
+
+/// Get a message, making sure that it is in the room, even if it's held for review.
+///
+/// Only for moderators, who approve (or delete) held messages.
+async fn get_message_for_review(
+    database: &dyn Store,
+    id: &crate::model::message::Id,
+    room_id: &crate::model::room::Id,
+) -> Result<Message, Response> {
+    match database.get_message(id).await {
+        Ok(Some(message)) if &message.room == room_id => Ok(message),
+        Ok(_) => {
+            debug!("Message {} isn't in room {}", id, room_id);
+            Err(vec![Reply(ServerMsg::Error)])
+        }
+        Err(err) => {
+            error!("Failed to get message from database: {:?}", err);
+            Err(vec![Reply(ServerMsg::Error)])
+        }
+    }
+}
+
+/// Check that a parent is either the room itself (for a top level message),
+/// or a message in the room (that isn't held for review).
+async fn parent_in_room(
+    database: &dyn Store,
+    parent: &crate::model::message::Id,
+    room_id: &crate::model::room::Id,
+) -> StoreResult<bool> {
+    if parent == room_id {
+        return Ok(true);
+    }
+
+    Ok(database
+        .get_message(parent)
+        .await?
+        .is_some_and(|parent| &parent.room == room_id && !parent.held))
+}
+
+/// Announce a message that was edited, and notify the users that it newly mentions.
+async fn edited_message(
+    state: &AppState,
+    database: &dyn Store,
+    message: Message,
+    old_content: &str,
+) -> Response {
+    let mentioned = update_mentions(state, database, &message, old_content)
+        .await
+        .unwrap_or_else(|err| {
+            error!("Failed to update mentions in database: {:?}", err);
+            Vec::new()
+        });
+
+    let mut response = vec![Broadcast(ServerMsg::MessageEdited(message.clone()))];
+    response.extend(
+        mentioned
+            .into_iter()
+            .map(|user_id| ToUser(user_id, ServerMsg::Mentioned(message.clone()))),
+    );
+    response
+}
+
+/// Update the mentions of an edited message. Users that aren't mentioned any more lose the mention.
+///
+/// Returns the ids of the users that the edit newly mentions
+/// (the ones that were already mentioned were notified then).
+async fn update_mentions(
+    state: &AppState,
+    database: &dyn Store,
+    message: &Message,
+    old_content: &str,
+) -> StoreResult<Vec<user::Id>> {
+    let old_names = mention::parse_names(old_content);
+    let names = mention::parse_names(&message.content);
+
+    for name in old_names.iter().filter(|name| !names.contains(name)) {
+        if let Some(user) = database.get_user_by_name(name).await? {
+            database.remove_mention(&message.id, &user.id).await?;
+        }
+    }
+
+    let added = names
+        .into_iter()
+        .filter(|name| !old_names.contains(name))
+        .collect::<Vec<_>>();
+    add_mentions(state, database, message, &added).await
+}
+
+/// Store the mentions of registered users (by the given names) in a message.
+///
+/// Returns the ids of the users that were mentioned (excluding the author,
+/// and users that can't see the room).
+async fn add_mentions(
+    state: &AppState,
+    database: &dyn Store,
+    message: &Message,
+    names: &[&str],
+) -> StoreResult<Vec<user::Id>> {
+    let mut mentioned = Vec::new();
+    let mut room = None;
+
+    for &name in names {
+        let Some(user) = database.get_user_by_name(name).await? else {
+            continue;
+        };
+        if user.id == message.author || state.blocks.has_blocked(&user.id, &message.author) {
+            continue;
+        }
+
+        // Users that can't see the room (because it's private) aren't notified
+        if room.is_none() {
+            room = database.get_room(&message.room).await?;
+        }
+        let Some(room) = &room else {
+            continue;
+        };
+        if !database.can_access_room(room, Some(&user.id)).await? {
+            continue;
+        }
+
+        database.add_mention(message, &user.id).await?;
+        mentioned.push(user.id);
+    }
+
+    Ok(mentioned)
+}
+
+async fn load_all_messages(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &crate::model::room::Id,
+) -> Response {
     trace!("Loading all messages");
-    let database = state.database.lock().await;
-    match database.get_children_of(Some(room_id)) {
-        Ok(messages) => vec![Reply(ServerMsg::Messages(messages))],
+    let database = state.database.as_ref();
+    match database.get_room_messages(room_id).await {
+        Ok(messages) => history(state, presence, messages),
         Err(err) => {
             error!("Failed to get messages from database: {:?}", err);
             vec![Reply(ServerMsg::Error)]
@@ -140,10 +633,16 @@ async fn load_all_messages(state: &Arc<AppState>, room_id: &crate::model::room::
     }
 }
 
-async fn load_messages(state: &Arc<AppState>, before: Option<Snowflake>, amount: u8) -> Response {
-    let database = state.database.lock().await;
-    match database.get_some_messages(before, amount) {
-        Ok(messages) => vec![Reply(ServerMsg::Messages(messages))],
+async fn load_messages(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &crate::model::room::Id,
+    before: Option<Snowflake>,
+    amount: u8,
+) -> Response {
+    let database = state.database.as_ref();
+    match database.get_some_messages(room_id, before, amount).await {
+        Ok(messages) => history(state, presence, messages),
         Err(err) => {
             error!("Failed to get messages from database: {:?}", err);
             vec![Reply(ServerMsg::Error)]
@@ -151,10 +650,29 @@ async fn load_messages(state: &Arc<AppState>, before: Option<Snowflake>, amount:
     }
 }
 
-async fn load_children(state: Arc<AppState>, parent: Snowflake) -> Response {
-    let database = state.database.lock().await;
-    match database.get_children_of(Some(&parent)) {
-        Ok(messages) => vec![Reply(ServerMsg::Messages(messages))],
+/// Load the replies to a message. Only messages in the connection's room are loaded,
+/// so other rooms (like private ones) can't be read by asking for their messages' replies.
+async fn load_children(
+    state: Arc<AppState>,
+    presence: &Presence,
+    room_id: &crate::model::room::Id,
+    parent: Snowflake,
+) -> Response {
+    let database = state.database.as_ref();
+    match parent_in_room(database, &parent, room_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!("Message {} isn't in room {}", parent, room_id);
+            return vec![Reply(ServerMsg::Error)];
+        }
+        Err(err) => {
+            error!("Failed to get message from database: {:?}", err);
+            return vec![Reply(ServerMsg::Error)];
+        }
+    }
+
+    match database.get_children_of(room_id, Some(&parent)).await {
+        Ok(messages) => history(&state, presence, messages),
         Err(err) => {
             error!("Failed to get messages from database: {:?}", err);
             vec![Reply(ServerMsg::Error)]
@@ -162,8 +680,299 @@ async fn load_children(state: Arc<AppState>, parent: Snowflake) -> Response {
     }
 }
 
+/// Reply with messages from the history, collapsing the ones by users that the presence blocked.
+fn history(state: &AppState, presence: &Presence, mut messages: Vec<Message>) -> Response {
+    state
+        .blocks
+        .collapse(presence.user_id().as_ref(), &mut messages);
+    vec![Reply(ServerMsg::Messages(messages))]
+}
+
+async fn search(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &crate::model::room::Id,
+    query: SearchQuery,
+) -> Response {
+    let database = state.database.as_ref();
+    match database.search_messages(room_id, &query).await {
+        Ok(mut results) => {
+            state
+                .blocks
+                .filter_results(presence.user_id().as_ref(), &mut results);
+            vec![Reply(ServerMsg::SearchResults(results))]
+        }
+        Err(err) => {
+            error!("Failed to search messages: {:?}", err);
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
 async fn change_name(_state: &AppState, presence: &mut Presence, name: String) -> Response {
     presence.name = name;
 
     vec![Reply(ServerMsg::Update(presence.clone()))]
 }
+
+async fn react(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &crate::model::room::Id,
+    message_id: crate::model::message::Id,
+    emoji: String,
+) -> Response {
+    if !reaction::is_emoji(&emoji) {
+        debug!("Invalid reaction from client {}: {:?}", presence.id, emoji);
+        return vec![Reply(ServerMsg::Error)];
+    }
+
+    let database = state.database.as_ref();
+
+    // Check that the presence is allowed to react
+    if presence.session.is_none() {
+        match database.get_room(room_id).await {
+            Ok(Some(room)) if room.anonymous_reactions => {}
+            Ok(_) => {
+                debug!("Anonymous client {} can't react in this room", presence.id);
+                return vec![Reply(ServerMsg::Error)];
+            }
+            Err(err) => {
+                error!("Failed to get room from database: {:?}", err);
+                return vec![Reply(ServerMsg::Error)];
+            }
+        }
+    }
+    if let Err(response) = check_silenced(database, room_id, presence).await {
+        return response;
+    }
+
+    if let Err(response) = get_message_in_room(database, &message_id, room_id).await {
+        return response;
+    }
+
+    let user_id = presence.author_id();
+    match database.add_reaction(&message_id, &user_id, &emoji).await {
+        Ok(true) => reactions_changed(database, message_id).await,
+        Ok(false) => Vec::new(),
+        Err(err) => {
+            error!("Failed to add reaction to database: {:?}", err);
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
+async fn unreact(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &crate::model::room::Id,
+    message_id: crate::model::message::Id,
+    emoji: String,
+) -> Response {
+    let database = state.database.as_ref();
+
+    if let Err(response) = check_silenced(database, room_id, presence).await {
+        return response;
+    }
+    if let Err(response) = get_message_in_room(database, &message_id, room_id).await {
+        return response;
+    }
+
+    let user_id = presence.author_id();
+    match database
+        .remove_reaction(&message_id, &user_id, &emoji)
+        .await
+    {
+        Ok(true) => reactions_changed(database, message_id).await,
+        Ok(false) => Vec::new(),
+        Err(err) => {
+            error!("Failed to remove reaction from database: {:?}", err);
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
+async fn reactions_changed(
+    database: &dyn Store,
+    message_id: crate::model::message::Id,
+) -> Response {
+    match database.get_reactions(&message_id).await {
+        Ok(reactions) => vec![Broadcast(ServerMsg::ReactionsChanged(
+            reaction::ReactionsChanged {
+                message_id,
+                reactions,
+            },
+        ))],
+        Err(err) => {
+            error!("Failed to get reactions from database: {:?}", err);
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
+async fn report(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &crate::model::room::Id,
+    message_id: crate::model::message::Id,
+    reason: String,
+) -> Response {
+    let reason = reason.trim().to_string();
+    if reason.chars().count() > report::MAX_REASON_CHARS {
+        debug!("Report reason from client {} is too long", presence.id);
+        return vec![Reply(ServerMsg::Error)];
+    }
+
+    let database = state.database.as_ref();
+
+    if let Err(response) = require(database, room_id, presence, Permission::Post).await {
+        return response;
+    }
+    let message = match get_message_in_room(database, &message_id, room_id).await {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+
+    let id = state.next_snowflake();
+    let report = Report {
+        created_at: id.created_at().into(),
+        id,
+        room_id: room_id.clone(),
+        message_id: message.id,
+        reporter: presence.author_id(),
+        reason,
+        status: report::Status::Open,
+        moderator: None,
+    };
+
+    if let Err(err) = database.add_report(&report).await {
+        error!("Failed to add report to database: {:?}", err);
+        return vec![Reply(ServerMsg::Error)];
+    }
+
+    let mut response = vec![Reply(ServerMsg::Reported(report.id.clone()))];
+    response.extend(to_moderators(state, database, room_id, ServerMsg::ReportFiled(report)).await);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        model::{moderation::Ban, room, store::Backend, Room, User},
+    };
+
+    /// A server with a user, `alice`, and an anonymous presence that joined its main room.
+    async fn setup() -> (Arc<AppState>, User, Presence) {
+        let config = Config {
+            store: Backend::Memory,
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(&config).await);
+        let user = User {
+            id: state.next_snowflake(),
+            name: "alice".to_string(),
+            password: auth::hash::hash_password("password".to_string()),
+        };
+        state.database.add_user(user.clone()).await.unwrap();
+        let presence = Presence {
+            id: state.next_snowflake(),
+            session: None,
+            name: "Anonymous".to_string(),
+            ip: [127, 0, 0, 1].into(),
+        };
+        (state, user, presence)
+    }
+
+    fn credentials() -> PartialUser {
+        PartialUser {
+            name: "alice".to_string(),
+            password: "password".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn authenticates_users() {
+        let (state, user, mut presence) = setup().await;
+        let response = authenticate(&state, credentials(), &mut presence, &room::main_id()).await;
+        assert!(matches!(
+            response.last(),
+            Some(Reply(ServerMsg::Authenticate { success: true, .. }))
+        ));
+        assert_eq!(presence.user_id(), Some(user.id));
+    }
+
+    #[tokio::test]
+    async fn banned_users_cant_authenticate() {
+        let (state, user, mut presence) = setup().await;
+        let ban = Ban {
+            id: state.next_snowflake(),
+            room_id: room::main_id(),
+            user_id: user.id.clone(),
+            ip: None,
+            until: None,
+            reason: Some("spam".to_string()),
+            actor: user.id,
+        };
+        state.database.add_ban(&ban).await.unwrap();
+
+        let response = authenticate(&state, credentials(), &mut presence, &room::main_id()).await;
+        assert!(matches!(
+            response.as_slice(),
+            [Reply(ServerMsg::Disconnected(
+                DisconnectReason::Banned { .. }
+            ))]
+        ));
+        assert!(presence.session.is_none());
+    }
+
+    #[tokio::test]
+    async fn users_cant_authenticate_in_private_rooms_they_arent_in() {
+        let (state, _, mut presence) = setup().await;
+        let room = Room {
+            id: state.next_snowflake(),
+            name: "secret".to_string(),
+            anonymous_reactions: false,
+            visibility: Visibility::Private,
+        };
+        state.database.add_room(&room).await.unwrap();
+
+        let response = authenticate(&state, credentials(), &mut presence, &room.id).await;
+        assert!(matches!(
+            response.as_slice(),
+            [Reply(ServerMsg::Disconnected(_))]
+        ));
+        assert!(presence.session.is_none());
+    }
+
+    #[tokio::test]
+    async fn held_messages_are_only_found_for_review() {
+        let (state, user, _) = setup().await;
+        let id = state.next_snowflake();
+        let message = Message {
+            id: id.clone(),
+            author: user.id,
+            author_name: user.name,
+            parent: room::main_id(),
+            room: room::main_id(),
+            content: "held".to_string(),
+            deleted: false,
+            held: true,
+            blocked: false,
+            created_at: id.created_at().into(),
+            reactions: Vec::new(),
+        };
+        let database = state.database.as_ref();
+        database.add_message(&message).await.unwrap();
+
+        let main = room::main_id();
+        assert!(get_message_in_room(database, &id, &main).await.is_err());
+        assert!(!parent_in_room(database, &id, &main).await.unwrap());
+        assert!(get_message_for_review(database, &id, &main).await.is_ok());
+
+        database.approve_message(&id).await.unwrap();
+        assert!(get_message_in_room(database, &id, &main).await.is_ok());
+        assert!(parent_in_room(database, &id, &main).await.unwrap());
+    }
+}
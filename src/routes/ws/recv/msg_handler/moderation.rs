@@ -0,0 +1,564 @@
+use std::{sync::Arc, time::Duration};
+
+use log::{debug, error};
+
+use crate::model::{
+    filter, message,
+    moderation::{Ban, ModAction, Silence},
+    role::Permission,
+    room::{self, Settings, Visibility},
+    user, AppState, Role, Snowflake, Store, Timestamp,
+};
+use crate::routes::ws::presence::Presence;
+
+use super::super::super::{DisconnectReason, RejectReason, ServerMsg, SystemEvent};
+use super::{
+    check_silenced, edited_message, get_message_for_review, get_message_in_room, log_action,
+    new_message, normalize_content, require, HandlerResult::*, Response,
+};
+
+pub(super) async fn edit(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &room::Id,
+    message_id: message::Id,
+    content: String,
+) -> Response {
+    let content = match normalize_content(&content, &state.limits) {
+        Ok(content) => content,
+        Err(reason) => {
+            return vec![Reply(ServerMsg::MessageRejected {
+                dedup_id: None,
+                reason,
+            })]
+        }
+    };
+
+    let database = state.database.as_ref();
+
+    let mut message = match get_message_in_room(database, &message_id, room_id).await {
+        Ok(message) if message.deleted => {
+            return vec![Reply(ServerMsg::MessageRejected {
+                dedup_id: None,
+                reason: RejectReason::Deleted,
+            })]
+        }
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+
+    let permission = if message.author == presence.author_id() {
+        Permission::EditOwn
+    } else {
+        Permission::EditOthers
+    };
+    if let Err(response) = require(database, room_id, presence, permission).await {
+        return response;
+    }
+    if let Err(response) = check_silenced(database, room_id, presence).await {
+        return response;
+    }
+
+    let outcome = state.content_filters.check(room_id, &content);
+    for hit in &outcome.hits {
+        let (action, target) = match hit.action {
+            filter::Action::Mask => (ModAction::FilterMasked, message.id.clone()),
+            // The edit isn't stored, so record who made it instead
+            filter::Action::Hold | filter::Action::Reject => {
+                (ModAction::FilterRejected, presence.author_id())
+            }
+        };
+        log_action(
+            state,
+            database,
+            room_id,
+            None,
+            action,
+            Some(target),
+            Some(hit.reason.clone()),
+        )
+        .await;
+    }
+    // The message has already been sent, so it can't be held for review any more.
+    // Edits that would be held are rejected instead.
+    if matches!(
+        outcome.action(),
+        Some(filter::Action::Hold | filter::Action::Reject)
+    ) {
+        debug!("Edit from client {} was rejected by a filter", presence.id);
+        return vec![Reply(ServerMsg::MessageRejected {
+            dedup_id: None,
+            reason: RejectReason::Filtered,
+        })];
+    }
+
+    match database
+        .update_message_content(&message.id, &outcome.content)
+        .await
+    {
+        Ok(()) => {
+            let old_content = std::mem::replace(&mut message.content, outcome.content);
+            edited_message(state, database, message, &old_content).await
+        }
+        Err(err) => {
+            error!("Failed to update message in database: {:?}", err);
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
+pub(super) async fn delete(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &room::Id,
+    message_id: message::Id,
+) -> Response {
+    let database = state.database.as_ref();
+
+    // Moderators can delete held messages instead of approving them
+    let reviewer = matches!(
+        database.get_role(room_id, presence.user_id().as_ref()).await,
+        Ok(role) if role.can(Permission::HandleReports)
+    );
+    let message = if reviewer {
+        get_message_for_review(database, &message_id, room_id).await
+    } else {
+        get_message_in_room(database, &message_id, room_id).await
+    };
+    let message = match message {
+        Ok(message) => message,
+        Err(response) => return response,
+    };
+
+    let permission = if message.author == presence.author_id() {
+        Permission::DeleteOwn
+    } else {
+        Permission::DeleteOthers
+    };
+    if let Err(response) = require(database, room_id, presence, permission).await {
+        return response;
+    }
+
+    match database.delete_message(&message.id).await {
+        Ok(()) => {
+            if permission == Permission::DeleteOthers {
+                log_action(
+                    state,
+                    database,
+                    room_id,
+                    Some(presence.author_id()),
+                    ModAction::DeleteMessage,
+                    Some(message.id.clone()),
+                    None,
+                )
+                .await;
+            }
+            vec![Broadcast(ServerMsg::MessageDeleted(message.id))]
+        }
+        Err(err) => {
+            error!("Failed to delete message from database: {:?}", err);
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
+pub(super) async fn change_settings(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &room::Id,
+    settings: Settings,
+) -> Response {
+    let database = state.database.as_ref();
+
+    if let Err(response) = require(database, room_id, presence, Permission::ChangeSettings).await {
+        return response;
+    }
+    // Only direct conversations are direct, and they stay that way
+    if settings.visibility == Some(Visibility::Direct) {
+        return vec![Reply(ServerMsg::Error)];
+    }
+
+    if let Err(err) = database.update_room_settings(room_id, &settings).await {
+        error!("Failed to update room settings in database: {:?}", err);
+        return vec![Reply(ServerMsg::Error)];
+    }
+    log_action(
+        state,
+        database,
+        room_id,
+        Some(presence.author_id()),
+        ModAction::ChangeSettings,
+        None,
+        None,
+    )
+    .await;
+
+    match database.get_room(room_id).await {
+        Ok(Some(room)) => vec![Broadcast(ServerMsg::RoomUpdated(room))],
+        Ok(None) => vec![Reply(ServerMsg::Error)],
+        Err(err) => {
+            error!("Failed to get room from database: {:?}", err);
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
+pub(super) async fn set_role(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &room::Id,
+    user_id: user::Id,
+    role: Role,
+) -> Response {
+    let database = state.database.as_ref();
+
+    if let Err(response) = require(database, room_id, presence, Permission::ChangeRoles).await {
+        return response;
+    }
+
+    match database.get_user(&user_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            debug!("Can't set the role of missing user {}", user_id);
+            return vec![Reply(ServerMsg::Error)];
+        }
+        Err(err) => {
+            error!("Failed to get user from database: {:?}", err);
+            return vec![Reply(ServerMsg::Error)];
+        }
+    }
+
+    if let Err(response) = check_owner_change(database, presence, room_id, &user_id, role).await {
+        return response;
+    }
+
+    match database.set_role(room_id, &user_id, role).await {
+        Ok(()) => {
+            log_action(
+                state,
+                database,
+                room_id,
+                Some(presence.author_id()),
+                ModAction::ChangeRole,
+                Some(user_id.clone()),
+                None,
+            )
+            .await;
+            vec![Broadcast(ServerMsg::RoleChanged { user_id, role })]
+        }
+        Err(err) => {
+            error!("Failed to set role in database: {:?}", err);
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
+/// Owners can only change their own role, and the last owner can't stop being one
+/// (so a room always has someone who can change roles).
+async fn check_owner_change(
+    database: &dyn Store,
+    presence: &Presence,
+    room_id: &room::Id,
+    user_id: &user::Id,
+    role: Role,
+) -> Result<(), Response> {
+    match database.get_role(room_id, Some(user_id)).await {
+        Ok(Role::Owner) => {}
+        Ok(_) => return Ok(()),
+        Err(err) => {
+            error!("Failed to get role from database: {:?}", err);
+            return Err(vec![Reply(ServerMsg::Error)]);
+        }
+    }
+
+    if presence.user_id().as_ref() != Some(user_id) {
+        debug!(
+            "{} can't change the role of {}, another owner of room {}",
+            presence.author_id(),
+            user_id,
+            room_id
+        );
+        return Err(vec![Reply(ServerMsg::PermissionDenied(
+            Permission::ChangeRoles,
+        ))]);
+    }
+
+    if role == Role::Owner {
+        return Ok(());
+    }
+    match database.get_owners(room_id).await {
+        Ok(owners) if owners.len() > 1 => Ok(()),
+        Ok(_) => {
+            debug!(
+                "{} can't stop being the last owner of room {}",
+                user_id, room_id
+            );
+            Err(vec![Reply(ServerMsg::PermissionDenied(
+                Permission::ChangeRoles,
+            ))])
+        }
+        Err(err) => {
+            error!("Failed to get owners from database: {:?}", err);
+            Err(vec![Reply(ServerMsg::Error)])
+        }
+    }
+}
+
+pub(super) async fn kick(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &room::Id,
+    presence_id: Snowflake,
+) -> Response {
+    let database = state.database.as_ref();
+
+    let role = match require(database, room_id, presence, Permission::Kick).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+
+    let Some(target) = state
+        .presences
+        .get(&presence_id)
+        .filter(|online| &online.room_id == room_id)
+    else {
+        debug!("Presence {} isn't in room {}", presence_id, room_id);
+        return vec![Reply(ServerMsg::Error)];
+    };
+
+    if let Err(response) = outranks(
+        database,
+        room_id,
+        role,
+        &target.author_id(),
+        Permission::Kick,
+    )
+    .await
+    {
+        return response;
+    }
+
+    log_action(
+        state,
+        database,
+        room_id,
+        Some(presence.author_id()),
+        ModAction::Kick,
+        Some(target.id.clone()),
+        None,
+    )
+    .await;
+
+    vec![
+        ToPresence(
+            target.id.clone(),
+            ServerMsg::Disconnected(DisconnectReason::Kicked),
+        ),
+        Broadcast(ServerMsg::System(SystemEvent::Kicked {
+            presence_id: target.id,
+            by: presence.author_id(),
+        })),
+    ]
+}
+
+pub(super) async fn ban(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &room::Id,
+    user_id: user::Id,
+    duration: Option<u64>,
+    reason: Option<String>,
+) -> Response {
+    let database = state.database.as_ref();
+
+    let role = match require(database, room_id, presence, Permission::Ban).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+    if let Err(response) = outranks(database, room_id, role, &user_id, Permission::Ban).await {
+        return response;
+    }
+
+    let online = state.presences.in_room(room_id);
+
+    // Anonymous users are banned by address too, since they could just rejoin otherwise
+    let ip = online
+        .iter()
+        .find(|online| online.user_id.is_none() && online.id == user_id)
+        .map(|online| online.ip);
+
+    let ban = Ban {
+        id: state.next_snowflake(),
+        room_id: room_id.clone(),
+        user_id,
+        ip,
+        until: duration.map(|secs| Timestamp::after(Duration::from_secs(secs))),
+        reason: reason.filter(|reason| !reason.trim().is_empty()),
+        actor: presence.author_id(),
+    };
+
+    if let Err(err) = database.add_ban(&ban).await {
+        error!("Failed to add ban to database: {:?}", err);
+        return vec![Reply(ServerMsg::Error)];
+    }
+    log_action(
+        state,
+        database,
+        room_id,
+        Some(presence.author_id()),
+        ModAction::Ban,
+        Some(ban.user_id.clone()),
+        ban.reason.clone(),
+    )
+    .await;
+
+    // Disconnect everyone the ban applies to
+    let mut response: Response = online
+        .into_iter()
+        .filter(|online| {
+            online.author_id() == ban.user_id
+                || (online.user_id.is_none() && Some(online.ip) == ban.ip)
+        })
+        .map(|online| {
+            ToPresence(
+                online.id,
+                ServerMsg::Disconnected(DisconnectReason::Banned {
+                    until: ban.until,
+                    reason: ban.reason.clone(),
+                }),
+            )
+        })
+        .collect();
+
+    response.push(Broadcast(ServerMsg::System(SystemEvent::Banned {
+        user_id: ban.user_id,
+        until: ban.until,
+        reason: ban.reason,
+        by: ban.actor,
+    })));
+    response
+}
+
+pub(super) async fn silence(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &room::Id,
+    user_id: user::Id,
+    duration: Option<u64>,
+) -> Response {
+    let database = state.database.as_ref();
+
+    let role = match require(database, room_id, presence, Permission::Silence).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+    if let Err(response) = outranks(database, room_id, role, &user_id, Permission::Silence).await {
+        return response;
+    }
+
+    let silence = Silence {
+        room_id: room_id.clone(),
+        user_id,
+        until: duration.map(|secs| Timestamp::after(Duration::from_secs(secs))),
+        actor: presence.author_id(),
+    };
+
+    match database.set_silence(&silence).await {
+        Ok(()) => {
+            log_action(
+                state,
+                database,
+                room_id,
+                Some(presence.author_id()),
+                ModAction::Silence,
+                Some(silence.user_id.clone()),
+                None,
+            )
+            .await;
+            vec![Broadcast(ServerMsg::System(SystemEvent::Silenced {
+                user_id: silence.user_id,
+                until: silence.until,
+                by: silence.actor,
+            }))]
+        }
+        Err(err) => {
+            error!("Failed to add silence to database: {:?}", err);
+            vec![Reply(ServerMsg::Error)]
+        }
+    }
+}
+
+/// Check that an actor's role is higher than the role of the user (or anonymous presence)
+/// they're trying to moderate.
+///
+/// If it isn't (or the check fails), returns the response to send instead.
+async fn outranks(
+    database: &dyn Store,
+    room_id: &room::Id,
+    role: Role,
+    user_id: &user::Id,
+    permission: Permission,
+) -> Result<(), Response> {
+    // Ids that aren't registered users are anonymous presences
+    let target_role = match database.get_user(user_id).await {
+        Ok(Some(_)) => database.get_role(room_id, Some(user_id)).await,
+        Ok(None) => Ok(Role::ANONYMOUS),
+        Err(err) => Err(err),
+    };
+
+    match target_role {
+        Ok(target_role) if role > target_role => Ok(()),
+        Ok(target_role) => {
+            debug!(
+                "{:?} can't moderate {} ({:?}) in room {}",
+                role, user_id, target_role, room_id
+            );
+            Err(vec![Reply(ServerMsg::PermissionDenied(permission))])
+        }
+        Err(err) => {
+            error!("Failed to get role from database: {:?}", err);
+            Err(vec![Reply(ServerMsg::Error)])
+        }
+    }
+}
+
+/// Approve a message that was held by a content filter, sending it to the room.
+pub(super) async fn approve(
+    state: &Arc<AppState>,
+    presence: &Presence,
+    room_id: &room::Id,
+    message_id: message::Id,
+) -> Response {
+    let database = state.database.as_ref();
+
+    if let Err(response) = require(database, room_id, presence, Permission::HandleReports).await {
+        return response;
+    }
+
+    let mut message = match get_message_for_review(database, &message_id, room_id).await {
+        Ok(message) if message.held && !message.deleted => message,
+        Ok(_) => {
+            debug!("Message {} isn't held for review", message_id);
+            return vec![Reply(ServerMsg::Error)];
+        }
+        Err(response) => return response,
+    };
+
+    if let Err(err) = database.approve_message(&message.id).await {
+        error!("Failed to approve message in database: {:?}", err);
+        return vec![Reply(ServerMsg::Error)];
+    }
+    log_action(
+        state,
+        database,
+        room_id,
+        Some(presence.author_id()),
+        ModAction::ApproveMessage,
+        Some(message.id.clone()),
+        None,
+    )
+    .await;
+
+    message.held = false;
+    new_message(state, database, message).await
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use log::{error, info};
+use tokio::sync::watch;
+
+/// Shutting down the server: telling the websocket connections to close, and waiting until they have.
+#[derive(Debug)]
+pub struct Shutdown {
+    /// Whether shutting down has started. Each connection holds a receiver, so once there are none
+    /// left, every connection has closed.
+    started: watch::Sender<bool>,
+    /// How long clients are told to wait before reconnecting.
+    pub reconnect_after: Duration,
+}
+
+impl Shutdown {
+    pub fn new(reconnect_after: Duration) -> Shutdown {
+        let (started, _) = watch::channel(false);
+        Shutdown {
+            started,
+            reconnect_after,
+        }
+    }
+
+    /// Start shutting down, which tells every connection to close.
+    pub fn start(&self) {
+        self.started.send_replace(true);
+    }
+
+    pub fn is_started(&self) -> bool {
+        *self.started.borrow()
+    }
+
+    /// Wait until shutting down starts.
+    pub async fn wait(&self) {
+        let mut started = self.started.subscribe();
+        // The sender is in `self`, so it can't be dropped while waiting
+        let _ = started.wait_for(|started| *started).await;
+    }
+
+    /// Register a connection. It should close once the receiver changes to `true`,
+    /// and drop the receiver once it has.
+    pub fn connection(&self) -> watch::Receiver<bool> {
+        self.started.subscribe()
+    }
+
+    /// Wait until every connection has closed.
+    pub async fn connections_closed(&self) {
+        self.started.closed().await;
+    }
+}
+
+/// Wait for a signal to shut down: SIGINT (Ctrl+C), or SIGTERM (e.g. from `docker stop`).
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for SIGINT: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                error!("Failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("Got SIGINT"),
+        _ = terminate => info!("Got SIGTERM"),
+    }
+}
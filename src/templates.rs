@@ -1,14 +1,16 @@
 use axum::{
     extract::{Path, State},
+    headers::Cookie,
     http::StatusCode,
     response::Html,
-    Router,
+    Router, TypedHeader,
 };
 use axum_macros::debug_handler;
+use log::error;
 use tera::{Context, Tera};
 use tower_http::services::ServeDir;
 
-use crate::model::AppState;
+use crate::{config::Config, model::AppState};
 
 #[derive(Clone)]
 struct TemplateState {
@@ -16,8 +18,8 @@ struct TemplateState {
     appstate: AppState,
 }
 
-pub fn router(appstate: AppState) -> Router {
-    let templates = match Tera::new("templates/**/*") {
+pub fn router(appstate: AppState, config: &Config) -> Router {
+    let templates = match Tera::new(&format!("{}/**/*", config.templates.display())) {
         Ok(t) => t,
         Err(e) => {
             panic!("Parsing error(s): {}", e);
@@ -31,33 +33,63 @@ pub fn router(appstate: AppState) -> Router {
 
     Router::new()
         .route("/", axum::routing::get(index))
-        .nest_service("/static", ServeDir::new("public"))
+        .nest_service("/static", ServeDir::new(&config.public))
         .route("/room/:room_name", axum::routing::get(room))
         .with_state(state)
 }
 
 #[debug_handler]
-async fn index(State(state): State<TemplateState>) -> Html<String> {
+async fn index(State(state): State<TemplateState>) -> Result<Html<String>, StatusCode> {
     let context = Context::new();
-    let rendered = state.templates.render("base.html", &context).unwrap();
-    Html(rendered)
+    render(&state, "base.html", &context)
 }
 
 #[debug_handler]
 async fn room(
     State(state): State<TemplateState>,
+    cookies: Option<TypedHeader<Cookie>>,
     Path(room_name): Path<String>,
 ) -> Result<Html<String>, StatusCode> {
     // Get the room from the database
-    let database = state.appstate.database.lock().await;
-    let Some(room) = database.get_room_by_name(&room_name).unwrap() else {
-        return Err(StatusCode::NOT_FOUND);
+    let database = state.appstate.database.as_ref();
+    let room = match database.get_room_by_name(&room_name).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            error!("Failed to get room from database: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
     };
 
+    // Private rooms are only shown to their members
+    let session = crate::routes::auth::optional_session(cookies, database).await?;
+    let user_id = session.as_ref().map(|session| &session.user_id);
+    match database.can_access_room(&room, user_id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            error!("Failed to check access to room: {:?}", err);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
     let mut context = Context::new();
     context.insert("room_id", &room.id);
     context.insert("room_name", &room_name);
 
-    let rendered = state.templates.render("room.html", &context).unwrap();
-    Ok(Html(rendered))
+    render(&state, "room.html", &context)
+}
+
+fn render(
+    state: &TemplateState,
+    template: &str,
+    context: &Context,
+) -> Result<Html<String>, StatusCode> {
+    match state.templates.render(template, context) {
+        Ok(rendered) => Ok(Html(rendered)),
+        Err(err) => {
+            error!("Failed to render template {}: {:?}", template, err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }